@@ -0,0 +1,249 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use atmos::constants::*;
+use atmos::gases::gases;
+use atmos::mix::AtmosMixture;
+use atmos::registry::{Handle, MixtureRegistry};
+
+use error::{Error, Result};
+
+/// How far outside its limits a reading is. The numbers are DM's alarm levels.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Severity {
+    Safe = 0,
+    Warning = 1,
+    Danger = 2,
+}
+
+/// Warning and danger limits for one reading. A missing limit is never crossed.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Tlv {
+    pub danger_min: Option<Float>,
+    pub warning_min: Option<Float>,
+    pub warning_max: Option<Float>,
+    pub danger_max: Option<Float>,
+}
+
+impl Tlv {
+    /// Same comparisons as DM's `/datum/tlv`: touching a limit counts as crossing it.
+    pub fn check(&self, value: Float) -> Severity {
+        let below = |limit: Option<Float>| limit.is_some_and(|limit| value <= limit);
+        let above = |limit: Option<Float>| limit.is_some_and(|limit| value >= limit);
+        if below(self.danger_min) || above(self.danger_max) {
+            Severity::Danger
+        } else if below(self.warning_min) || above(self.warning_max) {
+            Severity::Warning
+        } else {
+            Severity::Safe
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Metric {
+    /// Total pressure, in kPa.
+    Pressure,
+    Temperature,
+    /// Partial pressure of the gas at this index, in kPa.
+    Gas(usize),
+}
+
+/// An air alarm's limits, in the order they were given.
+#[derive(Clone, Default, Debug)]
+pub struct AlarmThresholds {
+    pub limits: Vec<(String, Metric, Tlv)>,
+}
+
+/// The result of checking a mixture against an alarm's limits.
+pub struct AlarmReport {
+    pub level: Severity,
+    pub metrics: Vec<(String, Severity)>,
+}
+
+impl AlarmThresholds {
+    /// Reads `pressure=danger_min,warning_min,warning_max,danger_max;temperature=...;o2=...;`.
+    /// Anything but `pressure` and `temperature` is a gas id, checked against that
+    /// gas's partial pressure. Negative limits are off, like DM's -1. Unknown gas
+    /// ids are skipped, as they are in gas strings.
+    pub fn parse(tlv_string: &str) -> Result<AlarmThresholds> {
+        let gases = gases();
+        let mut limits = Vec::new();
+        for entry in tlv_string.split(';') {
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let mut entry_split = entry.splitn(2, '=');
+            let (name, values) = match (entry_split.next(), entry_split.next()) {
+                (Some(name), Some(values)) => (name.trim(), values),
+                _ => return Err(Error::MalformedEntry(entry.to_string())),
+            };
+            let metric = match name {
+                "pressure" => Metric::Pressure,
+                "temperature" => Metric::Temperature,
+                _ => match gases.index_of(name) {
+                    Some(index) => Metric::Gas(index),
+                    None => continue,
+                },
+            };
+            let mut bounds = Vec::with_capacity(4);
+            for value in values.split(',') {
+                let bound: Float = value.trim().parse().map_err(|_| Error::InvalidNumber(value.to_string()))?;
+                bounds.push(if bound < 0.0 { None } else { Some(bound) });
+            }
+            if bounds.len() != 4 {
+                return Err(Error::MalformedEntry(entry.to_string()));
+            }
+            let tlv = Tlv {
+                danger_min: bounds[0],
+                warning_min: bounds[1],
+                warning_max: bounds[2],
+                danger_max: bounds[3],
+            };
+            limits.push((name.to_string(), metric, tlv));
+        }
+        Ok(AlarmThresholds { limits })
+    }
+    pub fn evaluate(&self, mix: &AtmosMixture) -> AlarmReport {
+        let pressure = mix.pressure();
+        let pressure_per_mole = mix.temperature * GAS_CONSTANT / mix.volume;
+        let mut level = Severity::Safe;
+        let mut metrics = Vec::with_capacity(self.limits.len());
+        for &(ref name, metric, ref tlv) in &self.limits {
+            let value = match metric {
+                Metric::Pressure => pressure,
+                Metric::Temperature => mix.temperature,
+                Metric::Gas(index) => mix.gases[index] * pressure_per_mole,
+            };
+            let severity = tlv.check(value);
+            level = level.max(severity);
+            metrics.push((name.clone(), severity));
+        }
+        AlarmReport { level, metrics }
+    }
+}
+
+impl AlarmReport {
+    /// `level$pressure=0;temperature=1;o2=0;`, with the overall level first so DM
+    /// can read it without parsing the rest.
+    pub fn to_params(&self) -> String {
+        let mut params = format!("{}$", self.level as u8);
+        for &(ref name, severity) in &self.metrics {
+            params.push_str(&format!("{}={};", name, severity as u8));
+        }
+        params
+    }
+}
+
+/// Fails with `InvalidArgument` if `id` has a `:`, `;` or `$` in it, any of which
+/// would break the `id:handle;...` strings DM sends and the `id$...` lines it reads.
+pub fn check_id(id: &str) -> Result<()> {
+    if id.contains([':', ';', '$']) {
+        return Err(Error::InvalidArgument("id".to_string(), id.to_string()));
+    }
+    Ok(())
+}
+
+/// Every registered alarm's limits, keyed by whatever id DM gave it.
+#[derive(Default)]
+pub struct AlarmRegistry {
+    alarms: HashMap<String, AlarmThresholds>,
+}
+
+impl AlarmRegistry {
+    pub fn set(&mut self, id: &str, thresholds: AlarmThresholds) -> Result<()> {
+        check_id(id)?;
+        self.alarms.insert(id.to_string(), thresholds);
+        Ok(())
+    }
+    pub fn remove(&mut self, id: &str) -> Result<AlarmThresholds> {
+        self.alarms.remove(id).ok_or_else(|| Error::UnknownAlarm(id.to_string()))
+    }
+    pub fn get(&self, id: &str) -> Result<&AlarmThresholds> {
+        self.alarms.get(id).ok_or_else(|| Error::UnknownAlarm(id.to_string()))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.alarms.is_empty()
+    }
+    /// Checks every `alarm:handle` pair in `alarm:handle;alarm:handle;...` and returns
+    /// one `alarm$level$metrics` line per alarm. Nothing is checked if any id or
+    /// handle is bad.
+    pub fn evaluate_all(&self, mixtures: &MixtureRegistry, alarm_string: &str) -> Result<String> {
+        let mut pairs = Vec::new();
+        for entry in alarm_string.split(';') {
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let mut entry_split = entry.splitn(2, ':');
+            let (id, handle) = match (entry_split.next(), entry_split.next()) {
+                (Some(id), Some(handle)) => (id.trim(), handle.parse::<Handle>()?),
+                _ => return Err(Error::MalformedEntry(entry.to_string())),
+            };
+            pairs.push((id, self.get(id)?, mixtures.get(handle)?));
+        }
+        Ok(pairs
+            .into_iter()
+            .map(|(id, thresholds, mix)| format!("{}${}", id, thresholds.evaluate(mix).to_params()))
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+}
+
+thread_local! {
+    static ALARMS: RefCell<AlarmRegistry> = RefCell::new(AlarmRegistry::default());
+}
+
+/// Runs `f` against the alarm limits backing the BYOND exports.
+pub fn with_alarms<F, T>(f: F) -> T
+where
+    F: FnOnce(&mut AlarmRegistry) -> T,
+{
+    ALARMS.with(|cell| f(&mut cell.borrow_mut()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atmos::gases::gas_index;
+
+    const STATION_TLV: &str = "pressure=20,40,140,160;temperature=-1,270,330,360;o2=16,19,135,140;plasma=-1,-1,0.05,0.5;nonexistent_gas=1,2,3,4;";
+
+    #[test]
+    fn test_evaluate() {
+        let thresholds = AlarmThresholds::parse(STATION_TLV).unwrap();
+        assert_eq!(thresholds.limits.len(), 4);
+        assert_eq!(thresholds.limits[1].2.danger_min, None);
+        let mut mix = AtmosMixture::from_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap();
+        let report = thresholds.evaluate(&mix);
+        assert_eq!(report.level, Severity::Safe);
+        assert_eq!(report.to_params(), "0$pressure=0;temperature=0;o2=0;plasma=0;");
+        mix.temperature = float(340.0);
+        mix.gases[gas_index("plasma")] = float(1.0);
+        let report = thresholds.evaluate(&mix);
+        assert_eq!(report.level, Severity::Danger);
+        assert_eq!(report.to_params(), "2$pressure=0;temperature=1;o2=0;plasma=2;");
+        let vacuum = AtmosMixture::from_gas_string("TEMP=2.7;").unwrap();
+        assert_eq!(thresholds.evaluate(&vacuum).to_params(), "2$pressure=2;temperature=1;o2=2;plasma=0;");
+        assert!(AlarmThresholds::parse("pressure=1,2,3;").is_err());
+        assert!(AlarmThresholds::parse("pressure=1,2,3,lots;").is_err());
+        assert!(AlarmThresholds::parse("pressure").is_err());
+    }
+    #[test]
+    fn test_evaluate_all() {
+        let mut mixtures = MixtureRegistry::default();
+        let air = mixtures.create(AtmosMixture::from_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap());
+        let vacuum = mixtures.create(Default::default());
+        let mut alarms = AlarmRegistry::default();
+        alarms.set("bridge", AlarmThresholds::parse(STATION_TLV).unwrap()).unwrap();
+        alarms.set("freezer", AlarmThresholds::parse("temperature=-1,-1,250,300;").unwrap()).unwrap();
+        assert!(matches!(alarms.set("bridge:2", AlarmThresholds::parse(STATION_TLV).unwrap()), Err(Error::InvalidArgument(..))));
+        assert!(alarms.set("bridge$", AlarmThresholds::parse(STATION_TLV).unwrap()).is_err());
+        let report = alarms.evaluate_all(&mixtures, &format!("bridge:{};freezer:{};bridge:{};", air, air, vacuum)).unwrap();
+        assert_eq!(report, "bridge$0$pressure=0;temperature=0;o2=0;plasma=0;\nfreezer$1$temperature=1;\nbridge$2$pressure=2;temperature=0;o2=2;plasma=0;");
+        assert!(alarms.evaluate_all(&mixtures, &format!("galley:{};", air)).is_err());
+        assert!(alarms.evaluate_all(&mixtures, "bridge:bogus;").is_err());
+        alarms.remove("freezer").unwrap();
+        assert!(alarms.remove("freezer").is_err());
+        assert!(!alarms.is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt::Write;
+
+use atmos::constants::*;
+use atmos::gases::gases;
+use atmos::mix::AtmosMixture;
+use atmos::reaction::reactions;
+
+pub struct GasReading {
+    pub id: String,
+    pub name: String,
+    pub moles: Float,
+    /// Share of the total moles, out of 100.
+    pub percentage: Float,
+    /// In kPa.
+    pub partial_pressure: Float,
+}
+
+/// What a gas analyzer shows for a mixture. Only gases that are present are listed.
+pub struct ScanReport {
+    pub pressure: Float,
+    pub temperature: Float,
+    pub volume: Float,
+    pub total_moles: Float,
+    pub heat_capacity: Float,
+    pub thermal_energy: Float,
+    pub gases: Vec<GasReading>,
+    /// Ids and names of the reactions whose requirements the mixture meets, in the
+    /// order they'd run. Stops after one that stops reactions, as `react` would.
+    pub reactions: Vec<(String, String)>,
+}
+
+impl ScanReport {
+    pub fn scan(mix: &AtmosMixture) -> ScanReport {
+        let gas_registry = gases();
+        let total_moles = mix.total_moles();
+        let pressure_per_mole = mix.temperature * GAS_CONSTANT / mix.volume;
+        let mut gases = Vec::new();
+        for (index, &moles) in mix.gases.iter().enumerate() {
+            if moles > 0.0 {
+                let gas = gas_registry.get(index);
+                gases.push(GasReading {
+                    id: gas.id.clone(),
+                    name: gas.name.clone(),
+                    moles,
+                    percentage: moles * float(100.0) / total_moles,
+                    partial_pressure: moles * pressure_per_mole,
+                });
+            }
+        }
+        let mut possible_reactions = Vec::new();
+        for reaction in reactions().iter() {
+            if reaction.can_react(mix) {
+                possible_reactions.push((reaction.id.clone(), reaction.name.clone()));
+                if reaction.stops_reactions {
+                    break;
+                }
+            }
+        }
+        ScanReport {
+            pressure: mix.pressure(),
+            temperature: mix.temperature,
+            volume: mix.volume,
+            total_moles,
+            heat_capacity: mix.heat_capacity(),
+            thermal_energy: mix.thermal_energy(),
+            gases,
+            reactions: possible_reactions,
+        }
+    }
+    pub fn temperature_celsius(&self) -> Float {
+        self.temperature - T0C
+    }
+    /// `pressure=...;temperature=...;...$o2=moles,percentage,partial_pressure;...$reaction,reaction`.
+    pub fn to_params(&self) -> String {
+        let mut params = format!("pressure={};temperature={};temperature_celsius={};volume={};total_moles={};heat_capacity={};thermal_energy={};",
+            self.pressure, self.temperature, self.temperature_celsius(), self.volume, self.total_moles, self.heat_capacity, self.thermal_energy);
+        params.push('$');
+        for gas in &self.gases {
+            let _ = write!(params, "{}={},{},{};", gas.id, gas.moles, gas.percentage, gas.partial_pressure);
+        }
+        params.push('$');
+        params.push_str(&self.reactions.iter().map(|(id, _)| id.as_str()).collect::<Vec<&str>>().join(","));
+        params
+    }
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"pressure\":{},\"temperature\":{},\"temperature_celsius\":{},\"volume\":{},\"total_moles\":{},\"heat_capacity\":{},\"thermal_energy\":{},\"gases\":[",
+            json_number(self.pressure), json_number(self.temperature), json_number(self.temperature_celsius()), json_number(self.volume),
+            json_number(self.total_moles), json_number(self.heat_capacity), json_number(self.thermal_energy));
+        for (i, gas) in self.gases.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"id\":{},\"name\":{},\"moles\":{},\"percentage\":{},\"partial_pressure\":{}}}",
+                json_string(&gas.id), json_string(&gas.name), json_number(gas.moles), json_number(gas.percentage), json_number(gas.partial_pressure));
+        }
+        json.push_str("],\"reactions\":[");
+        for (i, (id, name)) in self.reactions.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"id\":{},\"name\":{}}}", json_string(id), json_string(name));
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+// JSON has no infinity or NaN, which an empty or zero-volume mixture can give.
+fn json_number(value: Float) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan() {
+        let mix = AtmosMixture::from_gas_string("TEMP=500;o2=30;plasma=10;").unwrap();
+        let report = ScanReport::scan(&mix);
+        assert!((report.pressure - mix.pressure()).abs() < 0.001);
+        assert!((report.temperature_celsius() - 226.85).abs() < 0.001);
+        assert_eq!(report.gases.len(), 2);
+        assert_eq!(report.gases[0].id, "o2");
+        assert!((report.gases[0].percentage - 75.0).abs() < 0.001);
+        let partial_pressures: Float = report.gases.iter().map(|gas| gas.partial_pressure).sum();
+        assert!((partial_pressures - report.pressure).abs() < 0.01);
+        assert_eq!(report.reactions.iter().map(|(id, _)| id.as_str()).collect::<Vec<&str>>(), vec!["plasfire"]);
+        let params = report.to_params();
+        let sections: Vec<&str> = params.split('$').collect();
+        assert_eq!(sections.len(), 3);
+        assert!(sections[0].starts_with("pressure="));
+        assert!(sections[1].starts_with("o2=30,75,"));
+        assert_eq!(sections[2], "plasfire");
+        let json = report.to_json();
+        assert!(json.starts_with("{\"pressure\":"));
+        assert!(json.contains("\"gases\":[{\"id\":\"o2\",\"name\":"));
+        assert!(json.ends_with("\"reactions\":[{\"id\":\"plasfire\",\"name\":\"Plasma combustion\"}]}"));
+    }
+    #[test]
+    fn test_empty_scan() {
+        let report = ScanReport::scan(&Default::default());
+        assert!(report.gases.is_empty() && report.reactions.is_empty());
+        assert!(report.to_params().starts_with("pressure=0;"));
+        assert!(report.to_params().ends_with("$$"));
+        assert!(report.to_json().ends_with("\"gases\":[],\"reactions\":[]}"));
+        #[cfg(not(feature = "fixed"))]
+        assert_eq!(json_number(Float::INFINITY), "null");
+        assert_eq!(json_string("a \"b\"\\\n"), "\"a \\\"b\\\"\\\\\\n\"");
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::{HashMap, HashSet};
+
+use atmos::constants::Float;
+use atmos::registry::{Handle, MixtureRegistry};
+
+use error::Result;
+
+pub struct TurfAdjacency {
+    pub turf: Handle,
+    pub neighbours: Vec<Handle>,
+}
+
+/// Parses `turf:neighbour,neighbour;turf:neighbour;...` into an adjacency list.
+pub fn parse_adjacency(adjacency_string: &str) -> Result<Vec<TurfAdjacency>> {
+    let mut turfs = Vec::new();
+    for entry in adjacency_string.split(';') {
+        if entry.trim().is_empty() {
+            continue;
+        }
+        let mut entry_split = entry.splitn(2, ':');
+        let turf = entry_split.next().unwrap_or("").parse()?;
+        let mut neighbours = Vec::new();
+        for neighbour in entry_split.next().unwrap_or("").split(',') {
+            if !neighbour.trim().is_empty() {
+                neighbours.push(neighbour.parse()?);
+            }
+        }
+        turfs.push(TurfAdjacency { turf, neighbours });
+    }
+    Ok(turfs)
+}
+
+/// Runs one process_cell style share step over every listed turf. Each pair of
+/// neighbours shares once, with the adjacent turf count taken from the turf that
+/// gets processed first. Returns every mixture whose contents or temperature moved.
+pub fn share_all(mixtures: &mut MixtureRegistry, turfs: &[TurfAdjacency]) -> Result<Vec<Handle>> {
+    // validate everything up front so a bad handle doesn't leave half a tick applied
+    for adjacency in turfs {
+        mixtures.get(adjacency.turf)?;
+        for neighbour in &adjacency.neighbours {
+            mixtures.get(*neighbour)?;
+        }
+    }
+    let mut initial_temperatures: HashMap<Handle, Float> = HashMap::new();
+    let mut moved: HashSet<Handle> = HashSet::new();
+    let mut processed: HashSet<Handle> = HashSet::new();
+    for adjacency in turfs {
+        processed.insert(adjacency.turf);
+        let adjacent_turfs = adjacency.neighbours.len() as i16;
+        for neighbour in &adjacency.neighbours {
+            if processed.contains(neighbour) {
+                continue;
+            }
+            let (mix, sharer) = mixtures.get_pair_mut(adjacency.turf, *neighbour)?;
+            initial_temperatures.entry(adjacency.turf).or_insert(mix.temperature);
+            initial_temperatures.entry(*neighbour).or_insert(sharer.temperature);
+            let (_, last_share) = mix.share(sharer, adjacent_turfs);
+            if last_share > 0.0 {
+                moved.insert(adjacency.turf);
+                moved.insert(*neighbour);
+            }
+        }
+    }
+    let mut changed: Vec<Handle> = initial_temperatures
+        .into_iter()
+        .filter(|&(handle, temperature)| moved.contains(&handle) || mixtures.get(handle).map(|mix| mix.temperature != temperature).unwrap_or(false))
+        .map(|(handle, _)| handle)
+        .collect();
+    changed.sort_by_key(|handle| handle.to_bits());
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atmos::mix::AtmosMixture;
+
+    #[test]
+    fn test_share_all() {
+        let mut mixtures = MixtureRegistry::default();
+        let mut full : AtmosMixture = Default::default();
+        full.parse_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap();
+        let left = mixtures.create(full.clone());
+        let middle = mixtures.create(full);
+        let right = mixtures.create(Default::default());
+        let far = mixtures.create(Default::default());
+        let adjacency_string = format!("{}:{};{}:{},{};{}:{};{}:;", left, middle, middle, left, right, right, middle, far);
+        let turfs = parse_adjacency(&adjacency_string).unwrap();
+        assert_eq!(turfs.len(), 4);
+        let initial_total_moles : Float = [left, middle, right].iter().map(|h| mixtures.get(*h).unwrap().total_moles()).sum();
+        let changed = share_all(&mut mixtures, &turfs).unwrap();
+        assert_eq!(changed, vec![middle, right]);
+        let total_moles : Float = [left, middle, right].iter().map(|h| mixtures.get(*h).unwrap().total_moles()).sum();
+        assert!((total_moles - initial_total_moles).abs() < 0.01);
+        assert!(parse_adjacency("12:bogus").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use atmos::constants::{float, Constant, Float};
+use atmos::gases::gases;
+use atmos::mix::AtmosMixture;
+
+use error::{Error, Result};
+
+// version 1 stores f32s, version 2 the same layout with f64s and version 3 the
+// raw i64s of a fixed build
+const F32_VERSION : u8 = 1;
+const F64_VERSION : u8 = 2;
+const FIXED_VERSION : u8 = 3;
+
+#[cfg(not(any(feature = "f64", feature = "fixed")))]
+pub const BINARY_VERSION : u8 = F32_VERSION;
+#[cfg(feature = "f64")]
+pub const BINARY_VERSION : u8 = F64_VERSION;
+#[cfg(feature = "fixed")]
+pub const BINARY_VERSION : u8 = FIXED_VERSION;
+
+// Layout, all little-endian:
+//   u8 version, u16 gas count, u16 entry count,
+//   entry count * (u16 gas index, float moles),
+//   float temperature, float volume
+// Only gases whose bits aren't all zero get an entry. Floats are stored as their raw
+// bits in the build's own width, so decoding gives back exactly what was encoded,
+// unlike to_params. Any version decodes in any build, rounding if it has to.
+
+pub fn encode(mix: &AtmosMixture) -> Vec<u8> {
+    // the padding past the last gas isn't part of the format
+    let gas_count = mix.gases.len().min(gases().len());
+    let entries: Vec<(usize, Float)> = mix.gases[..gas_count].iter().cloned().enumerate().filter(|&(_, amount)| amount.to_bits() != 0).collect();
+    let width = ::std::mem::size_of::<Float>();
+    let mut bytes = Vec::with_capacity(5 + entries.len() * (2 + width) + 2 * width);
+    bytes.push(BINARY_VERSION);
+    bytes.extend_from_slice(&(gas_count as u16).to_le_bytes());
+    bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (index, amount) in entries {
+        bytes.extend_from_slice(&(index as u16).to_le_bytes());
+        bytes.extend_from_slice(&amount.to_bits().to_le_bytes());
+    }
+    bytes.extend_from_slice(&mix.temperature.to_bits().to_le_bytes());
+    bytes.extend_from_slice(&mix.volume.to_bits().to_le_bytes());
+    bytes
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.position + count > self.bytes.len() {
+            return Err(Error::InvalidBinary("unexpected end of data".to_string()));
+        }
+        let slice = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(slice)
+    }
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16> {
+        let slice = self.take(2)?;
+        Ok(u16::from_le_bytes([slice[0], slice[1]]))
+    }
+    fn float(&mut self, version: u8) -> Result<Float> {
+        if version == F32_VERSION {
+            let slice = self.take(4)?;
+            return Ok(float(f32::from_bits(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]])) as Constant));
+        }
+        let slice = self.take(8)?;
+        let mut bits = [0; 8];
+        bits.copy_from_slice(slice);
+        if version == F64_VERSION {
+            Ok(float(f64::from_bits(u64::from_le_bytes(bits)) as Constant))
+        } else {
+            Ok(from_fixed_bits(i64::from_le_bytes(bits)))
+        }
+    }
+}
+
+#[cfg(feature = "fixed")]
+fn from_fixed_bits(bits: i64) -> Float {
+    Float::from_bits(bits)
+}
+
+#[cfg(not(feature = "fixed"))]
+fn from_fixed_bits(bits: i64) -> Float {
+    // a fixed build counts 2^-20ths
+    (bits as f64 / (1 << 20) as f64) as Float
+}
+
+pub fn decode(bytes: &[u8]) -> Result<AtmosMixture> {
+    let mut reader = Reader { bytes, position: 0 };
+    let version = reader.u8()?;
+    if version != F32_VERSION && version != F64_VERSION && version != FIXED_VERSION {
+        return Err(Error::InvalidBinary(format!("unsupported version {}", version)));
+    }
+    let gas_count = reader.u16()? as usize;
+    let known_gases = gases().len();
+    if gas_count > known_gases {
+        return Err(Error::InvalidBinary(format!("{} gases, only {} are known", gas_count, known_gases)));
+    }
+    let mut mix : AtmosMixture = Default::default();
+    let entries = reader.u16()?;
+    for _ in 0..entries {
+        let index = reader.u16()? as usize;
+        if index >= gas_count {
+            return Err(Error::InvalidBinary(format!("gas index {} out of range", index)));
+        }
+        mix.gases[index] = reader.float(version)?;
+    }
+    mix.temperature = reader.float(version)?;
+    mix.volume = reader.float(version)?;
+    if reader.position != bytes.len() {
+        return Err(Error::InvalidBinary("trailing data".to_string()));
+    }
+    Ok(mix)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex = hex.trim();
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(Error::InvalidBinary("malformed hex".to_string()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| Error::InvalidBinary("malformed hex".to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f32_blobs() {
+        // version 1 with 2.5 moles of the first gas, read the same in f32 and f64 builds
+        let mut bytes = vec![1, 1, 0, 1, 0, 0, 0];
+        for value in [2.5f32, 300.0, 2500.0].iter() {
+            bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.gases[0], 2.5);
+        assert_eq!(decoded.temperature, 300.0);
+        bytes.push(0);
+        assert!(decode(&bytes).is_err());
+    }
+    #[test]
+    fn test_fixed_blobs() {
+        // version 3 with 2.5 moles of the first gas, in 2^-20ths
+        let mut bytes = vec![3, 1, 0, 1, 0, 0, 0];
+        for value in [2.5f64, 300.0, 2500.0].iter() {
+            bytes.extend_from_slice(&((value * (1 << 20) as f64) as i64).to_le_bytes());
+        }
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.gases[0], 2.5);
+        assert_eq!(decoded.volume, 2500.0);
+    }
+    #[test]
+    fn test_round_trip() {
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=304.3;plasma=40;tritium=40;miasma=30;no2=40;").unwrap();
+        mix.gases[0] = float(0.1 + 0.2);
+        mix.gases[1] = float(-0.0);
+        mix.gases[3] = float(1e-40);
+        mix.temperature = float(1.0 / 3.0);
+        let hex = to_hex(&encode(&mix));
+        let decoded = decode(&from_hex(&hex).unwrap()).unwrap();
+        for (a, b) in mix.gases.iter().zip(decoded.gases.iter()) {
+            assert_eq!(a.to_bits(), b.to_bits());
+        }
+        assert_eq!(mix.temperature.to_bits(), decoded.temperature.to_bits());
+        assert_eq!(mix.volume.to_bits(), decoded.volume.to_bits());
+    }
+    #[test]
+    fn test_rejects_garbage() {
+        let bytes = encode(&Default::default());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        let mut wrong_version = bytes.clone();
+        wrong_version[0] = 4;
+        assert!(decode(&wrong_version).is_err());
+        assert!(from_hex("0g").is_err());
+        assert!(from_hex("abc").is_err());
+    }
+}
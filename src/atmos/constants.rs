@@ -1,9 +1,64 @@
-pub const GAS_CONSTANT : f32 = 8.31446261815324;
-pub const OPEN_HEAT_TRANSFER_COEFFICIENT : f32 = 0.4;
-pub const CMB_TEMP : f32 = 2.7;
-pub const MINIMUM_MOLE_COUNT : f32 = 0.1;
-pub const T0C: f32 = 273.15;
-pub const FIRE_MINIMUM_TEMPERATURE_TO_EXIST : f32 = T0C + 100.0;
-pub const FUSION_MOLE_THRESHOLD : f32 = 250.0;
-pub const STIMULUM_HEAT_SCALE : f32 = 100_000.0;
-pub const ATMOSPHERE : f32 = 101.325;
\ No newline at end of file
+/// The number type all atmos math is done in: `f32` by default, `f64` when built
+/// with the `f64` feature and the deterministic `Fixed` with the `fixed` feature.
+#[cfg(not(any(feature = "f64", feature = "fixed")))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+#[cfg(feature = "fixed")]
+pub type Float = ::atmos::fixed::Fixed;
+
+#[cfg(all(feature = "f64", feature = "fixed"))]
+compile_error!("the f64 and fixed features can't be used together");
+
+/// The type of the constants below and of `float_consts`. It's `Float` itself
+/// except in a `fixed` build, where constants stay `f64` and are converted where
+/// they meet a `Float`, since `Fixed` arithmetic can't run at compile time.
+#[cfg(not(feature = "fixed"))]
+pub type Constant = Float;
+#[cfg(feature = "fixed")]
+pub type Constant = f64;
+
+/// Turns a `Constant`, a literal or a count cast `as Constant` into a `Float`.
+#[cfg(not(feature = "fixed"))]
+pub const fn float(value: Constant) -> Float {
+    value
+}
+#[cfg(feature = "fixed")]
+pub const fn float(value: Constant) -> Float {
+    ::atmos::fixed::Fixed::from_f64(value)
+}
+
+/// `std::f32::consts` or `std::f64::consts` to go with `Constant`.
+#[cfg(not(any(feature = "f64", feature = "fixed")))]
+pub use std::f32::consts as float_consts;
+#[cfg(any(feature = "f64", feature = "fixed"))]
+pub use std::f64::consts as float_consts;
+
+pub const GAS_CONSTANT : Constant = 8.31446261815324;
+pub const OPEN_HEAT_TRANSFER_COEFFICIENT : Constant = 0.4;
+pub const CMB_TEMP : Constant = 2.7;
+pub const MINIMUM_MOLE_COUNT : Constant = 0.1;
+pub const T0C: Constant = 273.15;
+pub const FIRE_MINIMUM_TEMPERATURE_TO_EXIST : Constant = T0C + 100.0;
+pub const FUSION_MOLE_THRESHOLD : Constant = 250.0;
+pub const STIMULUM_HEAT_SCALE : Constant = 100_000.0;
+pub const ATMOSPHERE : Constant = 101.325;
+pub const T20C : Constant = T0C + 20.0;
+pub const CELL_VOLUME : Constant = 2500.0;
+pub const MOLES_CELLSTANDARD : Constant = ATMOSPHERE*CELL_VOLUME/(T20C*GAS_CONSTANT);
+pub const MINIMUM_AIR_RATIO_TO_MOVE : Constant = 0.001;
+pub const MINIMUM_MOLES_DELTA_TO_MOVE : Constant = MOLES_CELLSTANDARD*MINIMUM_AIR_RATIO_TO_MOVE;
+pub const MINIMUM_AIR_RATIO_TO_SUSPEND : Constant = 0.1;
+pub const MINIMUM_AIR_TO_SUSPEND : Constant = MOLES_CELLSTANDARD*MINIMUM_AIR_RATIO_TO_SUSPEND;
+pub const EXCITED_GROUP_BREAKDOWN_CYCLES : u32 = 4;
+pub const EXCITED_GROUP_DISMANTLE_CYCLES : u32 = 16;
+pub const STEFAN_BOLTZMANN_CONSTANT : Constant = 5.670374e-8;
+pub const HE_PIPE_MINIMUM_TEMPERATURE_DIFFERENCE : Constant = 20.0;
+pub const MAX_OUTPUT_PRESSURE : Constant = 4500.0;
+pub const VOLUME_PUMP_MAX_OUTPUT_PRESSURE : Constant = 9000.0;
+pub const VOLUME_PUMP_OVERCLOCK_MAX_DIFFERENCE : Constant = 1000.0;
+pub const VENT_PUMP_MAX_PRESSURE_DELTA : Constant = 10000.0;
+pub const SCRUBBER_DEFAULT_VOLUME_RATE : Constant = 200.0;
+pub const SCRUBBER_MAX_PIPE_PRESSURE : Constant = 50.0*ATMOSPHERE;
+pub const MONSTERMOS_TURF_LIMIT : usize = 200;
+pub const MONSTERMOS_HARD_TURF_LIMIT : usize = 2000;
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use atmos::constants::{float, Constant, Float};
+use atmos::formula::{self, constant, Expr, Variable};
+use atmos::gases::gases;
+use atmos::mix::AtmosMixture;
+use atmos::reaction::{ReactionRegistry, ReactionResult};
+
+use error::{Error, Result};
+
+// Reactions defined in a file rather than in reaction.rs. A file is a list of
+// blocks like this, one line per field, `#` for comments:
+//
+//   [reaction_id]
+//   name = Display name
+//   priority = 2                      higher goes first
+//   min_temperature = 10000           these three are constants, checked before anything else
+//   min_pressure = 50
+//   min_moles tritium = 1
+//   let burned = min(o2 / 100, plasma)   evaluated in order; later lines can use earlier ones
+//   condition = o2 >= burned              every condition has to hold or nothing happens
+//   stops_reactions = 1                   nothing after this reaction runs if it passes
+//   exclusive = 1                         nothing after this reaction runs if it reacts
+//   consume plasma = burned               moles taken away, added or set, applied in file order
+//   produce co2 = burned
+//   set o2 = 0
+//   energy = 3000000 * burned             energy released; negative takes heat
+//   temperature = temperature + 1         sets the temperature outright instead of using energy
+//   result = burned, energy_released      reported to DM after the reaction id
+//   report_if = burned > 0
+//
+// Formulas can use gas ids for moles, temperature, pressure, volume, total_moles,
+// heat_capacity, thermal_energy, fusion_power and any earlier `let`. Everything is
+// worked out from the mixture as it was before the reaction touched it.
+
+enum Change {
+    Consume(usize, Expr),
+    Produce(usize, Expr),
+    Set(usize, Expr),
+}
+
+pub struct DataReaction {
+    pub id: String,
+    pub name: String,
+    pub priority: Float,
+    pub min_temperature: Option<Float>,
+    pub min_pressure: Option<Float>,
+    pub min_moles: Vec<(usize, Float)>,
+    pub stops_reactions: bool,
+    pub exclusive: bool,
+    locals: Vec<Expr>,
+    conditions: Vec<Expr>,
+    changes: Vec<Change>,
+    energy: Option<Expr>,
+    temperature: Option<Expr>,
+    results: Vec<Expr>,
+    report_if: Option<Expr>,
+}
+
+fn lookup(air: &AtmosMixture, locals: &[Float], variable: Variable) -> Float {
+    match variable {
+        Variable::Temperature => air.temperature,
+        Variable::Pressure => air.pressure(),
+        Variable::Volume => air.volume,
+        Variable::TotalMoles => air.total_moles(),
+        Variable::HeatCapacity => air.heat_capacity(),
+        Variable::ThermalEnergy => air.thermal_energy(),
+        Variable::FusionPower => gases().iter().zip(air.gases.iter()).fold(float(0.0), |x, (gas, amount)| x + gas.fusion_power * amount),
+        Variable::Gas(index) => air.gases.get(index).cloned().unwrap_or(float(0.0)),
+        Variable::Local(index) => locals[index],
+    }
+}
+
+impl DataReaction {
+    fn new(id: String) -> DataReaction {
+        DataReaction {
+            name: id.clone(),
+            id,
+            priority: float(0.0),
+            min_temperature: None,
+            min_pressure: None,
+            min_moles: Vec::new(),
+            stops_reactions: false,
+            exclusive: false,
+            locals: Vec::new(),
+            conditions: Vec::new(),
+            changes: Vec::new(),
+            energy: None,
+            temperature: None,
+            results: Vec::new(),
+            report_if: None,
+        }
+    }
+    /// Runs the reaction. The minimums aren't checked here; `Reaction::react` does that.
+    pub fn react(&self, air: &mut AtmosMixture) -> (ReactionResult, Vec<String>) {
+        let mut locals = Vec::with_capacity(self.locals.len());
+        for expr in &self.locals {
+            let value = expr.evaluate(&|variable| lookup(air, &locals, variable));
+            locals.push(value);
+        }
+        let evaluate = |expr: &Expr| expr.evaluate(&|variable| lookup(air, &locals, variable));
+        if !self.conditions.iter().all(|condition| evaluate(condition) != 0.0) {
+            return (ReactionResult::NoReaction, Vec::new());
+        }
+        if self.stops_reactions {
+            return (ReactionResult::StopReactions, Vec::new());
+        }
+        let changes: Vec<Float> = self.changes.iter().map(|change| match *change {
+            Change::Consume(_, ref expr) | Change::Produce(_, ref expr) | Change::Set(_, ref expr) => evaluate(expr),
+        }).collect();
+        let energy = self.energy.as_ref().map(&evaluate).unwrap_or(float(0.0));
+        let temperature = self.temperature.as_ref().map(&evaluate);
+        let mut results = Vec::new();
+        if !self.results.is_empty() && self.report_if.as_ref().map(|expr| evaluate(expr) != 0.0).unwrap_or(true) {
+            results.push(self.id.clone());
+            results.extend(self.results.iter().map(|expr| evaluate(expr).to_string()));
+        }
+        let old_energy = air.thermal_energy();
+        for (change, amount) in self.changes.iter().zip(changes) {
+            match *change {
+                Change::Consume(index, _) => air.gases[index] -= amount,
+                Change::Produce(index, _) => air.gases[index] += amount,
+                Change::Set(index, _) => air.gases[index] = amount,
+            }
+        }
+        if let Some(temperature) = temperature {
+            air.temperature = temperature;
+        } else if energy != 0.0 {
+            air.temperature = (old_energy + energy) / air.heat_capacity();
+        }
+        (ReactionResult::Reacting, results)
+    }
+}
+
+// splits on commas that aren't inside a function call
+fn split_top_level(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+struct ReactionParser {
+    reaction: DataReaction,
+    local_names: HashMap<String, usize>,
+    // lets that come out constant are put straight into later formulas
+    constants: HashMap<String, Constant>,
+}
+
+impl ReactionParser {
+    fn resolve(&self, name: &str) -> Option<Expr> {
+        if let Some(&value) = self.constants.get(name) {
+            return Some(Expr::Number(value));
+        }
+        if let Some(&index) = self.local_names.get(name) {
+            return Some(Expr::Variable(Variable::Local(index)));
+        }
+        Variable::builtin(name).or_else(|| gases().index_of(name).map(Variable::Gas)).map(Expr::Variable)
+    }
+    fn formula(&self, source: &str) -> Result<Expr> {
+        formula::parse(source, &|name| self.resolve(name))
+    }
+    fn constant(&self, source: &str) -> Result<Float> {
+        formula::parse(source, &|_| None)?
+            .constant_value()
+            .ok_or_else(|| self.error(&format!("{} isn't a constant", source)))
+    }
+    fn gas(&self, id: Option<&str>) -> Result<usize> {
+        let id = id.ok_or_else(|| self.error("missing gas id"))?;
+        gases().index_of(id).ok_or_else(|| self.error(&format!("unknown gas {}", id)))
+    }
+    fn error(&self, reason: &str) -> Error {
+        Error::InvalidReaction(format!("{} in reaction {}", reason, self.reaction.id))
+    }
+    fn field(&mut self, key: &str, argument: Option<&str>, value: &str) -> Result<()> {
+        match (key, argument) {
+            ("name", None) => self.reaction.name = value.to_string(),
+            ("priority", None) => self.reaction.priority = self.constant(value)?,
+            ("min_temperature", None) => self.reaction.min_temperature = Some(self.constant(value)?),
+            ("min_pressure", None) => self.reaction.min_pressure = Some(self.constant(value)?),
+            ("min_moles", _) => {
+                let requirement = (self.gas(argument)?, self.constant(value)?);
+                self.reaction.min_moles.push(requirement);
+            }
+            ("stops_reactions", None) => self.reaction.stops_reactions = self.constant(value)? != 0.0,
+            ("exclusive", None) => self.reaction.exclusive = self.constant(value)? != 0.0,
+            ("let", Some(name)) => {
+                if self.resolve(name).is_some() || constant(name).is_some() {
+                    return Err(self.error(&format!("{} is already defined", name)));
+                }
+                let expr = self.formula(value)?;
+                if let Some(value) = expr.number() {
+                    self.constants.insert(name.to_string(), value);
+                } else {
+                    self.local_names.insert(name.to_string(), self.reaction.locals.len());
+                    self.reaction.locals.push(expr);
+                }
+            }
+            ("condition", None) => {
+                let expr = self.formula(value)?;
+                self.reaction.conditions.push(expr);
+            }
+            ("consume", _) | ("produce", _) | ("set", _) => {
+                let (index, expr) = (self.gas(argument)?, self.formula(value)?);
+                self.reaction.changes.push(match key {
+                    "consume" => Change::Consume(index, expr),
+                    "produce" => Change::Produce(index, expr),
+                    _ => Change::Set(index, expr),
+                });
+            }
+            ("energy", None) => self.reaction.energy = Some(self.formula(value)?),
+            ("temperature", None) => self.reaction.temperature = Some(self.formula(value)?),
+            ("result", None) => {
+                for part in split_top_level(value) {
+                    let expr = self.formula(part)?;
+                    self.reaction.results.push(expr);
+                }
+            }
+            ("report_if", None) => self.reaction.report_if = Some(self.formula(value)?),
+            _ => return Err(self.error(&format!("unknown field {}", key))),
+        }
+        Ok(())
+    }
+}
+
+/// Parses a reaction file into a registry of its reactions.
+pub fn parse_reactions(definitions: &str) -> Result<ReactionRegistry> {
+    let mut reactions: Vec<DataReaction> = Vec::new();
+    let mut current: Option<ReactionParser> = None;
+    for line in definitions.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            let id = line[1..line.len() - 1].trim().to_string();
+            if id.is_empty() || reactions.iter().chain(current.as_ref().map(|parser| &parser.reaction)).any(|reaction| reaction.id == id) {
+                return Err(Error::InvalidReaction(format!("missing or duplicate reaction id [{}]", id)));
+            }
+            if let Some(parser) = current.take() {
+                reactions.push(parser.reaction);
+            }
+            current = Some(ReactionParser { reaction: DataReaction::new(id), local_names: HashMap::new(), constants: HashMap::new() });
+            continue;
+        }
+        let parser = current.as_mut().ok_or_else(|| Error::InvalidReaction(format!("{} is outside of a reaction", line)))?;
+        let mut line_split = line.splitn(2, '=');
+        let (key, value) = match (line_split.next(), line_split.next()) {
+            (Some(key), Some(value)) => (key, value.trim()),
+            _ => return Err(parser.error(&format!("malformed line {}", line))),
+        };
+        let mut key_split = key.split_whitespace();
+        let (key, argument) = (key_split.next().unwrap_or(""), key_split.next());
+        if key_split.next().is_some() {
+            return Err(parser.error(&format!("malformed line {}", line)));
+        }
+        parser.field(key, argument, value)?;
+    }
+    if let Some(parser) = current.take() {
+        reactions.push(parser.reaction);
+    }
+    if reactions.iter().any(|reaction| reaction.priority.is_nan()) {
+        return Err(Error::InvalidReaction("priority can't be NaN".to_string()));
+    }
+    let mut registry = ReactionRegistry::default();
+    for reaction in reactions {
+        registry.register(reaction.into());
+    }
+    Ok(registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atmos::gases::gas_index;
+    use atmos::reaction::ReactionHandler;
+
+    const REACTION_FILE: &str = include_str!("../../data/reactions.txt");
+
+    fn close(a: Float, b: Float) -> bool {
+        a == b || (a - b).abs() <= 1e-4 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    // The shipped reaction file is meant to be the hand-written reactions, line for line.
+    #[test]
+    fn test_file_matches_builtin_reactions() {
+        let reactions = parse_reactions(REACTION_FILE).unwrap();
+        let builtin = ReactionRegistry::builtin();
+        assert_eq!(reactions.len(), builtin.len());
+        let temperatures = [300.0, 380.0, 500.0, 1000.0, 20000.0, 60000.0, 200000.0, 6e6, 1e8];
+        let amounts = [0.0, 0.05, 1.0, 6.0, 15.0, 25.0, 40.0, 300.0, 2500.0];
+        let ids = ["n2", "o2", "plasma", "co2", "n2o", "water_vapor", "bz", "tritium", "miasma", "no2", "nob", "stim"];
+        let mut seed: u32 = 12345;
+        let mut next = |n: usize| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as usize % n
+        };
+        for _ in 0..2000 {
+            let mut mix = AtmosMixture { temperature: float(temperatures[next(temperatures.len())]), ..Default::default() };
+            for id in ids.iter() {
+                mix.gases[gas_index(id)] = float(amounts[next(amounts.len())]);
+            }
+            for (native, data) in builtin.iter().zip(reactions.iter()) {
+                assert_eq!(native.id, data.id);
+                assert_eq!(native.min_moles, data.min_moles, "{}", data.id);
+                assert!(matches!(data.handler, ReactionHandler::Data(_)));
+                let mut native_mix = mix.clone();
+                let mut data_mix = mix.clone();
+                let (native_result, native_strings) = native.react(&mut native_mix);
+                let (data_result, data_strings) = data.react(&mut data_mix);
+                assert_eq!(native_result == ReactionResult::StopReactions, data_result == ReactionResult::StopReactions, "{}", data.id);
+                assert!(close(native_mix.temperature, data_mix.temperature), "{}: {} vs {}", data.id, native_mix.temperature, data_mix.temperature);
+                for (a, b) in native_mix.gases.iter().zip(data_mix.gases.iter()) {
+                    assert!(close(*a, *b), "{}: {} vs {}", data.id, a, b);
+                }
+                assert_eq!(native_strings.len(), data_strings.len(), "{}", data.id);
+                if let (Some(a), Some(b)) = (native_strings.first(), data_strings.first()) {
+                    assert_eq!(a, b);
+                }
+                for (a, b) in native_strings.iter().zip(data_strings.iter()).skip(1) {
+                    assert!(close(a.parse().unwrap(), b.parse().unwrap()), "{}: {} vs {}", data.id, a, b);
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_bad_files() {
+        assert!(parse_reactions("name = floating").is_err());
+        assert!(parse_reactions("[a]\nlet o2 = 1").is_err());
+        assert!(parse_reactions("[a]\nconsume unobtainium = 1").is_err());
+        assert!(parse_reactions("[a]\nmin_temperature = temperature").is_err());
+        assert!(parse_reactions("[a]\n[a]").is_err());
+        assert!(parse_reactions("[a]\nresult = min(o2, n2), o2 +").is_err());
+        let reactions = parse_reactions("[low]\npriority = -1\n[high]\npriority = 5\nresult = min(o2, n2), 2").unwrap();
+        assert_eq!(reactions.iter().next().unwrap().id, "high");
+    }
+}
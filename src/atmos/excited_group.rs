@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+
+use atmos::grid::Turf;
+use atmos::mix::AtmosMixture;
+
+use atmos::constants::*;
+
+/// A set of connected turfs that keep trading small amounts of gas with each other.
+/// Rather than letting them trickle towards equilibrium forever, the group gets
+/// averaged out every few quiet ticks and put to sleep once it stays quiet.
+#[derive(Clone, Default)]
+pub struct ExcitedGroup {
+    pub turfs: Vec<usize>,
+    pub breakdown_cooldown: u32,
+    pub dismantle_cooldown: u32,
+}
+
+impl ExcitedGroup {
+    pub fn reset_cooldowns(&mut self) {
+        self.breakdown_cooldown = 0;
+        self.dismantle_cooldown = 0;
+    }
+    /// Sets every turf in the group to the group's average mixture, keeping their own volumes.
+    pub fn self_breakdown(&mut self, turfs: &mut [Turf]) {
+        let mut combined : AtmosMixture = Default::default();
+        let mut total_volume = float(0.0);
+        for &index in &self.turfs {
+            combined.merge(turfs[index].mix.clone());
+            total_volume += turfs[index].mix.volume;
+        }
+        for &index in &self.turfs {
+            let volume = turfs[index].mix.volume;
+            let mut share = combined.remove_ratio(volume / total_volume);
+            total_volume -= volume;
+            share.volume = volume;
+            turfs[index].mix = share;
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ExcitedGroups {
+    groups: BTreeMap<u32, ExcitedGroup>,
+    next_id: u32,
+}
+
+impl ExcitedGroups {
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+    /// Puts two turfs that just shared into the same group, creating or merging
+    /// groups as needed. Returns the id of the group they ended up in.
+    pub fn link(&mut self, turfs: &mut [Turf], first: usize, second: usize) -> u32 {
+        match (turfs[first].excited_group, turfs[second].excited_group) {
+            (Some(first_group), Some(second_group)) if first_group == second_group => first_group,
+            (Some(first_group), Some(second_group)) => {
+                let (keep, absorb) = if self.groups[&first_group].turfs.len() >= self.groups[&second_group].turfs.len() {
+                    (first_group, second_group)
+                } else {
+                    (second_group, first_group)
+                };
+                let absorbed = self.groups.remove(&absorb).unwrap();
+                for &index in &absorbed.turfs {
+                    turfs[index].excited_group = Some(keep);
+                }
+                let group = self.groups.get_mut(&keep).unwrap();
+                group.turfs.extend(absorbed.turfs);
+                group.reset_cooldowns();
+                keep
+            }
+            (Some(group), None) => self.add_turf(turfs, group, second),
+            (None, Some(group)) => self.add_turf(turfs, group, first),
+            (None, None) => {
+                let id = self.next_id;
+                self.next_id = self.next_id.wrapping_add(1);
+                self.groups.insert(id, Default::default());
+                self.add_turf(turfs, id, first);
+                self.add_turf(turfs, id, second)
+            }
+        }
+    }
+    fn add_turf(&mut self, turfs: &mut [Turf], id: u32, index: usize) -> u32 {
+        let group = self.groups.get_mut(&id).unwrap();
+        group.turfs.push(index);
+        group.reset_cooldowns();
+        turfs[index].excited_group = Some(id);
+        id
+    }
+    pub fn reset_cooldowns(&mut self, id: u32) {
+        if let Some(group) = self.groups.get_mut(&id) {
+            group.reset_cooldowns();
+        }
+    }
+    /// Takes a turf out of whatever group it is in, e.g. because it just became a wall.
+    pub fn remove_turf(&mut self, turfs: &mut [Turf], index: usize) {
+        if let Some(id) = turfs[index].excited_group.take() {
+            let now_empty = match self.groups.get_mut(&id) {
+                Some(group) => {
+                    group.turfs.retain(|&turf| turf != index);
+                    group.turfs.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                self.groups.remove(&id);
+            }
+        }
+    }
+    /// Advances every group's cooldowns by one tick. Groups that have been quiet long
+    /// enough get broken down to their average; groups that have been quiet for longer
+    /// still are dismantled. Returns the broken down turfs and the dismantled turfs.
+    pub fn process(&mut self, turfs: &mut [Turf]) -> (Vec<usize>, Vec<usize>) {
+        let mut broken_down = Vec::new();
+        let mut dismantled = Vec::new();
+        let mut finished = Vec::new();
+        for (&id, group) in self.groups.iter_mut() {
+            group.breakdown_cooldown += 1;
+            group.dismantle_cooldown += 1;
+            if group.breakdown_cooldown >= EXCITED_GROUP_BREAKDOWN_CYCLES {
+                group.self_breakdown(turfs);
+                group.breakdown_cooldown = 0;
+                broken_down.extend(group.turfs.iter().cloned());
+            }
+            if group.dismantle_cooldown >= EXCITED_GROUP_DISMANTLE_CYCLES {
+                for &index in &group.turfs {
+                    turfs[index].excited_group = None;
+                }
+                dismantled.extend(group.turfs.iter().cloned());
+                finished.push(id);
+            }
+        }
+        for id in finished {
+            self.groups.remove(&id);
+        }
+        (broken_down, dismantled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breakdown_averages() {
+        let mut turfs : Vec<Turf> = vec![Default::default(); 3];
+        turfs[0].mix.parse_gas_string("TEMP=400;o2=30;").unwrap();
+        turfs[1].mix.parse_gas_string("TEMP=200;o2=10;n2=20;").unwrap();
+        let mut groups : ExcitedGroups = Default::default();
+        let id = groups.link(&mut turfs, 0, 1);
+        assert_eq!(groups.link(&mut turfs, 2, 1), id);
+        assert_eq!(groups.groups[&id].turfs.len(), 3);
+        let initial_energy : Float = turfs.iter().map(|turf| turf.mix.thermal_energy()).sum();
+        for _ in 0..EXCITED_GROUP_BREAKDOWN_CYCLES {
+            groups.process(&mut turfs);
+        }
+        for turf in &turfs {
+            assert!((turf.mix.total_moles() - 20.0).abs() < 0.001);
+            assert!((turf.mix.temperature - turfs[0].mix.temperature).abs() < 0.001);
+        }
+        let energy : Float = turfs.iter().map(|turf| turf.mix.thermal_energy()).sum();
+        assert!((energy - initial_energy).abs() / initial_energy < 0.0001);
+    }
+    #[test]
+    fn test_merge_and_dismantle() {
+        let mut turfs : Vec<Turf> = vec![Default::default(); 4];
+        let mut groups : ExcitedGroups = Default::default();
+        let first = groups.link(&mut turfs, 0, 1);
+        let second = groups.link(&mut turfs, 2, 3);
+        assert!(first != second);
+        groups.link(&mut turfs, 1, 2);
+        assert_eq!(groups.len(), 1);
+        groups.remove_turf(&mut turfs, 3);
+        assert!(turfs[3].excited_group.is_none());
+        let mut dismantled = Vec::new();
+        for _ in 0..EXCITED_GROUP_DISMANTLE_CYCLES {
+            dismantled = groups.process(&mut turfs).1;
+        }
+        assert_eq!(dismantled.len(), 3);
+        assert!(groups.is_empty());
+    }
+}
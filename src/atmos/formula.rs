@@ -0,0 +1,557 @@
+use std::cmp::{max, min};
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+use super::float_ord::FloatOrd;
+
+use atmos::constants::*;
+use atmos::constants::float_consts::PI;
+use atmos::gases::gases;
+
+use error::{Error, Result};
+
+// A small expression language for reaction definitions. Numbers are Floats like
+// everywhere else, comparisons and logic give 1 or 0, and anything nonzero is true.
+// Operators, loosest first: || && (< <= > >= == !=) (+ -) (* / %) (unary - !) ^
+// min and max go through FloatOrd, same as the hand-written reactions, so a
+// reaction moved into a file gives back the same numbers. For the same reason
+// constants are worked out as `Constant`s, and multiplying by one is `Float *
+// Constant`, which keeps the constant's full precision in a fixed build.
+
+/// How deep a formula can nest, counting brackets, function calls, unary operators
+/// and every operator chained onto the one before it. Keeps a formula from DM from
+/// overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+/// The numbers a formula can be worked out in: `Float` when reacting, and
+/// `Constant` when folding constants at parse time.
+pub trait Scalar: Copy + PartialEq + PartialOrd + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Rem<Output = Self> + Neg<Output = Self> {
+    fn from_constant(value: Constant) -> Self;
+    fn scale(self, factor: Constant) -> Self;
+    fn powf(self, exponent: Self) -> Self;
+    fn abs(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn atan(self) -> Self;
+    fn min_ord(self, other: Self) -> Self;
+    fn max_ord(self, other: Self) -> Self;
+}
+
+macro_rules! scalar_methods {
+    ($t:ty) => {
+        fn powf(self, exponent: $t) -> $t {
+            <$t>::powf(self, exponent)
+        }
+        fn abs(self) -> $t {
+            <$t>::abs(self)
+        }
+        fn sqrt(self) -> $t {
+            <$t>::sqrt(self)
+        }
+        fn sin(self) -> $t {
+            <$t>::sin(self)
+        }
+        fn cos(self) -> $t {
+            <$t>::cos(self)
+        }
+        fn atan(self) -> $t {
+            <$t>::atan(self)
+        }
+        fn min_ord(self, other: $t) -> $t {
+            min(FloatOrd(self), FloatOrd(other)).0
+        }
+        fn max_ord(self, other: $t) -> $t {
+            max(FloatOrd(self), FloatOrd(other)).0
+        }
+    };
+}
+
+impl Scalar for Float {
+    fn from_constant(value: Constant) -> Float {
+        float(value)
+    }
+    fn scale(self, factor: Constant) -> Float {
+        self * factor
+    }
+    scalar_methods!(Float);
+}
+
+#[cfg(feature = "fixed")]
+impl Scalar for Constant {
+    fn from_constant(value: Constant) -> Constant {
+        value
+    }
+    fn scale(self, factor: Constant) -> Constant {
+        self * factor
+    }
+    scalar_methods!(Constant);
+}
+
+#[cfg(not(feature = "fixed"))]
+fn to_constant(value: Float) -> Constant {
+    value
+}
+#[cfg(feature = "fixed")]
+fn to_constant(value: Float) -> Constant {
+    value.to_f64()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variable {
+    Temperature,
+    Pressure,
+    Volume,
+    TotalMoles,
+    HeatCapacity,
+    ThermalEnergy,
+    FusionPower,
+    Gas(usize),
+    Local(usize),
+}
+
+impl Variable {
+    pub fn builtin(name: &str) -> Option<Variable> {
+        match name {
+            "temperature" => Some(Variable::Temperature),
+            "pressure" => Some(Variable::Pressure),
+            "volume" => Some(Variable::Volume),
+            "total_moles" => Some(Variable::TotalMoles),
+            "heat_capacity" => Some(Variable::HeatCapacity),
+            "thermal_energy" => Some(Variable::ThermalEnergy),
+            "fusion_power" => Some(Variable::FusionPower),
+            _ => None,
+        }
+    }
+}
+
+pub fn constant(name: &str) -> Option<Constant> {
+    Some(match name {
+        "PI" => PI,
+        "INFINITY" => Constant::INFINITY,
+        "GAS_CONSTANT" => GAS_CONSTANT,
+        "CMB_TEMP" => CMB_TEMP,
+        "T0C" => T0C,
+        "T20C" => T20C,
+        "ATMOSPHERE" => ATMOSPHERE,
+        "CELL_VOLUME" => CELL_VOLUME,
+        "MOLES_CELLSTANDARD" => MOLES_CELLSTANDARD,
+        "MINIMUM_MOLE_COUNT" => MINIMUM_MOLE_COUNT,
+        "FIRE_MINIMUM_TEMPERATURE_TO_EXIST" => FIRE_MINIMUM_TEMPERATURE_TO_EXIST,
+        "FUSION_MOLE_THRESHOLD" => FUSION_MOLE_THRESHOLD,
+        "STIMULUM_HEAT_SCALE" => STIMULUM_HEAT_SCALE,
+        _ => return None,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Function {
+    Min,
+    Max,
+    Abs,
+    Sqrt,
+    Sin,
+    Cos,
+    Atan,
+    If,
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Number(Constant),
+    Variable(Variable),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+fn truth<T: Scalar>(value: bool) -> T {
+    T::from_constant(if value { 1.0 } else { 0.0 })
+}
+
+fn is_true<T: Scalar>(value: T) -> bool {
+    value != T::from_constant(0.0)
+}
+
+impl Expr {
+    pub fn evaluate<T: Scalar, F: Fn(Variable) -> T>(&self, lookup: &F) -> T {
+        match *self {
+            Expr::Number(value) => T::from_constant(value),
+            Expr::Variable(variable) => lookup(variable),
+            Expr::Negate(ref inner) => -inner.evaluate(lookup),
+            Expr::Not(ref inner) => truth(!is_true(inner.evaluate(lookup))),
+            Expr::Binary(BinaryOp::And, ref left, ref right) => truth(is_true(left.evaluate(lookup)) && is_true(right.evaluate(lookup))),
+            Expr::Binary(BinaryOp::Or, ref left, ref right) => truth(is_true(left.evaluate(lookup)) || is_true(right.evaluate(lookup))),
+            Expr::Binary(BinaryOp::Multiply, ref left, ref right) => match (left.number(), right.number()) {
+                (_, Some(factor)) => left.evaluate(lookup).scale(factor),
+                (Some(factor), _) => right.evaluate(lookup).scale(factor),
+                _ => left.evaluate(lookup) * right.evaluate(lookup),
+            },
+            Expr::Binary(op, ref left, ref right) => {
+                let (a, b) = (left.evaluate(lookup), right.evaluate(lookup));
+                match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Subtract => a - b,
+                    BinaryOp::Divide => a / b,
+                    BinaryOp::Remainder => a % b,
+                    BinaryOp::Power => a.powf(b),
+                    BinaryOp::Less => truth(a < b),
+                    BinaryOp::LessEqual => truth(a <= b),
+                    BinaryOp::Greater => truth(a > b),
+                    BinaryOp::GreaterEqual => truth(a >= b),
+                    BinaryOp::Equal => truth(a == b),
+                    BinaryOp::NotEqual => truth(a != b),
+                    BinaryOp::Multiply | BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+            Expr::Call(Function::If, ref args) => {
+                if is_true(args[0].evaluate(lookup)) {
+                    args[1].evaluate(lookup)
+                } else {
+                    args[2].evaluate(lookup)
+                }
+            }
+            // min(a, b, c) is min(min(a, b), c), which is what the tie-breaking of std's min and max gives too
+            Expr::Call(Function::Min, ref args) => args[1..].iter().fold(args[0].evaluate(lookup), |x, arg| x.min_ord(arg.evaluate(lookup))),
+            Expr::Call(Function::Max, ref args) => args[1..].iter().fold(args[0].evaluate(lookup), |x, arg| x.max_ord(arg.evaluate(lookup))),
+            Expr::Call(function, ref args) => {
+                let value = args[0].evaluate(lookup);
+                match function {
+                    Function::Abs => value.abs(),
+                    Function::Sqrt => value.sqrt(),
+                    Function::Sin => value.sin(),
+                    Function::Cos => value.cos(),
+                    Function::Atan => value.atan(),
+                    Function::Min | Function::Max | Function::If => unreachable!(),
+                }
+            }
+        }
+    }
+    /// The value of an expression that doesn't read the mixture, like a reaction's
+    /// minimum temperature.
+    pub fn constant_value(&self) -> Option<Float> {
+        self.number().map(float)
+    }
+    /// The same, at full `Constant` precision.
+    pub fn number(&self) -> Option<Constant> {
+        match *self {
+            Expr::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+    fn is_number(&self) -> bool {
+        self.number().is_some()
+    }
+    // anything built only out of numbers and constants gets worked out once, here
+    fn fold(self) -> Expr {
+        let constant = match self {
+            Expr::Negate(ref inner) | Expr::Not(ref inner) => inner.is_number(),
+            Expr::Binary(_, ref left, ref right) => left.is_number() && right.is_number(),
+            Expr::Call(_, ref args) => args.iter().all(Expr::is_number),
+            _ => false,
+        };
+        if constant {
+            Expr::Number(self.evaluate::<Constant, _>(&|_| 0.0))
+        } else {
+            self
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(Constant),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// longest first, so <= isn't read as < then =
+const SYMBOLS: &[&str] = &["<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!", "(", ")", ","];
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut exponent = i + 1;
+                if exponent < chars.len() && (chars[exponent] == '-' || chars[exponent] == '+') {
+                    exponent += 1;
+                }
+                if exponent < chars.len() && chars[exponent].is_ascii_digit() {
+                    i = exponent;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(text.parse().map_err(|_| formula_error(source, &format!("bad number {}", text)))?));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+                Some(symbol) => {
+                    tokens.push(Token::Symbol(symbol));
+                    i += symbol.len();
+                }
+                None => return Err(formula_error(source, &format!("unexpected {}", c))),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn formula_error(source: &str, reason: &str) -> Error {
+    Error::InvalidReaction(format!("{} in \"{}\"", reason, source))
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+    resolve: &'a dyn Fn(&str) -> Option<Expr>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, reason: &str) -> Error {
+        formula_error(self.source, reason)
+    }
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(&Token::Symbol(symbol)) => Some(symbol),
+            _ => None,
+        }
+    }
+    fn eat(&mut self, symbol: &str) -> bool {
+        if self.peek_symbol() == Some(symbol) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {}", symbol)))
+        }
+    }
+    /// Goes a level deeper, or fails if that's past `MAX_DEPTH`. Callers put
+    /// `depth` back once they're done.
+    fn descend(&mut self) -> Result<()> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("formula nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+    fn binary_level(&mut self, ops: &[(&str, BinaryOp)], next: fn(&mut Parser<'a>) -> Result<Expr>) -> Result<Expr> {
+        let depth = self.depth;
+        let left = self.binary_chain(ops, next);
+        self.depth = depth;
+        left
+    }
+    // every operator chained on makes the tree one deeper, so each counts as a level
+    fn binary_chain(&mut self, ops: &[(&str, BinaryOp)], next: fn(&mut Parser<'a>) -> Result<Expr>) -> Result<Expr> {
+        let mut left = next(self)?;
+        'outer: loop {
+            for &(symbol, op) in ops {
+                if self.eat(symbol) {
+                    self.descend()?;
+                    let right = next(self)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right)).fold();
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+    fn or(&mut self) -> Result<Expr> {
+        self.binary_level(&[("||", BinaryOp::Or)], Parser::and)
+    }
+    fn and(&mut self) -> Result<Expr> {
+        self.binary_level(&[("&&", BinaryOp::And)], Parser::comparison)
+    }
+    fn comparison(&mut self) -> Result<Expr> {
+        self.binary_level(&[
+            ("<=", BinaryOp::LessEqual),
+            (">=", BinaryOp::GreaterEqual),
+            ("<", BinaryOp::Less),
+            (">", BinaryOp::Greater),
+            ("==", BinaryOp::Equal),
+            ("!=", BinaryOp::NotEqual),
+        ], Parser::sum)
+    }
+    fn sum(&mut self) -> Result<Expr> {
+        self.binary_level(&[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)], Parser::product)
+    }
+    fn product(&mut self) -> Result<Expr> {
+        self.binary_level(&[("*", BinaryOp::Multiply), ("/", BinaryOp::Divide), ("%", BinaryOp::Remainder)], Parser::unary)
+    }
+    // every bracket, call and unary operator comes back through here
+    fn unary(&mut self) -> Result<Expr> {
+        let depth = self.depth;
+        self.descend()?;
+        let expr = if self.eat("-") {
+            self.unary().map(|inner| Expr::Negate(Box::new(inner)).fold())
+        } else if self.eat("!") {
+            self.unary().map(|inner| Expr::Not(Box::new(inner)).fold())
+        } else {
+            self.power()
+        };
+        self.depth = depth;
+        expr
+    }
+    fn power(&mut self) -> Result<Expr> {
+        let base = self.atom()?;
+        if self.eat("^") {
+            // right associative, and -x ^ 2 is -(x ^ 2)
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(BinaryOp::Power, Box::new(base), Box::new(exponent)).fold());
+        }
+        Ok(base)
+    }
+    fn atom(&mut self) -> Result<Expr> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Symbol("(")) => {
+                let inner = self.or()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Some(Token::Name(name)) => {
+                if self.eat("(") {
+                    return self.call(&name);
+                }
+                if let Some(value) = constant(&name) {
+                    return Ok(Expr::Number(value));
+                }
+                (self.resolve)(&name).ok_or_else(|| self.error(&format!("unknown name {}", name)))
+            }
+            Some(Token::Symbol(symbol)) => Err(self.error(&format!("unexpected {}", symbol))),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+    fn call(&mut self, name: &str) -> Result<Expr> {
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.or()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        // specific_heat(gas) is a number as soon as we know which gas
+        if name == "specific_heat" {
+            return match (args.len(), args.first()) {
+                (1, Some(&Expr::Variable(Variable::Gas(index)))) => Ok(Expr::Number(to_constant(gases().get(index).specific_heat))),
+                _ => Err(self.error("specific_heat takes a gas id")),
+            };
+        }
+        let (function, arity_ok) = match name {
+            "min" => (Function::Min, !args.is_empty()),
+            "max" => (Function::Max, !args.is_empty()),
+            "abs" => (Function::Abs, args.len() == 1),
+            "sqrt" => (Function::Sqrt, args.len() == 1),
+            "sin" => (Function::Sin, args.len() == 1),
+            "cos" => (Function::Cos, args.len() == 1),
+            "atan" => (Function::Atan, args.len() == 1),
+            "if" => (Function::If, args.len() == 3),
+            _ => return Err(self.error(&format!("unknown function {}", name))),
+        };
+        if !arity_ok {
+            return Err(self.error(&format!("wrong number of arguments to {}", name)));
+        }
+        Ok(Expr::Call(function, args).fold())
+    }
+}
+
+/// Parses a formula. Names that aren't constants or functions are handed to
+/// `resolve`, which says what they read from the mixture or stand for.
+pub fn parse(source: &str, resolve: &dyn Fn(&str) -> Option<Expr>) -> Result<Expr> {
+    let mut parser = Parser { source, tokens: tokenize(source)?, position: 0, depth: 0, resolve };
+    let expr = parser.or()?;
+    if parser.position < parser.tokens.len() {
+        return Err(parser.error("trailing input"));
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(source: &str, x: Constant) -> Float {
+        let expr = parse(source, &|name| if name == "x" { Some(Expr::Variable(Variable::Local(0))) } else { None }).unwrap();
+        expr.evaluate(&|_| float(x))
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3", 0.0), 7.0);
+        assert_eq!(evaluate("-x ^ 2", 3.0), -9.0);
+        assert_eq!(evaluate("2 ^ 3 ^ 2", 0.0), 512.0);
+        assert_eq!(evaluate("x * 4 % 5", 2.0), 3.0);
+        assert_eq!(evaluate("x > 1 && x < 3 || 0", 2.0), 1.0);
+        assert_eq!(evaluate("!(x == 2)", 2.0), 0.0);
+        assert_eq!(evaluate("if(x > 1, min(x, 5, 4), -1)", 7.0), 4.0);
+        // 1e-10 is below what a fixed build can hold, but constants fold at full precision
+        assert_eq!(evaluate("1e-10 * 1e10 + T0C", 0.0), float(1.0 + T0C));
+    }
+    #[test]
+    fn test_folding() {
+        let expr = parse("FIRE_MINIMUM_TEMPERATURE_TO_EXIST * 400", &|_| None).unwrap();
+        assert_eq!(expr.constant_value(), Some(float(FIRE_MINIMUM_TEMPERATURE_TO_EXIST * 400.0)));
+        assert!(parse("x + 1", &|_| None).is_err());
+        assert!(parse("min()", &|_| None).is_err());
+        assert!(parse("(1 + 2", &|_| None).is_err());
+        assert!(parse("1 2", &|_| None).is_err());
+    }
+    #[test]
+    fn test_depth_limit() {
+        let nested = format!("{}1{}", "(".repeat(10000), ")".repeat(10000));
+        assert!(matches!(parse(&nested, &|_| None), Err(Error::InvalidReaction(_))));
+        assert!(parse(&"-".repeat(10000), &|_| None).is_err());
+        assert!(parse(&vec!["temperature"; 10000].join(" + "), &|name| Variable::builtin(name).map(Expr::Variable)).is_err());
+        assert!(parse(&format!("{}1{}", "min(".repeat(40), ")".repeat(40)), &|_| None).is_ok());
+        assert!(parse(&vec!["temperature"; 40].join(" + "), &|name| Variable::builtin(name).map(Expr::Variable)).is_ok());
+    }
+}
@@ -1,14 +1,166 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
+
+use atmos::constants::{float, Float};
+use atmos::lanes::padded_len;
+
+use error::{Error, Result};
+
 pub struct GasDatum {
-    pub id: &'static str,
-    pub name: &'static str,
-    pub specific_heat: f32,
-    pub fusion_power: f32,
+    pub id: String,
+    pub name: String,
+    pub specific_heat: Float,
+    pub fusion_power: Float,
+    pub rarity: Float,
+    pub metadata: BTreeMap<String, String>,
 }
 
-impl GasDatum {
-    pub fn heat_capacity(&self,amount: f32) -> f32 {
-        return amount * self.specific_heat;
+/// Gases the hardcoded reactions refer to by id. Every definition file has to have these.
+pub const REQUIRED_GASES: &[&str] = &["n2", "o2", "plasma", "co2", "n2o", "water_vapor", "bz", "tritium", "miasma", "no2", "nob", "stim"];
+
+pub const DEFAULT_GASES: &str = include_str!("../../data/gases.txt");
+
+pub struct GasRegistry {
+    gases: Vec<GasDatum>,
+    by_id: HashMap<String, usize>,
+    /// Every gas's specific heat, zero-padded to the length mixtures' gas vectors use.
+    specific_heats: Vec<Float>,
+}
+
+impl GasRegistry {
+    /// Parses a definition file: one `id=...;name=...;specific_heat=...;` line per gas,
+    /// blank lines and `#` comments skipped. Indices follow line order.
+    pub fn parse(definitions: &str) -> Result<GasRegistry> {
+        let mut registry = GasRegistry { gases: Vec::new(), by_id: HashMap::new(), specific_heats: Vec::new() };
+        for line in definitions.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let gas = parse_gas_line(line)?;
+            if registry.by_id.contains_key(&gas.id) {
+                return Err(Error::InvalidGasDefinition(format!("duplicate gas id {}", gas.id)));
+            }
+            registry.by_id.insert(gas.id.clone(), registry.gases.len());
+            registry.gases.push(gas);
+        }
+        for id in REQUIRED_GASES {
+            if !registry.by_id.contains_key(*id) {
+                return Err(Error::InvalidGasDefinition(format!("missing gas {}, which reactions need", id)));
+            }
+        }
+        // the binary format stores gas indices as u16
+        if registry.gases.len() > u16::MAX as usize {
+            return Err(Error::InvalidGasDefinition("too many gases".to_string()));
+        }
+        registry.specific_heats = registry.gases.iter().map(|gas| gas.specific_heat).collect();
+        registry.specific_heats.resize(padded_len(registry.gases.len()), float(0.0));
+        Ok(registry)
+    }
+    pub fn len(&self) -> usize {
+        self.gases.len()
+    }
+    /// How long a mixture's gas vector is: the gas count padded to whole lanes.
+    pub fn padded_len(&self) -> usize {
+        self.specific_heats.len()
+    }
+    pub fn specific_heats(&self) -> &[Float] {
+        &self.specific_heats
+    }
+    pub fn get(&self, index: usize) -> &GasDatum {
+        &self.gases[index]
+    }
+    pub fn iter(&self) -> ::std::slice::Iter<'_, GasDatum> {
+        self.gases.iter()
+    }
+    pub fn index_of(&self, id: &str) -> Option<usize> {
+        self.by_id.get(id).cloned()
+    }
+}
+
+fn parse_gas_line(line: &str) -> Result<GasDatum> {
+    let invalid = |reason: String| Error::InvalidGasDefinition(format!("{} in \"{}\"", reason, line));
+    let mut fields: BTreeMap<String, String> = BTreeMap::new();
+    for entry in line.split(';') {
+        if entry.trim().is_empty() {
+            continue;
+        }
+        let mut entry_split = entry.splitn(2, '=');
+        match (entry_split.next(), entry_split.next()) {
+            (Some(key), Some(value)) => {
+                fields.insert(key.trim().to_string(), value.trim().to_string());
+            }
+            _ => return Err(invalid(format!("malformed entry {}", entry))),
+        }
+    }
+    let id = fields.remove("id").ok_or_else(|| invalid("no id".to_string()))?;
+    if id.is_empty() || id == "TEMP" || id.contains(|c: char| c.is_whitespace() || c == '=' || c == '$') {
+        return Err(invalid(format!("bad gas id {}", id)));
     }
+    let mut number = |key: &str, default: Option<Float>| -> Result<Float> {
+        match fields.remove(key) {
+            Some(value) => match value.parse::<Float>() {
+                Ok(number) if number.is_finite() => Ok(number),
+                _ => Err(invalid(format!("{} is not a number", key))),
+            },
+            None => default.ok_or_else(|| invalid(format!("no {}", key))),
+        }
+    };
+    let specific_heat = number("specific_heat", None)?;
+    let fusion_power = number("fusion_power", Some(float(0.0)))?;
+    let rarity = number("rarity", Some(float(0.0)))?;
+    if specific_heat <= 0.0 {
+        return Err(invalid("specific_heat must be positive".to_string()));
+    }
+    let name = fields.remove("name").unwrap_or_else(|| id.clone());
+    Ok(GasDatum { id, name, specific_heat, fusion_power, rarity, metadata: fields })
+}
+
+static GASES: LazyLock<RwLock<Arc<GasRegistry>>> = LazyLock::new(|| {
+    RwLock::new(Arc::new(GasRegistry::parse(DEFAULT_GASES).expect("default gas definitions are invalid")))
+});
+
+/// The current gas definitions. Hold on to the Arc rather than calling this per gas.
+pub fn gases() -> Arc<GasRegistry> {
+    GASES.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+/// Swaps in new definitions. Mixtures are sized from the registry when they're made,
+/// so the caller has to make sure none are still around.
+pub fn set_gases(registry: GasRegistry) {
+    *GASES.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(registry);
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+// bumped by set_gases so each thread's copy knows when it's out of date
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static LOCAL_GASES: RefCell<Option<(usize, Arc<GasRegistry>)>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` against the current definitions through this thread's copy, which
+/// skips the lock and the Arc's reference count. For the per-mixture hot paths;
+/// `gases()` is fine everywhere else.
+pub fn with_gases<T, F: FnOnce(&GasRegistry) -> T>(f: F) -> T {
+    let generation = GENERATION.load(Ordering::SeqCst);
+    LOCAL_GASES.with(|cell| {
+        // while an outer call is still using the copy it's left alone, so nested calls agree
+        if let Ok(mut local) = cell.try_borrow_mut() {
+            if local.as_ref().map(|&(seen, _)| seen != generation).unwrap_or(true) {
+                *local = Some((generation, gases()));
+            }
+        }
+        let local = cell.borrow();
+        f(&local.as_ref().expect("gas registry copy wasn't made").1)
+    })
+}
+
+/// Index of a gas every registry is guaranteed to have; see `REQUIRED_GASES`.
+pub fn gas_index(id: &str) -> usize {
+    with_gases(|gases| gases.index_of(id)).unwrap_or_else(|| panic!("required gas {} is not registered", id))
 }
 
 #[cfg(test)]
@@ -17,133 +169,22 @@ mod tests {
 
     #[test]
     fn test_heat_cap() {
-        assert_eq!(OXYGEN.heat_capacity(100.0),2_000.0);
+        let gases = gases();
+        let mut amounts = vec![float(0.0); gases.padded_len()];
+        amounts[gases.index_of("o2").unwrap()] = float(100.0);
+        assert_eq!(::atmos::lanes::dot(&amounts, gases.specific_heats()),2_000.0);
+    }
+    #[test]
+    fn test_definitions() {
+        let extra = format!("{}\nid=freon;name=Freon;specific_heat=600;colour=#6cf;\n", DEFAULT_GASES);
+        let registry = GasRegistry::parse(&extra).unwrap();
+        assert_eq!(registry.len(), 14);
+        let freon = registry.get(registry.index_of("freon").unwrap());
+        assert_eq!(freon.fusion_power, 0.0);
+        assert_eq!(freon.metadata["colour"], "#6cf");
+        assert!(GasRegistry::parse(&format!("{}\nid=o2;specific_heat=20;", DEFAULT_GASES)).is_err());
+        assert!(GasRegistry::parse(&format!("{}\nid=freon;", DEFAULT_GASES)).is_err());
+        assert!(GasRegistry::parse(&format!("{}\nid=freon;specific_heat=lots;", DEFAULT_GASES)).is_err());
+        assert!(GasRegistry::parse("id=o2;specific_heat=20;").is_err());
     }
 }
-
-pub const OXYGEN : GasDatum = GasDatum {
-    id: "o2",
-    name: "Oxygen",
-    specific_heat: 20.0,
-    fusion_power: 0.0,
-};
-
-pub const NITROGEN : GasDatum = GasDatum {
-    id: "n2",
-    name: "Nitrogen",
-    specific_heat: 20.0,
-    fusion_power: 0.0,
-};
-
-pub const CARBON_DIOXIDE : GasDatum = GasDatum {
-    id: "co2",
-    name: "Carbon Dioxide",
-    specific_heat: 30.0,
-    fusion_power: 3.0,
-};
-
-pub const PLASMA : GasDatum = GasDatum {
-    id: "plasma",
-    name: "Plasma",
-    specific_heat: 200.0,
-    fusion_power: 0.0,
-};
-
-pub const WATER_VAPOR : GasDatum = GasDatum {
-    id: "water_vapor",
-    name: "Water Vapor",
-    specific_heat: 40.0,
-    fusion_power: 8.0,
-};
-
-pub const HYPERNOBLIUM : GasDatum = GasDatum {
-    id: "nob",
-    name: "Hyper-noblium",
-    specific_heat: 2000.0,
-    fusion_power: 0.0,
-};
-
-pub const NITROUS_OXIDE : GasDatum = GasDatum {
-    id: "n2o",
-    name: "Nitrous Oxide",
-    specific_heat: 40.0,
-    fusion_power: 0.0,
-};
-
-pub const NITRYL : GasDatum = GasDatum {
-    id: "no2",
-    name: "Nitryl",
-    specific_heat: 20.0,
-    fusion_power: 15.0,
-};
-
-pub const TRITIUM : GasDatum = GasDatum {
-    id: "tritium",
-    name: "Tritium",
-    specific_heat: 10.0,
-    fusion_power: 1.0,
-};
-
-pub const BZ : GasDatum = GasDatum {
-    id: "bz",
-    name: "BZ",
-    specific_heat: 20.0,
-    fusion_power: 8.0,
-};
-
-pub const STIMULUM : GasDatum = GasDatum {
-    id: "stim",
-    name: "Stimulum",
-    specific_heat: 5.0,
-    fusion_power: 7.0,
-};
-
-pub const PLUOXIUM : GasDatum = GasDatum {
-    id: "pluox",
-    name: "Pluoxium",
-    specific_heat: 80.0,
-    fusion_power: 10.0,
-};
-
-pub const MIASMA : GasDatum = GasDatum {
-    id: "miasma",
-    name: "Miasma",
-    specific_heat: 0.00001,
-    fusion_power: 50.0,
-};
-
-// this list is hardcoded sorted by rarity, greatest to smallest, so we don't need to store rarity
-pub const GASES: &'static [GasDatum] = &[ 
-    NITROGEN, // 1000
-    OXYGEN, // 900
-    PLASMA, // 800
-    CARBON_DIOXIDE, // 700
-    NITROUS_OXIDE,  // 600
-    WATER_VAPOR, // 500
-    BZ, // 400
-    TRITIUM, // 300
-    MIASMA, // 250
-    PLUOXIUM, // 200
-    NITRYL, // 100
-    HYPERNOBLIUM, // 50
-    STIMULUM // 1
-    ];
-
-use super::phf::phf_map;
-
-pub const GASES_BY_ID: phf::Map<&'static str,usize> = phf_map! {
-    "n2" => 0,
-    "o2" => 1,
-    "plasma" => 2,
-    "co2" => 3,
-    "n2o" => 4,
-    "water_vapor" => 5,
-    "bz" => 6,
-    "tritium" => 7,
-    "miasma" => 8,
-    "pluox" => 9,
-    "no2" => 10,
-    "nob" => 11,
-    "stim" => 12
-};
-
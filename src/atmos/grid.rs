@@ -0,0 +1,594 @@
+use std::cell::RefCell;
+use std::cmp::max;
+use std::collections::BTreeSet;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+
+use atmos::excited_group::ExcitedGroups;
+use atmos::gases::with_gases;
+use atmos::lanes;
+use atmos::mix::AtmosMixture;
+use atmos::monstermos::{equalize_pressure, PressureDifference};
+use atmos::pool;
+
+use atmos::constants::*;
+
+use error::{Error, Result};
+
+// same values as BYOND's direction flags, so DM can hand its own bitfields over
+pub const NORTH: u8 = 1;
+pub const SOUTH: u8 = 2;
+pub const EAST: u8 = 4;
+pub const WEST: u8 = 8;
+pub const CARDINALS: [u8; 4] = [NORTH, SOUTH, EAST, WEST];
+
+pub fn reverse_dir(dir: u8) -> u8 {
+    match dir {
+        NORTH => SOUTH,
+        SOUTH => NORTH,
+        EAST => WEST,
+        WEST => EAST,
+        _ => 0,
+    }
+}
+
+pub fn space_mixture() -> AtmosMixture {
+    AtmosMixture {
+        temperature: float(CMB_TEMP),
+        ..Default::default()
+    }
+}
+
+#[derive(Clone)]
+pub struct Turf {
+    pub mix: AtmosMixture,
+    /// Directions air may flow out of this turf, as BYOND direction flags.
+    pub atmos_adjacency: u8,
+    /// Walls and the like; never shares and never gets shared with.
+    pub blocked: bool,
+    /// Shares like any other turf, but its mixture is reset to hard vacuum afterwards.
+    pub space: bool,
+    pub excited_group: Option<u32>,
+}
+
+impl Default for Turf {
+    fn default() -> Turf {
+        Turf {
+            mix: Default::default(),
+            atmos_adjacency: NORTH | SOUTH | EAST | WEST,
+            blocked: false,
+            space: false,
+            excited_group: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Grid {
+    pub width: usize,
+    pub height: usize,
+    pub turfs: Vec<Turf>,
+    pub excited_groups: ExcitedGroups,
+    /// Run monstermos equalization over active turfs before process_cell.
+    pub equalize: bool,
+    /// Pressure differences from the last tick's equalization, for DM to throw things with.
+    pub pressure_differences: Vec<PressureDifference>,
+    /// Reaction results from the last tick, for every turf that reacted.
+    pub reactions: Vec<(usize, Vec<Vec<String>>)>,
+    active: BTreeSet<usize>,
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize) -> Grid {
+        Grid {
+            width,
+            height,
+            turfs: vec![Default::default(); width * height],
+            excited_groups: Default::default(),
+            equalize: false,
+            pressure_differences: Vec::new(),
+            reactions: Vec::new(),
+            active: BTreeSet::new(),
+        }
+    }
+    pub fn index(&self, x: usize, y: usize) -> Result<usize> {
+        if x < self.width && y < self.height {
+            Ok(y * self.width + x)
+        } else {
+            Err(Error::OutOfBounds(x, y))
+        }
+    }
+    pub fn coords(&self, index: usize) -> (usize, usize) {
+        (index % self.width, index / self.width)
+    }
+    pub fn step(&self, index: usize, dir: u8) -> Option<usize> {
+        let (x, y) = self.coords(index);
+        match dir {
+            NORTH if y + 1 < self.height => Some(index + self.width),
+            SOUTH if y > 0 => Some(index - self.width),
+            EAST if x + 1 < self.width => Some(index + 1),
+            WEST if x > 0 => Some(index - 1),
+            _ => None,
+        }
+    }
+    /// Turfs air can currently flow to from `index`, along with the direction
+    /// they're in: both sides have to allow it and neither side can be blocked.
+    pub fn adjacent_dirs(&self, index: usize) -> Vec<(u8, usize)> {
+        let turf = &self.turfs[index];
+        if turf.blocked {
+            return Vec::new();
+        }
+        CARDINALS
+            .iter()
+            .filter(|&&dir| turf.atmos_adjacency & dir != 0)
+            .filter_map(|&dir| self.step(index, dir).map(|other| (dir, other)))
+            .filter(|&(dir, other)| {
+                let other_turf = &self.turfs[other];
+                !other_turf.blocked && other_turf.atmos_adjacency & reverse_dir(dir) != 0
+            })
+            .collect()
+    }
+    pub fn neighbours(&self, index: usize) -> Vec<usize> {
+        self.adjacent_dirs(index).into_iter().map(|(_, other)| other).collect()
+    }
+    pub fn activate(&mut self, index: usize) {
+        let turf = &self.turfs[index];
+        if !turf.blocked && !turf.space {
+            self.active.insert(index);
+        }
+    }
+    pub fn active_turfs(&self) -> Vec<usize> {
+        self.active.iter().cloned().collect()
+    }
+    fn activate_around(&mut self, index: usize) {
+        self.activate(index);
+        for &dir in CARDINALS.iter() {
+            if let Some(other) = self.step(index, dir) {
+                self.activate(other);
+            }
+        }
+    }
+    pub fn set_mixture(&mut self, x: usize, y: usize, mix: AtmosMixture) -> Result<()> {
+        let index = self.index(x, y)?;
+        self.turfs[index].mix = mix;
+        self.activate(index);
+        Ok(())
+    }
+    pub fn set_blocked(&mut self, x: usize, y: usize, blocked: bool) -> Result<()> {
+        let index = self.index(x, y)?;
+        self.turfs[index].blocked = blocked;
+        if blocked {
+            self.active.remove(&index);
+            self.excited_groups.remove_turf(&mut self.turfs, index);
+        }
+        self.activate_around(index);
+        Ok(())
+    }
+    pub fn set_space(&mut self, x: usize, y: usize, space: bool) -> Result<()> {
+        let index = self.index(x, y)?;
+        self.turfs[index].space = space;
+        if space {
+            self.turfs[index].mix = space_mixture();
+            self.active.remove(&index);
+            self.excited_groups.remove_turf(&mut self.turfs, index);
+        }
+        self.activate_around(index);
+        Ok(())
+    }
+    pub fn set_adjacency(&mut self, x: usize, y: usize, atmos_adjacency: u8) -> Result<()> {
+        let index = self.index(x, y)?;
+        self.turfs[index].atmos_adjacency = atmos_adjacency;
+        self.activate_around(index);
+        Ok(())
+    }
+    /// Runs process_cell over every active turf. Each adjacent pair shares once
+    /// per tick, and turfs that traded anything get linked into an excited group.
+    /// Then every turf that was active at some point in the tick reacts, and one
+    /// that reacted stays active, so a fire keeps burning after sharing settles.
+    /// Other turfs outside a group that neither moved gas nor heat go to sleep
+    /// until something next to them changes; grouped turfs sleep when their group
+    /// is dismantled. Returns the indices of every turf that changed.
+    ///
+    /// Pairs share from the mixtures as they were at the start of the tick, so the
+    /// sharing itself is split into row stripes and run on the thread pool when
+    /// there is one. The result is the same whatever the thread count.
+    pub fn tick(&mut self) -> Vec<usize> {
+        let mut changed = BTreeSet::new();
+        self.pressure_differences.clear();
+        self.reactions.clear();
+        if self.equalize {
+            changed.extend(self.equalize_active());
+        }
+        let active = self.active_turfs();
+        let mut pairs = Vec::new();
+        for &index in &active {
+            let neighbours = self.neighbours(index);
+            for &neighbour in &neighbours {
+                // already shared with as the earlier active turf of the pair
+                if neighbour < index && self.active.contains(&neighbour) {
+                    continue;
+                }
+                let adjacent_turfs = max(neighbours.len(), self.neighbours(neighbour).len()) as i16;
+                pairs.push((index, neighbour, adjacent_turfs));
+            }
+        }
+        let outcomes = self.share_pairs(&pairs);
+        let mut still_active = BTreeSet::new();
+        for (&(index, neighbour, _), outcome) in pairs.iter().zip(outcomes.iter()) {
+            let other_space = self.turfs[neighbour].space;
+            if other_space {
+                self.turfs[neighbour].mix = space_mixture();
+            }
+            if outcome.changed() {
+                still_active.insert(index);
+                changed.insert(index);
+                if !other_space {
+                    changed.insert(neighbour);
+                    self.active.insert(neighbour);
+                    let group = self.excited_groups.link(&mut self.turfs, index, neighbour);
+                    if outcome.last_share > MINIMUM_AIR_TO_SUSPEND {
+                        self.excited_groups.reset_cooldowns(group);
+                    }
+                }
+            }
+        }
+        let mut reacting: BTreeSet<usize> = self.active.iter().cloned().collect();
+        for &index in &active {
+            if !still_active.contains(&index) && self.turfs[index].excited_group.is_none() {
+                self.active.remove(&index);
+            }
+        }
+        if !self.excited_groups.is_empty() {
+            let (broken_down, dismantled) = self.excited_groups.process(&mut self.turfs);
+            changed.extend(broken_down);
+            for index in dismantled {
+                self.active.remove(&index);
+            }
+        }
+        reacting.extend(active);
+        for index in reacting {
+            let results = self.turfs[index].mix.react();
+            if results.iter().any(|result| !result.is_empty()) {
+                self.activate(index);
+                changed.insert(index);
+                self.reactions.push((index, results));
+            }
+        }
+        changed.into_iter().collect()
+    }
+    /// Shares every `(turf, neighbour, adjacent_turfs)` pair from the turfs' current
+    /// mixtures and writes the summed results back. Returns each pair's outcome.
+    ///
+    /// The touched mixtures are moved out of their turfs for the pairs to read, then
+    /// each turf gets the moles its pairs moved added on, and its temperature is
+    /// worked out from its thermal energy plus what its pairs gave it.
+    fn share_pairs(&mut self, pairs: &[(usize, usize, i16)]) -> Arc<Vec<ShareOutcome>> {
+        let mut touched: Vec<usize> = pairs.iter().flat_map(|&(index, neighbour, _)| vec![index, neighbour]).collect();
+        touched.sort();
+        touched.dedup();
+        let slot = |index: usize| touched.binary_search(&index).unwrap();
+        let archive: Arc<Vec<AtmosMixture>> = Arc::new(touched.iter().map(|&index| take_mixture(&mut self.turfs[index].mix)).collect());
+        let slot_pairs: Arc<Vec<SharePair>> = Arc::new(
+            pairs
+                .iter()
+                .map(|&(index, neighbour, adjacent_turfs)| SharePair {
+                    first: slot(index),
+                    second: slot(neighbour),
+                    adjacent_turfs,
+                })
+                .collect(),
+        );
+        let pair_rows: Vec<usize> = pairs.iter().map(|&(index, _, _)| index / self.width).collect();
+        let outcomes = {
+            let (archive, slot_pairs) = (archive.clone(), slot_pairs.clone());
+            Arc::new(run_striped(&pair_rows, move |number| share_pair(&archive, slot_pairs[number])))
+        };
+        // the pool's done with its copy once run_striped returns
+        let mut mixes = Arc::try_unwrap(archive).unwrap_or_else(|archive| (*archive).clone());
+        let gas_count = mixes.first().map_or(0, |mix| mix.gases.len());
+        let mut moved = vec![float(0.0); mixes.len() * gas_count];
+        let mut energy = vec![float(0.0); mixes.len()];
+        let mut changed = vec![false; mixes.len()];
+        for (pair, outcome) in slot_pairs.iter().zip(outcomes.iter()) {
+            let divisor = float((pair.adjacent_turfs + 1) as Constant);
+            let (first, second) = (&mixes[pair.first].gases, &mixes[pair.second].gases);
+            for (gas, (&first_amount, &second_amount)) in first.iter().zip(second.iter()).enumerate() {
+                let amount = lanes::moving(first_amount, second_amount, divisor);
+                moved[pair.first * gas_count + gas] -= amount;
+                moved[pair.second * gas_count + gas] += amount;
+            }
+            energy[pair.first] += outcome.energy;
+            energy[pair.second] -= outcome.energy;
+            changed[pair.first] |= outcome.changed();
+            changed[pair.second] |= outcome.changed();
+        }
+        for (slot, mix) in mixes.iter_mut().enumerate() {
+            if !changed[slot] {
+                continue;
+            }
+            let thermal_energy = mix.thermal_energy() + energy[slot];
+            lanes::add_assign(&mut mix.gases, &moved[slot * gas_count..(slot + 1) * gas_count]);
+            let heat_capacity = mix.heat_capacity();
+            if heat_capacity > 0.0 {
+                mix.temperature = thermal_energy / heat_capacity;
+            }
+        }
+        for (&index, mix) in touched.iter().zip(mixes) {
+            self.turfs[index].mix = mix;
+        }
+        outcomes
+    }
+}
+
+// leaves an empty mixture behind without allocating one
+fn take_mixture(mix: &mut AtmosMixture) -> AtmosMixture {
+    mem::replace(mix, AtmosMixture { gases: Vec::new(), temperature: float(0.0), volume: float(0.0) })
+}
+
+/// Two neighbours sharing this tick, as indices into the archived mixtures. Both
+/// share as if they had the larger of their neighbour counts, so a turf never
+/// gives away more than it has however many pairs it's in.
+#[derive(Clone, Copy)]
+struct SharePair {
+    first: usize,
+    second: usize,
+    adjacent_turfs: i16,
+}
+
+/// What sharing a pair from their archived mixtures did: the thermal energy the
+/// first turf gained, which the second lost, and how many moles moved. The moles
+/// themselves are worked out again from the archived mixtures when they're added up.
+struct ShareOutcome {
+    energy: Float,
+    last_share: Float,
+}
+
+impl ShareOutcome {
+    fn changed(&self) -> bool {
+        self.last_share > 0.0 || self.energy != 0.0
+    }
+}
+
+/// Shares a pair the way `AtmosMixture::share` does, except that the gas that moves
+/// takes its temperature along, so what one turf gains the other loses.
+fn share_pair(archive: &[AtmosMixture], pair: SharePair) -> ShareOutcome {
+    let (first, second) = (&archive[pair.first], &archive[pair.second]);
+    let divisor = float((pair.adjacent_turfs + 1) as Constant);
+    let (mut moved, mut outwards, mut inwards) = (float(0.0), float(0.0), float(0.0));
+    with_gases(|gases| {
+        for ((&first_amount, &second_amount), &specific_heat) in first.gases.iter().zip(second.gases.iter()).zip(gases.specific_heats()) {
+            let amount = lanes::moving(first_amount, second_amount, divisor);
+            moved += amount.abs();
+            if amount > 0.0 {
+                outwards += amount * specific_heat;
+            } else {
+                inwards -= amount * specific_heat;
+            }
+        }
+    });
+    let mut energy = inwards * second.temperature - outwards * first.temperature;
+    let first_heat_capacity = first.heat_capacity() + inwards - outwards;
+    let second_heat_capacity = second.heat_capacity() + outwards - inwards;
+    if first_heat_capacity > 0.0 && second_heat_capacity > 0.0 {
+        let temperature_delta = (first.thermal_energy() + energy) / first_heat_capacity - (second.thermal_energy() - energy) / second_heat_capacity;
+        if temperature_delta.abs() >= 0.1 {
+            energy -= float(OPEN_HEAT_TRANSFER_COEFFICIENT) * temperature_delta * (first_heat_capacity * second_heat_capacity / (first_heat_capacity + second_heat_capacity));
+        }
+    }
+    ShareOutcome {
+        energy,
+        last_share: if moved > 0.1 { moved } else { float(0.0) },
+    }
+}
+
+// below this many items a tick isn't worth handing to the pool
+const PARALLEL_MINIMUM : usize = 256;
+
+/// Runs `f` over `0..rows.len()` and returns the results in order. `rows` is the
+/// map row of each item, ascending; with a thread pool the items are cut into
+/// stripes of whole rows and the stripes run in parallel. `f` only ever sees its
+/// own item, so how they get cut up never changes the result.
+fn run_striped<T, F>(rows: &[usize], f: F) -> Vec<T>
+where
+    T: Send + 'static,
+    F: Fn(usize) -> T + Send + Sync + 'static,
+{
+    let pool = match pool::pool() {
+        Some(ref pool) if rows.len() >= PARALLEL_MINIMUM => pool.clone(),
+        _ => return (0..rows.len()).map(f).collect(),
+    };
+    let stripes = stripes(rows, pool.threads() * 4);
+    pool.map(stripes.len(), move |stripe| stripes[stripe].clone().map(&f).collect::<Vec<T>>())
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Splits items sorted by row into about `count` runs that never split a row.
+fn stripes(rows: &[usize], count: usize) -> Vec<Range<usize>> {
+    let rows_per_stripe = match rows.last() {
+        Some(&last_row) => last_row / count.max(1) + 1,
+        None => return Vec::new(),
+    };
+    let mut stripes = Vec::new();
+    let mut start = 0;
+    for end in 1..=rows.len() {
+        if end == rows.len() || rows[end] / rows_per_stripe != rows[start] / rows_per_stripe {
+            stripes.push(start..end);
+            start = end;
+        }
+    }
+    stripes
+}
+
+impl Grid {
+    /// Whether any neighbour differs from this turf by enough moles to be worth equalizing.
+    fn needs_equalizing(&self, index: usize) -> bool {
+        let moles = self.turfs[index].mix.total_moles();
+        self.neighbours(index)
+            .iter()
+            .any(|&other| (self.turfs[other].mix.total_moles() - moles).abs() > MINIMUM_MOLES_DELTA_TO_MOVE)
+    }
+    /// Runs monstermos from each active turf whose region hasn't been equalized yet
+    /// this tick. Returns every turf in a region that got equalized or vented.
+    fn equalize_active(&mut self) -> BTreeSet<usize> {
+        let mut done = BTreeSet::new();
+        for index in self.active_turfs() {
+            if done.contains(&index) || !self.needs_equalizing(index) {
+                continue;
+            }
+            let result = equalize_pressure(self, index);
+            for &turf in &result.region {
+                self.activate(turf);
+            }
+            done.extend(result.region);
+            self.pressure_differences.extend(result.pressure_differences);
+        }
+        done
+    }
+}
+
+thread_local! {
+    static GRID: RefCell<Option<Grid>> = const { RefCell::new(None) };
+}
+
+pub fn create_grid(width: usize, height: usize) {
+    GRID.with(|cell| cell.replace(Some(Grid::new(width, height))));
+}
+
+pub fn has_grid() -> bool {
+    GRID.with(|cell| cell.borrow().is_some())
+}
+
+/// Runs `f` against the grid backing the BYOND exports, if one has been created.
+pub fn with_grid<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce(&mut Grid) -> Result<T>,
+{
+    GRID.with(|cell| match *cell.borrow_mut() {
+        Some(ref mut grid) => f(grid),
+        None => Err(Error::NoGrid),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atmos::gases::gas_index;
+
+    #[test]
+    fn test_grid_tick() {
+        let mut grid = Grid::new(3, 1);
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap();
+        grid.set_mixture(0, 0, mix).unwrap();
+        assert_eq!(grid.tick(), vec![0, 1]);
+        assert_eq!(grid.active_turfs(), vec![0, 1]);
+        assert!(grid.turfs[2].mix.total_moles() == 0.0);
+        grid.tick();
+        assert!(grid.turfs[2].mix.total_moles() > 0.0);
+        grid.set_blocked(1, 0, true).unwrap();
+        assert!(grid.neighbours(0).is_empty());
+    }
+    #[test]
+    fn test_share_conserves_energy() {
+        let mut grid = Grid::new(3, 3);
+        for (x, y, air) in [(0, 0, "TEMP=1000;o2=300;"), (1, 1, "TEMP=150;n2=40;"), (2, 1, "TEMP=293.15;co2=90;n2=10;")] {
+            let mut mix : AtmosMixture = Default::default();
+            mix.parse_gas_string(air).unwrap();
+            grid.set_mixture(x, y, mix).unwrap();
+        }
+        let energy = |grid: &Grid| grid.turfs.iter().map(|turf| turf.mix.thermal_energy()).fold(float(0.0), |x, y| x + y);
+        let before = energy(&grid);
+        for _ in 0..5 {
+            grid.tick();
+        }
+        assert!((energy(&grid) - before).abs() < before * 1e-3);
+        // the turf the hot gas spreads into ends up hot, rather than at its old temperature
+        assert!(grid.turfs[1].mix.temperature > 500.0);
+    }
+    #[test]
+    fn test_grid_settles() {
+        let mut grid = Grid::new(4, 4);
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap();
+        grid.set_mixture(0, 0, mix).unwrap();
+        let mut ticks = 0;
+        while !grid.active_turfs().is_empty() {
+            grid.tick();
+            ticks += 1;
+            assert!(ticks < 200);
+        }
+        assert!(grid.excited_groups.is_empty());
+        let moles : Vec<Float> = grid.turfs.iter().map(|turf| turf.mix.total_moles()).collect();
+        assert!(moles.iter().all(|amount| (amount - 104.0 / 16.0).abs() < 1.0));
+    }
+    #[test]
+    fn test_equalizing_tick() {
+        let mut grid = Grid::new(4, 4);
+        grid.equalize = true;
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=293.15;o2=320;n2=1280;").unwrap();
+        grid.set_mixture(0, 0, mix).unwrap();
+        assert_eq!(grid.tick().len(), 16);
+        assert!(grid.turfs.iter().all(|turf| (turf.mix.total_moles() - 100.0).abs() < 0.1));
+        assert!(!grid.pressure_differences.is_empty());
+    }
+    #[test]
+    fn test_sealed_fire_keeps_burning() {
+        let mut grid = Grid::new(1, 1);
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=1000;plasma=50;o2=100;").unwrap();
+        grid.set_mixture(0, 0, mix).unwrap();
+        let plasma = gas_index("plasma");
+        let mut last_plasma = grid.turfs[0].mix.gases[plasma];
+        for _ in 0..3 {
+            // nothing to share with, so only the reaction keeps it going
+            assert_eq!(grid.tick(), vec![0]);
+            assert_eq!(grid.reactions.len(), 1);
+            assert_eq!(grid.active_turfs(), vec![0]);
+            assert!(grid.turfs[0].mix.gases[plasma] < last_plasma);
+            last_plasma = grid.turfs[0].mix.gases[plasma];
+        }
+    }
+    #[test]
+    fn test_space_drains() {
+        let mut grid = Grid::new(2, 1);
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap();
+        grid.set_mixture(0, 0, mix).unwrap();
+        grid.set_space(1, 0, true).unwrap();
+        let initial_moles = grid.turfs[0].mix.total_moles();
+        grid.tick();
+        assert!(grid.turfs[0].mix.total_moles() < initial_moles);
+        assert_eq!(grid.turfs[1].mix.total_moles(), 0.0);
+        grid.set_adjacency(0, 0, NORTH).unwrap();
+        assert!(grid.neighbours(0).is_empty());
+    }
+    #[test]
+    fn test_threads_match() {
+        let mut grid = Grid::new(32, 32);
+        for (number, &(x, y)) in [(3, 4), (20, 7), (9, 28), (30, 30)].iter().enumerate() {
+            let mut mix : AtmosMixture = Default::default();
+            mix.parse_gas_string(&format!("TEMP={};o2={};plasma={};", 200 + number * 150, 500 + number * 100, number * 40)).unwrap();
+            grid.set_mixture(x, y, mix).unwrap();
+        }
+        for y in 0..20 {
+            grid.set_blocked(15, y, true).unwrap();
+        }
+        grid.set_space(31, 0, true).unwrap();
+        let mut threaded = grid.clone();
+        pool::set_threads(1);
+        let single : Vec<Vec<usize>> = (0..30).map(|_| grid.tick()).collect();
+        pool::set_threads(4);
+        let parallel : Vec<Vec<usize>> = (0..30).map(|_| threaded.tick()).collect();
+        pool::set_threads(1);
+        assert_eq!(single, parallel);
+        for (turf, other) in grid.turfs.iter().zip(threaded.turfs.iter()) {
+            assert_eq!(turf.mix.gases, other.mix.gases);
+            assert_eq!(turf.mix.temperature, other.mix.temperature);
+        }
+        assert_eq!(stripes(&[0, 0, 1, 2, 2, 5, 9], 3), vec![0..5, 5..6, 6..7]);
+    }
+}
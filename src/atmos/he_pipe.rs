@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use atmos::constants::*;
+use atmos::mix::AtmosMixture;
+use atmos::pipenet::PipeNets;
+use atmos::registry::{Handle, MixtureRegistry};
+
+use error::{Error, Result};
+
+/// One heat-exchange pipe segment and what's around it: a turf mixture, or space if
+/// there's none.
+pub struct HeSegment {
+    pub pipe: Handle,
+    /// In square metres.
+    pub surface_area: Float,
+    pub turf: Option<Handle>,
+}
+
+/// What one pipe network's HE segments did in one tick.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HeExchange {
+    pub temperature: Float,
+    /// Joules radiated away to space.
+    pub radiated: Float,
+    /// Joules conducted into turfs, negative if the turfs heated the pipes.
+    pub conducted: Float,
+}
+
+/// Parses `pipe:surface_area:turf;...`, with `space` for a segment with no turf.
+/// Surface areas have to be finite and not negative.
+pub fn parse_segments(segment_string: &str) -> Result<Vec<HeSegment>> {
+    let mut segments = Vec::new();
+    for entry in segment_string.split(';') {
+        if entry.trim().is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = entry.split(':').collect();
+        if parts.len() != 3 {
+            return Err(Error::MalformedEntry(entry.to_string()));
+        }
+        let pipe = parts[0].parse()?;
+        let surface_area: Float = parts[1].trim().parse().map_err(|_| Error::InvalidNumber(parts[1].to_string()))?;
+        if surface_area < 0.0 || !surface_area.is_finite() {
+            return Err(Error::InvalidNumber(parts[1].to_string()));
+        }
+        let turf = match parts[2].trim() {
+            "space" => None,
+            turf => Some(turf.parse()?),
+        };
+        segments.push(HeSegment { pipe, surface_area, turf });
+    }
+    Ok(segments)
+}
+
+/// Radiates heat from `air` through `surface_area` of pipe wall to space, which
+/// goes as T^4. It never cools the gas below the background temperature. Returns
+/// the joules lost.
+pub fn radiate_to_space(air: &mut AtmosMixture, surface_area: Float) -> Float {
+    let heat_capacity = air.heat_capacity();
+    if heat_capacity <= 0.0 || surface_area <= 0.0 || air.temperature <= float(CMB_TEMP) {
+        return float(0.0);
+    }
+    // sigma T^4 squared from its root keeps it in range of a fixed-point Float
+    let root = STEFAN_BOLTZMANN_CONSTANT.sqrt();
+    let emitted = air.temperature * air.temperature * root;
+    let absorbed = float(CMB_TEMP * CMB_TEMP) * root;
+    let radiated = ((emitted * emitted - absorbed * absorbed) * surface_area).min((air.temperature - float(CMB_TEMP)) * heat_capacity);
+    air.temperature -= radiated / heat_capacity;
+    radiated
+}
+
+/// Conducts heat between the `share_volume` litres of `air` in one segment and the
+/// turf around it, DM's `temperature_interact`: the turf shares with that part of
+/// the air through `temperature_share_turf`, and the whole network's air gives or
+/// takes the heat. Returns the joules that went into the turf.
+pub fn conduct_to_turf(air: &mut AtmosMixture, share_volume: Float, turf: &mut AtmosMixture, conduction_coefficient: Float) -> Float {
+    let heat_capacity = air.heat_capacity();
+    let turf_heat_capacity = turf.heat_capacity();
+    let partial_heat_capacity = heat_capacity * share_volume / air.volume;
+    let turf_temperature = turf.temperature;
+    if partial_heat_capacity <= 0.0 || turf_heat_capacity <= 0.0 || (air.temperature - turf_temperature).abs() < float(HE_PIPE_MINIMUM_TEMPERATURE_DIFFERENCE) {
+        return float(0.0);
+    }
+    turf.temperature_share_turf(conduction_coefficient, air.temperature, partial_heat_capacity);
+    let heat = (turf.temperature - turf_temperature) * turf_heat_capacity;
+    air.temperature -= heat / heat_capacity;
+    heat
+}
+
+/// Runs every segment for one tick. Segments on the same pipe network share its
+/// air: each conducts with its own turf, then the network radiates through the
+/// total area of its segments in space. Returns one result per network, keyed by
+/// the first of its pipes listed. Nothing happens if any handle is bad or the
+/// conduction coefficient isn't between 0 and 1.
+pub fn exchange(pipenets: &mut PipeNets, mixtures: &mut MixtureRegistry, segments: &[HeSegment], conduction_coefficient: Float) -> Result<Vec<(Handle, HeExchange)>> {
+    if !(0.0..=1.0).contains(&conduction_coefficient) {
+        return Err(Error::InvalidArgument("conduction_coefficient".to_string(), conduction_coefficient.to_string()));
+    }
+    let mut networks: Vec<(Handle, Vec<&HeSegment>)> = Vec::new();
+    let mut network_index: HashMap<Handle, usize> = HashMap::new();
+    for segment in segments {
+        if let Some(turf) = segment.turf {
+            mixtures.get(turf)?;
+        }
+        let key = pipenets.network(segment.pipe)?.pipes[0];
+        let index = *network_index.entry(key).or_insert_with(|| {
+            networks.push((segment.pipe, Vec::new()));
+            networks.len() - 1
+        });
+        networks[index].1.push(segment);
+    }
+    let mut results = Vec::with_capacity(networks.len());
+    for (pipe, network_segments) in networks {
+        let mut space_area = float(0.0);
+        let mut conducted = float(0.0);
+        for segment in network_segments {
+            match segment.turf {
+                Some(turf) => {
+                    let share_volume = pipenets.pipe(segment.pipe)?.volume;
+                    conducted += conduct_to_turf(pipenets.air_mut(segment.pipe)?, share_volume, mixtures.get_mut(turf)?, conduction_coefficient);
+                }
+                None => space_area += segment.surface_area,
+            }
+        }
+        let air = pipenets.air_mut(pipe)?;
+        let radiated = radiate_to_space(air, space_area);
+        results.push((pipe, HeExchange { temperature: air.temperature, radiated, conducted }));
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_radiation() {
+        let mut hot = AtmosMixture::from_gas_string("TEMP=1000;co2=20;").unwrap();
+        let heat_capacity = hot.heat_capacity();
+        let radiated = radiate_to_space(&mut hot, float(2.0));
+        assert!((radiated - 113407.0).abs() < 1.0);
+        assert!((hot.temperature - (1000.0 - radiated / heat_capacity)).abs() < 0.01);
+        let mut warm = AtmosMixture::from_gas_string("TEMP=500;co2=20;").unwrap();
+        assert!((radiate_to_space(&mut warm, float(2.0)) - radiated / 16.0).abs() < 1.0);
+        let mut thin = AtmosMixture::from_gas_string("TEMP=1000;co2=0.01;").unwrap();
+        radiate_to_space(&mut thin, float(100.0));
+        assert!((thin.temperature - CMB_TEMP).abs() < 0.01);
+        let mut cold = AtmosMixture::from_gas_string("TEMP=2;co2=20;").unwrap();
+        assert_eq!(radiate_to_space(&mut cold, float(2.0)), 0.0);
+    }
+    #[test]
+    fn test_exchange() {
+        let mut pipenets = PipeNets::default();
+        let mut mixtures = MixtureRegistry::default();
+        let first = pipenets.add_pipe(float(70.0), &[]).unwrap();
+        let second = pipenets.add_pipe(float(70.0), &[first]).unwrap();
+        let third = pipenets.add_pipe(float(70.0), &[second]).unwrap();
+        let lonely = pipenets.add_pipe(float(70.0), &[]).unwrap();
+        pipenets.air_mut(first).unwrap().parse_gas_string("TEMP=800;co2=40;").unwrap();
+        pipenets.air_mut(lonely).unwrap().parse_gas_string("TEMP=310;n2=10;").unwrap();
+        let turf = mixtures.create(AtmosMixture::from_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap());
+        let initial_energy = pipenets.air(first).unwrap().thermal_energy() + mixtures.get(turf).unwrap().thermal_energy();
+        let segment_string = format!("{}:2:{};{}:2:space;{}:2:space;{}:2:{};", second, turf, third, first, lonely, turf);
+        let segments = parse_segments(&segment_string).unwrap();
+        let results = exchange(&mut pipenets, &mut mixtures, &segments, float(0.4)).unwrap();
+        assert_eq!(results.len(), 2);
+        let (pipe, ref network) = results[0];
+        assert_eq!(pipe, second);
+        assert!(network.conducted > 0.0 && network.radiated > 0.0);
+        assert_eq!(network.temperature, pipenets.air(third).unwrap().temperature);
+        let final_energy = pipenets.air(first).unwrap().thermal_energy() + mixtures.get(turf).unwrap().thermal_energy();
+        assert!((initial_energy - final_energy - network.radiated).abs() < 0.001 * initial_energy);
+        assert!(mixtures.get(turf).unwrap().temperature > 293.15);
+        // too close to the turf's temperature to conduct
+        assert_eq!(results[1].1.conducted, 0.0);
+        assert!(parse_segments("1:2").is_err());
+        assert!(matches!(parse_segments("1:-2:space;"), Err(Error::InvalidNumber(_))));
+        assert!(parse_segments("1:inf:space;").is_err());
+        assert!(exchange(&mut pipenets, &mut mixtures, &parse_segments("999:2:space;").unwrap(), float(0.4)).is_err());
+        assert!(matches!(exchange(&mut pipenets, &mut mixtures, &segments, float(1.5)), Err(Error::InvalidArgument(..))));
+        assert!(exchange(&mut pipenets, &mut mixtures, &segments, float(-0.1)).is_err());
+    }
+}
@@ -0,0 +1,447 @@
+//! Gas vector arithmetic over fixed-width lanes. Mixtures keep their gases padded
+//! to a multiple of `LANES` (the padding is always zero), and the registry keeps a
+//! matching specific heat vector, so these loops work on whole chunks that the
+//! compiler can turn into SIMD on its own. Building with the `simd` feature on
+//! x86_64 swaps in explicit SSE versions; those are f32 only, so an `f64` or
+//! `fixed` build always uses the portable loops.
+//!
+//! Both versions keep one running total per lane, add the lanes up the same way at
+//! the end, and don't use fused multiply-adds, so they give bit-identical results.
+
+use atmos::constants::{float, Float};
+
+pub const LANES: usize = 8;
+
+/// `len` rounded up to a whole number of lanes.
+pub fn padded_len(len: usize) -> usize {
+    len.div_ceil(LANES) * LANES
+}
+
+/// What `share` moved, for working out the new temperatures.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct ShareTotals {
+    pub heat_capacity_self_to_other: Float,
+    pub heat_capacity_other_to_self: Float,
+    pub moved_moles: Float,
+    pub abs_moved_moles: Float,
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", not(any(feature = "f64", feature = "fixed")))))]
+use self::portable as active;
+#[cfg(all(feature = "simd", target_arch = "x86_64", not(any(feature = "f64", feature = "fixed"))))]
+use self::sse as active;
+
+/// Adds up every element.
+pub fn sum(values: &[Float]) -> Float {
+    active::sum(values)
+}
+
+/// Sum of the elementwise products, over the shorter of the two.
+pub fn dot(first: &[Float], second: &[Float]) -> Float {
+    active::dot(first, second)
+}
+
+pub fn add_assign(target: &mut [Float], source: &[Float]) {
+    active::add_assign(target, source)
+}
+
+/// Moves `source[i] / divisor * multiplier` of each element out of `source` and
+/// into `removed`, overwriting what was there.
+pub fn split_off(source: &mut [Float], removed: &mut [Float], divisor: Float, multiplier: Float) {
+    active::split_off(source, removed, divisor, multiplier)
+}
+
+/// How much of one gas `share` moves from `first` to `second`.
+pub fn moving(first: Float, second: Float, divisor: Float) -> Float {
+    let delta = (first - second) / divisor;
+    if delta.abs() > 0.1 { delta } else { float(0.0) }
+}
+
+/// The per-gas part of `AtmosMixture::share`: moves `(first - second) / divisor`
+/// of every gas where that's more than 0.1 moles either way.
+pub fn share(first: &mut [Float], second: &mut [Float], specific_heats: &[Float], divisor: Float) -> ShareTotals {
+    active::share(first, second, specific_heats, divisor)
+}
+
+// pairwise, so it's a couple of shuffles and adds rather than a chain of eight
+fn reduce(totals: &[Float; LANES]) -> Float {
+    let halves = [totals[0] + totals[4], totals[1] + totals[5], totals[2] + totals[6], totals[3] + totals[7]];
+    (halves[0] + halves[2]) + (halves[1] + halves[3])
+}
+
+// with the simd feature on, the rest of the crate only goes through the SSE versions
+#[cfg_attr(all(feature = "simd", target_arch = "x86_64", not(any(feature = "f64", feature = "fixed"))), allow(dead_code))]
+pub mod portable {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    pub fn sum(values: &[Float]) -> Float {
+        let mut totals = [float(0.0); LANES];
+        let chunks = values.chunks_exact(LANES);
+        let rest = chunks.remainder();
+        for chunk in chunks {
+            let chunk: &[Float; LANES] = chunk.try_into().unwrap();
+            totals = std::array::from_fn(|lane| totals[lane] + chunk[lane]);
+        }
+        for (total, value) in totals.iter_mut().zip(rest) {
+            *total += value;
+        }
+        reduce(&totals)
+    }
+    pub fn dot(first: &[Float], second: &[Float]) -> Float {
+        let mut totals = [float(0.0); LANES];
+        let len = first.len().min(second.len());
+        let (first, second) = (&first[..len], &second[..len]);
+        let whole = len - len % LANES;
+        for (first, second) in first[..whole].chunks_exact(LANES).zip(second[..whole].chunks_exact(LANES)) {
+            for (total, (a, b)) in totals.iter_mut().zip(first.iter().zip(second)) {
+                *total += a * b;
+            }
+        }
+        for (total, (a, b)) in totals.iter_mut().zip(first[whole..].iter().zip(&second[whole..])) {
+            *total += a * b;
+        }
+        reduce(&totals)
+    }
+    pub fn add_assign(target: &mut [Float], source: &[Float]) {
+        for (target, source) in target.iter_mut().zip(source) {
+            *target += source;
+        }
+    }
+    pub fn split_off(source: &mut [Float], removed: &mut [Float], divisor: Float, multiplier: Float) {
+        for (amount, removed) in source.iter_mut().zip(removed.iter_mut()) {
+            *removed = (*amount / divisor) * multiplier;
+            *amount -= *removed;
+        }
+    }
+    pub fn share(first: &mut [Float], second: &mut [Float], specific_heats: &[Float], divisor: Float) -> ShareTotals {
+        let mut totals = [[float(0.0); LANES]; 4];
+        share_into(first, second, specific_heats, divisor, &mut totals);
+        share_totals(&totals)
+    }
+    /// The body of `share`, adding into per-lane `[self_to_other, other_to_self,
+    /// moved, abs_moved]` totals so the SSE version can hand it its leftovers.
+    pub fn share_into(first: &mut [Float], second: &mut [Float], specific_heats: &[Float], divisor: Float, totals: &mut [[Float; LANES]; 4]) {
+        let len = first.len().min(second.len()).min(specific_heats.len());
+        let whole = len - len % LANES;
+        let chunks = first[..whole].chunks_exact_mut(LANES).zip(second[..whole].chunks_exact_mut(LANES)).zip(specific_heats[..whole].chunks_exact(LANES));
+        for ((first, second), specific_heats) in chunks {
+            share_lanes(first.try_into().unwrap(), second.try_into().unwrap(), specific_heats.try_into().unwrap(), divisor, totals);
+        }
+        if whole < len {
+            // zero padding moves nothing and adds nothing to the totals
+            let (mut first_rest, mut second_rest, mut heats_rest) = ([float(0.0); LANES], [float(0.0); LANES], [float(0.0); LANES]);
+            let rest = len - whole;
+            first_rest[..rest].copy_from_slice(&first[whole..len]);
+            second_rest[..rest].copy_from_slice(&second[whole..len]);
+            heats_rest[..rest].copy_from_slice(&specific_heats[whole..len]);
+            share_lanes(&mut first_rest, &mut second_rest, &heats_rest, divisor, totals);
+            first[whole..len].copy_from_slice(&first_rest[..rest]);
+            second[whole..len].copy_from_slice(&second_rest[..rest]);
+        }
+    }
+    #[allow(clippy::needless_range_loop)]
+    fn share_lanes(first: &mut [Float; LANES], second: &mut [Float; LANES], specific_heats: &[Float; LANES], divisor: Float, totals: &mut [[Float; LANES]; 4]) {
+        for lane in 0..LANES {
+            let delta = (first[lane] - second[lane]) / divisor;
+            let heat_capacity_delta = delta * specific_heats[lane];
+            let outwards = delta > 0.0;
+            totals[0][lane] += if outwards { heat_capacity_delta } else { float(0.0) };
+            totals[1][lane] -= if outwards { float(0.0) } else { heat_capacity_delta };
+            let moving = if delta.abs() > 0.1 { delta } else { float(0.0) };
+            first[lane] -= moving;
+            second[lane] += moving;
+            totals[2][lane] += moving;
+            totals[3][lane] += moving.abs();
+        }
+    }
+}
+
+fn share_totals(totals: &[[Float; LANES]; 4]) -> ShareTotals {
+    ShareTotals {
+        heat_capacity_self_to_other: reduce(&totals[0]),
+        heat_capacity_other_to_self: reduce(&totals[1]),
+        moved_moles: reduce(&totals[2]),
+        abs_moved_moles: reduce(&totals[3]),
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", not(any(feature = "f64", feature = "fixed"))))]
+pub mod sse {
+    use std::arch::x86_64::*;
+
+    use super::*;
+
+    // SSE2 is part of the x86_64 baseline, so none of this needs runtime detection.
+    // Each lane of 8 is two 4-wide registers.
+
+    fn store(low: __m128, high: __m128) -> [Float; LANES] {
+        let mut totals = [0.0; LANES];
+        unsafe {
+            _mm_storeu_ps(totals.as_mut_ptr(), low);
+            _mm_storeu_ps(totals.as_mut_ptr().add(4), high);
+        }
+        totals
+    }
+
+    pub fn sum(values: &[Float]) -> Float {
+        let whole = values.len() - values.len() % LANES;
+        let mut totals = unsafe {
+            let (mut low, mut high) = (_mm_setzero_ps(), _mm_setzero_ps());
+            for chunk in values[..whole].chunks_exact(LANES) {
+                low = _mm_add_ps(low, _mm_loadu_ps(chunk.as_ptr()));
+                high = _mm_add_ps(high, _mm_loadu_ps(chunk.as_ptr().add(4)));
+            }
+            store(low, high)
+        };
+        for (total, value) in totals.iter_mut().zip(&values[whole..]) {
+            *total += value;
+        }
+        reduce(&totals)
+    }
+    pub fn dot(first: &[Float], second: &[Float]) -> Float {
+        let len = first.len().min(second.len());
+        let whole = len - len % LANES;
+        let mut totals = unsafe {
+            let (mut low, mut high) = (_mm_setzero_ps(), _mm_setzero_ps());
+            for (a, b) in first[..whole].chunks_exact(LANES).zip(second[..whole].chunks_exact(LANES)) {
+                low = _mm_add_ps(low, _mm_mul_ps(_mm_loadu_ps(a.as_ptr()), _mm_loadu_ps(b.as_ptr())));
+                high = _mm_add_ps(high, _mm_mul_ps(_mm_loadu_ps(a.as_ptr().add(4)), _mm_loadu_ps(b.as_ptr().add(4))));
+            }
+            store(low, high)
+        };
+        for (total, (a, b)) in totals.iter_mut().zip(first[whole..len].iter().zip(&second[whole..len])) {
+            *total += a * b;
+        }
+        reduce(&totals)
+    }
+    pub fn add_assign(target: &mut [Float], source: &[Float]) {
+        let len = target.len().min(source.len());
+        let whole = len - len % 4;
+        for (target, source) in target[..whole].chunks_exact_mut(4).zip(source[..whole].chunks_exact(4)) {
+            unsafe {
+                _mm_storeu_ps(target.as_mut_ptr(), _mm_add_ps(_mm_loadu_ps(target.as_ptr()), _mm_loadu_ps(source.as_ptr())));
+            }
+        }
+        portable::add_assign(&mut target[whole..len], &source[whole..len]);
+    }
+    pub fn split_off(source: &mut [Float], removed: &mut [Float], divisor: Float, multiplier: Float) {
+        let len = source.len().min(removed.len());
+        let whole = len - len % 4;
+        unsafe {
+            let (divisor, multiplier) = (_mm_set1_ps(divisor), _mm_set1_ps(multiplier));
+            for (amounts, removed) in source[..whole].chunks_exact_mut(4).zip(removed[..whole].chunks_exact_mut(4)) {
+                let current = _mm_loadu_ps(amounts.as_ptr());
+                let taken = _mm_mul_ps(_mm_div_ps(current, divisor), multiplier);
+                _mm_storeu_ps(removed.as_mut_ptr(), taken);
+                _mm_storeu_ps(amounts.as_mut_ptr(), _mm_sub_ps(current, taken));
+            }
+        }
+        portable::split_off(&mut source[whole..len], &mut removed[whole..len], divisor, multiplier);
+    }
+    pub fn share(first: &mut [Float], second: &mut [Float], specific_heats: &[Float], divisor: Float) -> ShareTotals {
+        let len = first.len().min(second.len()).min(specific_heats.len());
+        let whole = len - len % LANES;
+        // [self_to_other, other_to_self, moved, abs_moved], each as a low and high half
+        let mut registers = [unsafe { _mm_setzero_ps() }; 8];
+        unsafe {
+            let divisor = _mm_set1_ps(divisor);
+            let threshold = _mm_set1_ps(0.1);
+            let sign = _mm_set1_ps(-0.0);
+            let zero = _mm_setzero_ps();
+            for start in (0..whole).step_by(4) {
+                let half = (start / 4) % 2;
+                let a = _mm_loadu_ps(first.as_ptr().add(start));
+                let b = _mm_loadu_ps(second.as_ptr().add(start));
+                let delta = _mm_div_ps(_mm_sub_ps(a, b), divisor);
+                let heat_capacity_delta = _mm_mul_ps(delta, _mm_loadu_ps(specific_heats.as_ptr().add(start)));
+                let outwards = _mm_cmpgt_ps(delta, zero);
+                registers[half] = _mm_add_ps(registers[half], _mm_and_ps(outwards, heat_capacity_delta));
+                registers[2 + half] = _mm_sub_ps(registers[2 + half], _mm_andnot_ps(outwards, heat_capacity_delta));
+                let abs_delta = _mm_andnot_ps(sign, delta);
+                let moving = _mm_and_ps(_mm_cmpgt_ps(abs_delta, threshold), delta);
+                _mm_storeu_ps(first.as_mut_ptr().add(start), _mm_sub_ps(a, moving));
+                _mm_storeu_ps(second.as_mut_ptr().add(start), _mm_add_ps(b, moving));
+                registers[4 + half] = _mm_add_ps(registers[4 + half], moving);
+                registers[6 + half] = _mm_add_ps(registers[6 + half], _mm_andnot_ps(sign, moving));
+            }
+        }
+        let mut totals = [
+            store(registers[0], registers[1]),
+            store(registers[2], registers[3]),
+            store(registers[4], registers[5]),
+            store(registers[6], registers[7]),
+        ];
+        portable::share_into(&mut first[whole..len], &mut second[whole..len], &specific_heats[whole..len], divisor, &mut totals);
+        share_totals(&totals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hint::black_box;
+    use std::time::Instant;
+
+    use atmos::constants::Constant;
+    use atmos::gases::gases;
+
+    // The loops these replaced, kept to check against and to benchmark. They take
+    // the specific heats rather than looking them up, so the benchmark times the loop.
+    fn reference_dot(amounts: &[Float], specific_heats: &[Float]) -> Float {
+        amounts.iter().zip(specific_heats.iter()).fold(float(0.0), |x, (amount, specific_heat)| x + amount * specific_heat)
+    }
+    fn reference_sum(amounts: &[Float]) -> Float {
+        amounts.iter().fold(float(0.0), |x, amount| x + amount)
+    }
+    fn reference_share(first: &mut [Float], second: &mut [Float], specific_heats: &[Float], divisor: Float) -> ShareTotals {
+        let mut totals = ShareTotals::default();
+        for (i, this_amount) in first.iter_mut().enumerate().take(specific_heats.len()) {
+            let delta = (*this_amount - second[i]) / divisor;
+            let heat_cap_delta = delta * specific_heats[i];
+            if delta > 0.0 {
+                totals.heat_capacity_self_to_other += heat_cap_delta;
+            } else {
+                totals.heat_capacity_other_to_self -= heat_cap_delta;
+            }
+            if delta.abs() > 0.1 {
+                *this_amount -= delta;
+                second[i] += delta;
+                totals.moved_moles += delta;
+                totals.abs_moved_moles += delta.abs();
+            }
+        }
+        totals
+    }
+
+    fn random_gases(seed: &mut u32, len: usize) -> Vec<Float> {
+        let mut amounts = vec![float(0.0); padded_len(len)];
+        for amount in amounts.iter_mut().take(len) {
+            *seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            // a fair share of exact zeroes, like real mixtures
+            *amount = if *seed >> 30 == 0 { float(0.0) } else { float(((*seed >> 8) % 100_000) as Constant / 100.0) };
+        }
+        amounts
+    }
+
+    fn close(a: Float, b: Float) -> bool {
+        close_at(a, b, a.abs().max(b.abs()))
+    }
+    // for sums that can cancel out, relative to the size of what went into them
+    fn close_at(a: Float, b: Float, scale: Float) -> bool {
+        (a - b).abs() <= 1e-5 * scale.max(1.0)
+    }
+
+    #[test]
+    fn test_matches_reference() {
+        let specific_heats = gases().specific_heats().to_vec();
+        let mut seed = 1;
+        for _ in 0..500 {
+            let mut first = random_gases(&mut seed, gases().len());
+            let mut second = random_gases(&mut seed, gases().len());
+            assert!(close(dot(&first, &specific_heats), reference_dot(&first, &specific_heats)));
+            assert!(close(sum(&first), reference_sum(&first)));
+            let (mut reference_first, mut reference_second) = (first.clone(), second.clone());
+            let totals = share(&mut first, &mut second, &specific_heats, float(5.0));
+            let expected = reference_share(&mut reference_first, &mut reference_second, &specific_heats, float(5.0));
+            assert_eq!((first, second), (reference_first, reference_second));
+            assert!(close(totals.heat_capacity_self_to_other, expected.heat_capacity_self_to_other));
+            assert!(close(totals.heat_capacity_other_to_self, expected.heat_capacity_other_to_self));
+            assert!(close_at(totals.moved_moles, expected.moved_moles, expected.abs_moved_moles));
+            assert!(close(totals.abs_moved_moles, expected.abs_moved_moles));
+        }
+    }
+    #[cfg(all(feature = "simd", target_arch = "x86_64", not(any(feature = "f64", feature = "fixed"))))]
+    #[test]
+    fn test_sse_matches_portable() {
+        let mut seed = 7;
+        for len in 0..40 {
+            let mut first = random_gases(&mut seed, len);
+            let mut second = random_gases(&mut seed, len);
+            let mut specific_heats = random_gases(&mut seed, len);
+            first.truncate(len);
+            second.truncate(len);
+            specific_heats.truncate(len);
+            assert_eq!(sse::sum(&first).to_bits(), portable::sum(&first).to_bits());
+            assert_eq!(sse::dot(&first, &second).to_bits(), portable::dot(&first, &second).to_bits());
+            let (mut portable_first, mut portable_second) = (first.clone(), second.clone());
+            let totals = sse::share(&mut first, &mut second, &specific_heats, 3.0);
+            let expected = portable::share(&mut portable_first, &mut portable_second, &specific_heats, 3.0);
+            assert_eq!(totals, expected);
+            assert_eq!((&first, &second), (&portable_first, &portable_second));
+            let (mut removed, mut portable_removed) = (vec![0.0; len], vec![0.0; len]);
+            sse::split_off(&mut second, &mut removed, 7.0, 2.0);
+            portable::split_off(&mut portable_second, &mut portable_removed, 7.0, 2.0);
+            assert_eq!(removed, portable_removed);
+        }
+    }
+
+    fn time<F: FnMut()>(name: &str, iterations: u32, mut f: F) -> f64 {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            f();
+        }
+        let nanos = start.elapsed().as_nanos() as f64 / iterations as f64;
+        println!("{:>24}: {:8.2} ns", name, nanos);
+        nanos
+    }
+
+    /// `cargo test --release bench_gas_vectors -- --ignored --nocapture`, with and
+    /// without `--features simd`.
+    #[test]
+    #[ignore]
+    fn bench_gas_vectors() {
+        const ITERATIONS: u32 = 2_000_000;
+        let mut seed = 3;
+        let first = random_gases(&mut seed, gases().len());
+        let second = random_gases(&mut seed, gases().len());
+        let specific_heats = gases().specific_heats().to_vec();
+        let mut results = Vec::new();
+        {
+            let old = time("heat_capacity (old)", ITERATIONS, || { black_box(reference_dot(black_box(&first), &specific_heats)); });
+            let new = time("heat_capacity (lanes)", ITERATIONS, || { black_box(dot(black_box(&first), &specific_heats)); });
+            results.push(("heat_capacity", old / new));
+        }
+        {
+            let old = time("total_moles (old)", ITERATIONS, || { black_box(reference_sum(black_box(&first))); });
+            let new = time("total_moles (lanes)", ITERATIONS, || { black_box(sum(black_box(&first))); });
+            results.push(("total_moles", old / new));
+        }
+        {
+            let (mut a, mut b) = (first.clone(), second.clone());
+            let old = time("share (old)", ITERATIONS, || {
+                a.copy_from_slice(&first);
+                b.copy_from_slice(&second);
+                black_box(reference_share(&mut a, &mut b, &specific_heats, float(5.0)));
+            });
+            let new = time("share (lanes)", ITERATIONS, || {
+                a.copy_from_slice(&first);
+                b.copy_from_slice(&second);
+                black_box(share(&mut a, &mut b, &specific_heats, float(5.0)));
+            });
+            results.push(("share", old / new));
+        }
+        {
+            let mut a = first.clone();
+            let mut removed = vec![float(0.0); a.len()];
+            let old = time("remove_ratio (old)", ITERATIONS, || {
+                a.copy_from_slice(&first);
+                for (i, amount) in a.iter_mut().enumerate() {
+                    removed[i] = *amount * 0.5;
+                    *amount -= removed[i];
+                }
+                black_box(&removed);
+            });
+            let new = time("remove_ratio (lanes)", ITERATIONS, || {
+                a.copy_from_slice(&first);
+                split_off(&mut a, &mut removed, float(1.0), float(0.5));
+                black_box(&removed);
+            });
+            results.push(("remove_ratio", old / new));
+        }
+        for (name, speedup) in results {
+            println!("{:>24}: {:.2}x", name, speedup);
+        }
+    }
+}
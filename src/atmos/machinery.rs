@@ -0,0 +1,775 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+use atmos::air_alarm::check_id;
+use atmos::constants::*;
+use atmos::gases::{gas_index, gases};
+use atmos::mix::AtmosMixture;
+use atmos::pipenet::PipeNets;
+use atmos::registry::{Handle, MixtureRegistry};
+
+use error::{Error, Result};
+
+/// What one machine did in one tick.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Transfer {
+    pub moles: Float,
+    /// Work done on the gas, in joules. Passive machines never do any.
+    pub energy: Float,
+}
+
+// `moles$energy`
+impl fmt::Display for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}${}", self.moles, self.energy)
+    }
+}
+
+/// Work needed to push `moles` at `temperature` from `input_pressure` up to
+/// `output_pressure`, taking the compression as isothermal. Nothing if the gas
+/// flowed downhill.
+fn compression_energy(moles: Float, temperature: Float, input_pressure: Float, output_pressure: Float) -> Float {
+    if moles <= 0.0 || input_pressure <= 0.0 || output_pressure <= input_pressure {
+        return float(0.0);
+    }
+    moles * GAS_CONSTANT * temperature * (output_pressure / input_pressure).ln()
+}
+
+fn pump_moles(input: &mut AtmosMixture, output: &mut AtmosMixture, removed: AtmosMixture) -> Transfer {
+    let input_pressure = input.pressure();
+    let moles = removed.total_moles();
+    let temperature = removed.temperature;
+    output.merge(removed);
+    Transfer { moles, energy: compression_energy(moles, temperature, input_pressure, output.pressure()) }
+}
+
+/// Moles at the input's temperature it takes to raise the output's pressure by `pressure_delta`.
+fn moles_for_pressure(input: &AtmosMixture, output: &AtmosMixture, pressure_delta: Float) -> Float {
+    pressure_delta * output.volume / (input.temperature * GAS_CONSTANT)
+}
+
+/// Pumps gas until the output is at `target_pressure`.
+pub fn pressure_pump(input: &mut AtmosMixture, output: &mut AtmosMixture, target_pressure: Float) -> Transfer {
+    let pressure_delta = target_pressure - output.pressure();
+    if pressure_delta < 0.01 || input.total_moles() <= 0.0 || input.temperature <= 0.0 {
+        return Transfer::default();
+    }
+    let removed = input.remove(moles_for_pressure(input, output, pressure_delta));
+    pump_moles(input, output, removed)
+}
+
+/// Moves `transfer_rate` litres of the input each tick. It stalls against more
+/// than 9000 kPa, or if overclocked, once the output is 1000 kPa above the input.
+pub fn volume_pump(input: &mut AtmosMixture, output: &mut AtmosMixture, transfer_rate: Float, overclocked: bool) -> Transfer {
+    let input_pressure = input.pressure();
+    let output_pressure = output.pressure();
+    if input_pressure < 0.01 || input.volume <= 0.0 {
+        return Transfer::default();
+    }
+    if overclocked {
+        if output_pressure - input_pressure > float(VOLUME_PUMP_OVERCLOCK_MAX_DIFFERENCE) {
+            return Transfer::default();
+        }
+    } else if output_pressure > float(VOLUME_PUMP_MAX_OUTPUT_PRESSURE) {
+        return Transfer::default();
+    }
+    let removed = input.remove_ratio(transfer_rate / input.volume);
+    pump_moles(input, output, removed)
+}
+
+/// Lets gas flow from input to output until the output reaches `target_pressure`
+/// or the two are nearly equal, DM's `release_gas_to`.
+fn release_gas_to(input: &mut AtmosMixture, output: &mut AtmosMixture, target_pressure: Float) -> Transfer {
+    let input_pressure = input.pressure();
+    let output_pressure = output.pressure();
+    if output_pressure >= target_pressure.min(input_pressure - float(10.0)) || input.total_moles() <= 0.0 || input.temperature <= 0.0 {
+        return Transfer::default();
+    }
+    let pressure_delta = (target_pressure - output_pressure).min((input_pressure - output_pressure) / float(2.0));
+    let removed = input.remove(moles_for_pressure(input, output, pressure_delta));
+    let moles = removed.total_moles();
+    output.merge(removed);
+    Transfer { moles, energy: float(0.0) }
+}
+
+/// One-way flow from input to output, up to `target_pressure` on the output.
+pub fn passive_gate(input: &mut AtmosMixture, output: &mut AtmosMixture, target_pressure: Float) -> Transfer {
+    release_gas_to(input, output, target_pressure)
+}
+
+/// Vents the input into the output while the input is above `target_pressure`,
+/// moving no more than the `input_pressure - target_pressure` it's over by.
+pub fn release_valve(input: &mut AtmosMixture, output: &mut AtmosMixture, target_pressure: Float) -> Transfer {
+    let input_pressure = input.pressure();
+    if input_pressure <= target_pressure {
+        return Transfer::default();
+    }
+    let output_pressure = output.pressure();
+    release_gas_to(input, output, input_pressure.min(output_pressure + (input_pressure - target_pressure)))
+}
+
+/// A machine moving gas from one mixture to another, DM's binary components.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryMachine {
+    PressurePump { target_pressure: Float },
+    VolumePump { transfer_rate: Float, overclocked: bool },
+    PassiveGate { target_pressure: Float },
+    ReleaseValve { target_pressure: Float },
+}
+
+impl BinaryMachine {
+    /// `setting` is the target pressure in kPa, or the transfer rate in litres for
+    /// a volume pump.
+    pub fn new(kind: &str, setting: Float, overclocked: bool) -> Result<BinaryMachine> {
+        if setting < 0.0 || !setting.is_finite() {
+            return Err(Error::InvalidArgument("setting".to_string(), setting.to_string()));
+        }
+        match kind {
+            "pressure_pump" => Ok(BinaryMachine::PressurePump { target_pressure: setting }),
+            "volume_pump" => Ok(BinaryMachine::VolumePump { transfer_rate: setting, overclocked }),
+            "passive_gate" => Ok(BinaryMachine::PassiveGate { target_pressure: setting }),
+            "release_valve" => Ok(BinaryMachine::ReleaseValve { target_pressure: setting }),
+            _ => Err(Error::InvalidArgument("kind".to_string(), kind.to_string())),
+        }
+    }
+    pub fn process(&self, input: &mut AtmosMixture, output: &mut AtmosMixture) -> Transfer {
+        match *self {
+            BinaryMachine::PressurePump { target_pressure } => pressure_pump(input, output, target_pressure),
+            BinaryMachine::VolumePump { transfer_rate, overclocked } => volume_pump(input, output, transfer_rate, overclocked),
+            BinaryMachine::PassiveGate { target_pressure } => passive_gate(input, output, target_pressure),
+            BinaryMachine::ReleaseValve { target_pressure } => release_valve(input, output, target_pressure),
+        }
+    }
+}
+
+/// Reads a filter's `,`-separated gas ids. Ids this library doesn't know are
+/// skipped, as they are in gas strings.
+pub fn parse_gas_ids(gas_ids: &str) -> Vec<usize> {
+    let gases = gases();
+    gas_ids.split(',').filter_map(|id| gases.index_of(id.trim())).collect()
+}
+
+/// Takes `transfer_rate` litres of the input, sends the `filtered` gases to the
+/// filter output and the rest to the main output. Stops if the main output is at
+/// the maximum output pressure; filtered gas goes back to the input if the filter
+/// output is.
+pub fn filter(input: &mut AtmosMixture, filter_output: &mut AtmosMixture, main_output: &mut AtmosMixture, transfer_rate: Float, filtered: &[usize]) -> Transfer {
+    if transfer_rate <= 0.0 || input.volume <= 0.0 || main_output.pressure() >= float(MAX_OUTPUT_PRESSURE) {
+        return Transfer::default();
+    }
+    let mut removed = input.remove_ratio(transfer_rate / input.volume);
+    let moles = removed.total_moles();
+    if moles <= 0.0 {
+        return Transfer::default();
+    }
+    let filtered_out = removed.remove_gases(filtered);
+    if filter_output.pressure() < float(MAX_OUTPUT_PRESSURE) {
+        filter_output.merge(filtered_out);
+    } else {
+        input.merge(filtered_out);
+    }
+    main_output.merge(removed);
+    Transfer { moles, energy: float(0.0) }
+}
+
+/// Draws from both inputs, `first_concentration` of the pressure from the first
+/// and the rest from the second, until the output reaches `target_pressure`. If
+/// either input runs short, both are scaled down to keep the ratio.
+pub fn mixer(first_input: &mut AtmosMixture, second_input: &mut AtmosMixture, output: &mut AtmosMixture, target_pressure: Float, first_concentration: Float) -> Transfer {
+    let pressure_delta = target_pressure - output.pressure();
+    if pressure_delta <= 0.0 {
+        return Transfer::default();
+    }
+    let second_concentration = float(1.0) - first_concentration;
+    let moles_for = |input: &AtmosMixture, concentration: Float| {
+        if input.temperature > 0.0 {
+            moles_for_pressure(input, output, concentration * pressure_delta)
+        } else {
+            float(0.0)
+        }
+    };
+    let mut first_moles = moles_for(first_input, first_concentration);
+    let mut second_moles = moles_for(second_input, second_concentration);
+    let first_available = first_input.total_moles();
+    let second_available = second_input.total_moles();
+    if second_concentration <= 0.0 {
+        first_moles = first_moles.min(first_available);
+        second_moles = float(0.0);
+    } else if first_concentration <= 0.0 {
+        first_moles = float(0.0);
+        second_moles = second_moles.min(second_available);
+    } else if first_available < first_moles || second_available < second_moles {
+        let ratio = if first_moles > 0.0 && second_moles > 0.0 {
+            (first_available / first_moles).min(second_available / second_moles)
+        } else {
+            float(0.0)
+        };
+        first_moles *= ratio;
+        second_moles *= ratio;
+    }
+    let mut moles = float(0.0);
+    if first_moles > 0.0 {
+        let removed = first_input.remove(first_moles);
+        moles += removed.total_moles();
+        output.merge(removed);
+    }
+    if second_moles > 0.0 {
+        let removed = second_input.remove(second_moles);
+        moles += removed.total_moles();
+        output.merge(removed);
+    }
+    Transfer { moles, energy: float(0.0) }
+}
+
+/// Heat one machine put into a mixture in one tick, and what that cost.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct HeatTransfer {
+    /// In joules, negative when cooling.
+    pub heat: Float,
+    pub energy: Float,
+}
+
+// `heat$energy`
+impl fmt::Display for HeatTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}${}", self.heat, self.energy)
+    }
+}
+
+/// A freezer or heater. It acts like a body of `heat_capacity` held at the target
+/// temperature, evened out with the gas each tick, but never moves more than
+/// `max_heat_transfer` joules a tick if that's set.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Thermomachine {
+    pub target_temperature: Float,
+    pub heat_capacity: Float,
+    pub max_heat_transfer: Option<Float>,
+}
+
+impl Thermomachine {
+    /// Every setting has to be a finite number, none of them negative.
+    pub fn new(target_temperature: Float, heat_capacity: Float, max_heat_transfer: Option<Float>) -> Result<Thermomachine> {
+        let settings = [("target_temperature", Some(target_temperature)), ("heat_capacity", Some(heat_capacity)), ("max_heat_transfer", max_heat_transfer)];
+        for &(name, value) in settings.iter() {
+            if let Some(value) = value.filter(|&value| value < 0.0 || !value.is_finite()) {
+                return Err(Error::InvalidArgument(name.to_string(), value.to_string()));
+            }
+        }
+        Ok(Thermomachine { target_temperature, heat_capacity, max_heat_transfer })
+    }
+    /// Heats or cools `air` toward the target. The energy drawn is DM's active power
+    /// for a thermomachine, a tenth of the heat capacity per kelvin of change.
+    pub fn process(&self, air: &mut AtmosMixture) -> HeatTransfer {
+        let air_heat_capacity = air.heat_capacity();
+        let combined_heat_capacity = self.heat_capacity + air_heat_capacity;
+        if air_heat_capacity <= 0.0 || combined_heat_capacity <= 0.0 {
+            return HeatTransfer::default();
+        }
+        let equalized_temperature = (self.heat_capacity * self.target_temperature + air_heat_capacity * air.temperature) / combined_heat_capacity;
+        let mut heat = (equalized_temperature - air.temperature) * air_heat_capacity;
+        if let Some(max_heat_transfer) = self.max_heat_transfer {
+            heat = heat.max(-max_heat_transfer).min(max_heat_transfer);
+        }
+        let temperature_delta = heat / air_heat_capacity;
+        air.temperature += temperature_delta;
+        HeatTransfer { heat, energy: self.heat_capacity * temperature_delta.abs() / float(10.0) }
+    }
+}
+
+/// Conducts heat between two mixtures through a heat exchanger's walls, using
+/// `temperature_share`. Returns the joules that went from `first` to `second`.
+pub fn heat_exchanger(first: &mut AtmosMixture, second: &mut AtmosMixture, conduction_coefficient: Float) -> Float {
+    let second_heat_capacity = second.heat_capacity();
+    let second_temperature = second.temperature;
+    first.temperature_share(second, conduction_coefficient);
+    (second.temperature - second_temperature) * second_heat_capacity
+}
+
+// Reads a `key=value;...` settings string, handing each pair to `set`.
+fn parse_settings<F: FnMut(&str, &str) -> Result<()>>(settings: &str, mut set: F) -> Result<()> {
+    for entry in settings.split(';') {
+        if entry.trim().is_empty() {
+            continue;
+        }
+        let mut entry_split = entry.splitn(2, '=');
+        match (entry_split.next(), entry_split.next()) {
+            (Some(key), Some(value)) => set(key.trim(), value.trim())?,
+            _ => return Err(Error::MalformedEntry(entry.to_string())),
+        }
+    }
+    Ok(())
+}
+
+/// Bounds and rates can't be negative or non-finite.
+fn setting_number(key: &str, value: &str) -> Result<Float> {
+    match value.parse::<Float>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Ok(number),
+        _ => Err(Error::InvalidArgument(key.to_string(), value.to_string())),
+    }
+}
+
+fn setting_flag(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "1" => Ok(true),
+        "0" => Ok(false),
+        _ => Err(Error::InvalidArgument(key.to_string(), value.to_string())),
+    }
+}
+
+/// Vent pressure check flags, as in DM.
+pub const EXT_BOUND : u8 = 1;
+pub const INT_BOUND : u8 = 2;
+
+/// A vent pump between a pipe network and the turf it sits on. Releasing, it fills
+/// the turf up to the external bound without drawing the pipe below the internal
+/// one; siphoning, it pulls the turf toward the external bound without filling
+/// the pipe past the internal one. Only the checked bounds apply.
+#[derive(Clone, PartialEq, Debug)]
+pub struct VentPump {
+    pub releasing: bool,
+    pub pressure_checks: u8,
+    pub external_pressure_bound: Float,
+    pub internal_pressure_bound: Float,
+}
+
+impl Default for VentPump {
+    fn default() -> VentPump {
+        VentPump {
+            releasing: true,
+            pressure_checks: EXT_BOUND,
+            external_pressure_bound: float(ATMOSPHERE),
+            internal_pressure_bound: float(0.0),
+        }
+    }
+}
+
+impl VentPump {
+    /// Applies air alarm settings, `direction=1;checks=3;external=101.325;internal=0;`.
+    /// Direction 1 releases and 0 siphons. Settings left out keep their value.
+    pub fn configure(&mut self, settings: &str) -> Result<()> {
+        parse_settings(settings, |key, value| {
+            match key {
+                "direction" => self.releasing = setting_flag(key, value)?,
+                "checks" => self.pressure_checks = value.parse::<u8>().ok().filter(|checks| *checks <= (EXT_BOUND | INT_BOUND))
+                    .ok_or_else(|| Error::InvalidArgument(key.to_string(), value.to_string()))?,
+                "external" => self.external_pressure_bound = setting_number(key, value)?,
+                "internal" => self.internal_pressure_bound = setting_number(key, value)?,
+                _ => return Err(Error::InvalidArgument(key.to_string(), value.to_string())),
+            }
+            Ok(())
+        })
+    }
+    pub fn process(&self, pipe: &mut AtmosMixture, turf: &mut AtmosMixture) -> Transfer {
+        let turf_pressure = turf.pressure();
+        let pipe_pressure = pipe.pressure();
+        let mut pressure_delta = float(VENT_PUMP_MAX_PRESSURE_DELTA);
+        if self.releasing {
+            if self.pressure_checks & EXT_BOUND != 0 {
+                pressure_delta = pressure_delta.min(self.external_pressure_bound - turf_pressure);
+            }
+            if self.pressure_checks & INT_BOUND != 0 {
+                pressure_delta = pressure_delta.min(pipe_pressure - self.internal_pressure_bound);
+            }
+            if pressure_delta <= 0.0 || pipe.temperature <= 0.0 {
+                return Transfer::default();
+            }
+            let removed = pipe.remove(moles_for_pressure(pipe, turf, pressure_delta));
+            pump_moles(pipe, turf, removed)
+        } else {
+            if self.pressure_checks & EXT_BOUND != 0 {
+                pressure_delta = pressure_delta.min(turf_pressure - self.external_pressure_bound);
+            }
+            if self.pressure_checks & INT_BOUND != 0 {
+                pressure_delta = pressure_delta.min(self.internal_pressure_bound - pipe_pressure);
+            }
+            if pressure_delta <= 0.0 || turf.temperature <= 0.0 {
+                return Transfer::default();
+            }
+            let removed = turf.remove(moles_for_pressure(turf, pipe, pressure_delta));
+            pump_moles(turf, pipe, removed)
+        }
+    }
+}
+
+/// A scrubber between a turf and a pipe network. Scrubbing, it takes `volume_rate`
+/// litres of the turf's air, keeps the filtered gases and puts the rest back, but
+/// only if there's something to filter; siphoning, it keeps all of it. With
+/// widenet it works on the adjacent turfs too. Panic siphon is siphoning with
+/// widenet. It stops once the pipe reaches 50 atmospheres.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Scrubber {
+    pub scrubbing: bool,
+    pub volume_rate: Float,
+    pub widenet: bool,
+    pub filtered: Vec<usize>,
+}
+
+impl Default for Scrubber {
+    fn default() -> Scrubber {
+        Scrubber {
+            scrubbing: true,
+            volume_rate: float(SCRUBBER_DEFAULT_VOLUME_RATE),
+            widenet: false,
+            filtered: vec![gas_index("co2")],
+        }
+    }
+}
+
+impl Scrubber {
+    /// Applies air alarm settings, `scrubbing=1;volume_rate=200;widenet=0;filter=co2,plasma;`.
+    /// The filter list replaces the old one. Settings left out keep their value.
+    pub fn configure(&mut self, settings: &str) -> Result<()> {
+        parse_settings(settings, |key, value| {
+            match key {
+                "scrubbing" => self.scrubbing = setting_flag(key, value)?,
+                "volume_rate" => self.volume_rate = setting_number(key, value)?,
+                "widenet" => self.widenet = setting_flag(key, value)?,
+                "filter" => self.filtered = parse_gas_ids(value),
+                _ => return Err(Error::InvalidArgument(key.to_string(), value.to_string())),
+            }
+            Ok(())
+        })
+    }
+    /// Scrubs one turf.
+    pub fn process(&self, pipe: &mut AtmosMixture, turf: &mut AtmosMixture) -> Transfer {
+        if pipe.pressure() >= float(SCRUBBER_MAX_PIPE_PRESSURE) || turf.volume <= 0.0 {
+            return Transfer::default();
+        }
+        let ratio = (self.volume_rate / turf.volume).min(float(1.0));
+        if !self.scrubbing {
+            let removed = turf.remove_ratio(ratio);
+            return pump_moles(turf, pipe, removed);
+        }
+        if !self.filtered.iter().any(|&index| turf.gases[index] > 0.0) {
+            return Transfer::default();
+        }
+        let turf_pressure = turf.pressure();
+        let mut removed = turf.remove_ratio(ratio);
+        let filtered_out = removed.remove_gases(&self.filtered);
+        turf.merge(removed);
+        let moles = filtered_out.total_moles();
+        let temperature = filtered_out.temperature;
+        pipe.merge(filtered_out);
+        Transfer { moles, energy: compression_energy(moles, temperature, turf_pressure, pipe.pressure()) }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum UnaryDevice {
+    Vent(VentPump),
+    Scrubber(Scrubber),
+}
+
+/// Every vent and scrubber's settings, keyed by whatever id DM gave it.
+#[derive(Default)]
+pub struct DeviceRegistry {
+    devices: HashMap<String, UnaryDevice>,
+}
+
+impl DeviceRegistry {
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+    /// Applies settings to a vent, registering it with the defaults first if it's new.
+    pub fn configure_vent(&mut self, id: &str, settings: &str) -> Result<()> {
+        check_id(id)?;
+        let mut vent = match self.devices.get(id) {
+            Some(UnaryDevice::Vent(vent)) => vent.clone(),
+            _ => VentPump::default(),
+        };
+        vent.configure(settings)?;
+        self.devices.insert(id.to_string(), UnaryDevice::Vent(vent));
+        Ok(())
+    }
+    /// Applies settings to a scrubber, registering it with the defaults first if it's new.
+    pub fn configure_scrubber(&mut self, id: &str, settings: &str) -> Result<()> {
+        check_id(id)?;
+        let mut scrubber = match self.devices.get(id) {
+            Some(UnaryDevice::Scrubber(scrubber)) => scrubber.clone(),
+            _ => Scrubber::default(),
+        };
+        scrubber.configure(settings)?;
+        self.devices.insert(id.to_string(), UnaryDevice::Scrubber(scrubber));
+        Ok(())
+    }
+    pub fn remove(&mut self, id: &str) -> Result<UnaryDevice> {
+        self.devices.remove(id).ok_or_else(|| Error::UnknownDevice(id.to_string()))
+    }
+    pub fn get(&self, id: &str) -> Result<&UnaryDevice> {
+        self.devices.get(id).ok_or_else(|| Error::UnknownDevice(id.to_string()))
+    }
+    /// Runs every device in `device:pipe:turf,turf,...;...` for one tick, `pipe` being
+    /// the pipe its node is on and `turf` mixture handles, its own turf first. Only
+    /// widenet scrubbers use the turfs after the first. Returns one `device$moles$energy`
+    /// line per device. Nothing runs if any id or handle is bad.
+    pub fn process_all(&self, pipenets: &mut PipeNets, mixtures: &mut MixtureRegistry, device_string: &str) -> Result<String> {
+        let mut entries = Vec::new();
+        for entry in device_string.split(';') {
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = entry.split(':').collect();
+            if parts.len() != 3 {
+                return Err(Error::MalformedEntry(entry.to_string()));
+            }
+            let id = parts[0].trim();
+            let device = self.get(id)?;
+            let pipe: Handle = parts[1].parse()?;
+            pipenets.pipe(pipe)?;
+            let mut turfs: Vec<Handle> = Vec::new();
+            for turf in parts[2].split(',') {
+                if !turf.trim().is_empty() {
+                    let turf = turf.parse()?;
+                    mixtures.get(turf)?;
+                    turfs.push(turf);
+                }
+            }
+            if turfs.is_empty() {
+                return Err(Error::MalformedEntry(entry.to_string()));
+            }
+            entries.push((id, device, pipe, turfs));
+        }
+        let mut lines = Vec::with_capacity(entries.len());
+        for (id, device, pipe, turfs) in entries {
+            let air = pipenets.air_mut(pipe)?;
+            let transfer = match *device {
+                UnaryDevice::Vent(ref vent) => vent.process(air, mixtures.get_mut(turfs[0])?),
+                UnaryDevice::Scrubber(ref scrubber) => {
+                    let mut total = Transfer::default();
+                    for &turf in turfs.iter().take(if scrubber.widenet { turfs.len() } else { 1 }) {
+                        let transfer = scrubber.process(air, mixtures.get_mut(turf)?);
+                        total.moles += transfer.moles;
+                        total.energy += transfer.energy;
+                    }
+                    total
+                }
+            };
+            lines.push(format!("{}${}", id, transfer));
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+thread_local! {
+    static DEVICES: RefCell<DeviceRegistry> = RefCell::new(DeviceRegistry::default());
+}
+
+/// Runs `f` against the vent and scrubber settings backing the BYOND exports.
+pub fn with_devices<F, T>(f: F) -> T
+where
+    F: FnOnce(&mut DeviceRegistry) -> T,
+{
+    DEVICES.with(|cell| f(&mut cell.borrow_mut()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pumps() {
+        let mut input = AtmosMixture::from_gas_string("TEMP=293.15;n2=500;").unwrap();
+        let mut output = AtmosMixture::from_gas_string("TEMP=293.15;n2=10;").unwrap();
+        let initial_moles = input.total_moles() + output.total_moles();
+        let transfer = pressure_pump(&mut input, &mut output, float(300.0));
+        assert!((output.pressure() - 300.0).abs() < 0.1);
+        assert!((input.total_moles() + output.total_moles() - initial_moles).abs() < 0.01);
+        assert!(transfer.moles > 0.0 && transfer.energy > 0.0);
+        assert_eq!(pressure_pump(&mut input, &mut output, float(300.0)), Transfer::default());
+        let mut input = AtmosMixture::from_gas_string("TEMP=293.15;n2=100;").unwrap();
+        let mut output: AtmosMixture = Default::default();
+        let transfer = volume_pump(&mut input, &mut output, float(250.0), false);
+        assert!((transfer.moles - 10.0).abs() < 0.01);
+        assert!((output.total_moles() - 10.0).abs() < 0.01);
+        let mut packed = AtmosMixture::from_gas_string("TEMP=293.15;n2=10000;").unwrap();
+        assert_eq!(volume_pump(&mut input, &mut packed, float(250.0), false), Transfer::default());
+        assert_eq!(volume_pump(&mut input, &mut packed, float(250.0), true), Transfer::default());
+        let mut full = AtmosMixture::from_gas_string("TEMP=293.15;n2=9500;").unwrap();
+        assert!(volume_pump(&mut full, &mut packed, float(250.0), true).moles > 0.0);
+    }
+    #[test]
+    fn test_passive() {
+        let mut input = AtmosMixture::from_gas_string("TEMP=293.15;o2=200;").unwrap();
+        let mut output = AtmosMixture::from_gas_string("TEMP=293.15;o2=20;").unwrap();
+        let transfer = passive_gate(&mut input, &mut output, float(1000.0));
+        assert_eq!(transfer.energy, 0.0);
+        assert!((input.pressure() - output.pressure()).abs() < 0.1);
+        assert_eq!(passive_gate(&mut input, &mut output, float(1000.0)).moles, 0.0);
+        let mut output = AtmosMixture::from_gas_string("TEMP=293.15;o2=20;").unwrap();
+        passive_gate(&mut input, &mut output, float(50.0));
+        assert!((output.pressure() - 50.0).abs() < 0.1);
+        let mut input = AtmosMixture::from_gas_string("TEMP=293.15;o2=200;").unwrap();
+        let mut output: AtmosMixture = Default::default();
+        assert_eq!(release_valve(&mut input, &mut output, float(500.0)), Transfer::default());
+        assert!(release_valve(&mut input, &mut output, float(100.0)).moles > 0.0);
+        // only what the input is over its target gets let out
+        let mut input = AtmosMixture::from_gas_string("TEMP=293.15;o2=200;").unwrap();
+        let mut output: AtmosMixture = Default::default();
+        let over = input.pressure() - 180.0;
+        release_valve(&mut input, &mut output, float(180.0));
+        assert!((output.pressure() - over).abs() < 0.1);
+        assert!(BinaryMachine::new("pressure_pump", float(101.325), false).is_ok());
+        assert!(BinaryMachine::new("turbine", float(101.325), false).is_err());
+        assert!(BinaryMachine::new("volume_pump", float(-1.0), false).is_err());
+    }
+    #[test]
+    fn test_filter() {
+        let mut input = AtmosMixture::from_gas_string("TEMP=400;o2=20;n2=70;plasma=5;co2=5;").unwrap();
+        let mut filter_output: AtmosMixture = Default::default();
+        let mut main_output: AtmosMixture = Default::default();
+        let filtered = parse_gas_ids("plasma, co2,unobtainium");
+        assert_eq!(filtered.len(), 2);
+        let transfer = filter(&mut input, &mut filter_output, &mut main_output, float(1250.0), &filtered);
+        assert!((transfer.moles - 50.0).abs() < 0.01);
+        assert!((filter_output.gases[gas_index("plasma")] - 2.5).abs() < 0.001);
+        assert!((filter_output.gases[gas_index("co2")] - 2.5).abs() < 0.001);
+        assert_eq!(filter_output.gases[gas_index("o2")], 0.0);
+        assert!((main_output.total_moles() - 45.0).abs() < 0.01);
+        assert!((main_output.temperature - 400.0).abs() < 0.01);
+        assert!((filter_output.temperature - 400.0).abs() < 0.01);
+        let mut input = AtmosMixture::from_gas_string("TEMP=400;o2=20;plasma=20;").unwrap();
+        let mut full = AtmosMixture::from_gas_string("TEMP=293.15;n2=5000;").unwrap();
+        filter(&mut input, &mut full, &mut main_output, float(1250.0), &filtered);
+        assert!((input.gases[gas_index("plasma")] - 20.0).abs() < 0.001);
+        assert!((input.gases[gas_index("o2")] - 10.0).abs() < 0.001);
+        assert_eq!(filter(&mut input, &mut filter_output, &mut full, float(1250.0), &filtered), Transfer::default());
+    }
+    #[test]
+    fn test_mixer() {
+        let mut oxygen = AtmosMixture::from_gas_string("TEMP=293.15;o2=500;").unwrap();
+        let mut nitrogen = AtmosMixture::from_gas_string("TEMP=293.15;n2=500;").unwrap();
+        let mut output: AtmosMixture = Default::default();
+        mixer(&mut oxygen, &mut nitrogen, &mut output, float(101.325), float(0.21));
+        assert!((output.pressure() - 101.325).abs() < 0.01);
+        let ratio = output.gases[gas_index("o2")] / output.total_moles();
+        assert!((ratio - 0.21).abs() < 0.001);
+        let mut oxygen = AtmosMixture::from_gas_string("TEMP=293.15;o2=1;").unwrap();
+        let mut output: AtmosMixture = Default::default();
+        let transfer = mixer(&mut oxygen, &mut nitrogen, &mut output, float(101.325), float(0.5));
+        assert!((transfer.moles - 2.0).abs() < 0.001);
+        assert!((output.gases[gas_index("n2")] - 1.0).abs() < 0.001);
+        let mut output: AtmosMixture = Default::default();
+        mixer(&mut oxygen, &mut nitrogen, &mut output, float(50.0), float(0.0));
+        assert!((output.pressure() - 50.0).abs() < 0.01);
+        assert_eq!(output.gases[gas_index("o2")], 0.0);
+    }
+    #[test]
+    fn test_thermomachine() {
+        let mut air = AtmosMixture::from_gas_string("TEMP=293.15;n2=100;").unwrap();
+        let air_heat_capacity = air.heat_capacity();
+        let freezer = Thermomachine { target_temperature: float(73.15), heat_capacity: air_heat_capacity, max_heat_transfer: None };
+        let transfer = freezer.process(&mut air);
+        assert!((air.temperature - 183.15).abs() < 0.01);
+        assert!((transfer.heat + 110.0 * air_heat_capacity).abs() < 1.0);
+        assert!((transfer.energy - 11.0 * air_heat_capacity).abs() < 1.0);
+        let limited = Thermomachine { max_heat_transfer: Some(float(1000.0)), ..freezer };
+        let transfer = limited.process(&mut air);
+        assert!((transfer.heat + 1000.0).abs() < 0.01);
+        assert!((air.temperature - (183.15 - 1000.0 / air_heat_capacity)).abs() < 0.01);
+        let mut vacuum: AtmosMixture = Default::default();
+        assert_eq!(freezer.process(&mut vacuum), HeatTransfer::default());
+        assert_eq!(Thermomachine::new(float(73.15), air_heat_capacity, None).unwrap(), freezer);
+        assert!(matches!(Thermomachine::new(float(-1.0), air_heat_capacity, None), Err(Error::InvalidArgument(..))));
+        assert!(Thermomachine::new(float(73.15), float(-1.0), None).is_err());
+        assert!(Thermomachine::new(float(73.15), air_heat_capacity, Some(float(-1.0))).is_err());
+        // a fixed build has no NaN to pass
+        #[cfg(not(feature = "fixed"))]
+        assert!(Thermomachine::new(Float::NAN, air_heat_capacity, None).is_err());
+    }
+    #[test]
+    fn test_heat_exchanger() {
+        let mut hot = AtmosMixture::from_gas_string("TEMP=600;co2=50;").unwrap();
+        let mut cold = AtmosMixture::from_gas_string("TEMP=100;n2=80;").unwrap();
+        let initial_energy = hot.thermal_energy() + cold.thermal_energy();
+        let hot_energy = hot.thermal_energy();
+        let heat = heat_exchanger(&mut hot, &mut cold, float(0.4));
+        assert!(heat > 0.0 && hot.temperature < 600.0 && cold.temperature > 100.0);
+        assert!((hot_energy - hot.thermal_energy() - heat).abs() < 0.001 * heat);
+        assert!((hot.thermal_energy() + cold.thermal_energy() - initial_energy).abs() < 0.001 * initial_energy);
+        assert_eq!(heat_exchanger(&mut hot, &mut cold, float(0.0)), 0.0);
+    }
+    #[test]
+    fn test_vent_pump() {
+        let mut vent = VentPump::default();
+        let mut pipe = AtmosMixture::from_gas_string("TEMP=293.15;o2=200;n2=800;").unwrap();
+        pipe.volume = float(200.0);
+        let mut turf = AtmosMixture::from_gas_string("TEMP=293.15;o2=10;n2=40;").unwrap();
+        let transfer = vent.process(&mut pipe, &mut turf);
+        assert!(transfer.moles > 0.0);
+        assert!((turf.pressure() - ATMOSPHERE).abs() < 0.1);
+        assert!(vent.process(&mut pipe, &mut turf).moles < 0.001);
+        // the internal bound only leaves 20 kPa to give, less than the turf wants
+        vent.configure(&format!("checks=3;internal={};", pipe.pressure() - float(20.0))).unwrap();
+        let mut turf = AtmosMixture::from_gas_string("TEMP=293.15;o2=10;n2=40;").unwrap();
+        let turf_pressure = turf.pressure();
+        vent.process(&mut pipe, &mut turf);
+        assert!((turf.pressure() - turf_pressure - 20.0).abs() < 0.1);
+        vent.configure("direction=0;checks=1;external=50;").unwrap();
+        // like DM, siphoning works out the moles from the pipe's volume
+        let mut turf = AtmosMixture::from_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap();
+        let turf_pressure = turf.pressure();
+        let transfer = vent.process(&mut pipe, &mut turf);
+        assert!((turf_pressure - turf.pressure() - (turf_pressure - 50.0) * 200.0 / 2500.0).abs() < 0.01);
+        assert!(transfer.energy > 0.0);
+        assert!(vent.configure("direction=2;").is_err());
+        assert!(vent.configure("checks=4;").is_err());
+        assert!(vent.configure("power=1;").is_err());
+        assert!(matches!(vent.configure("external=-1;"), Err(Error::InvalidArgument(..))));
+        assert!(matches!(vent.configure("internal=inf;"), Err(Error::InvalidArgument(..))));
+    }
+    #[test]
+    fn test_scrubber() {
+        let mut scrubber = Scrubber::default();
+        let mut pipe = AtmosMixture { volume: float(200.0), ..Default::default() };
+        let mut turf = AtmosMixture::from_gas_string("TEMP=293.15;o2=22;n2=82;co2=10;plasma=5;").unwrap();
+        let transfer = scrubber.process(&mut pipe, &mut turf);
+        assert!((transfer.moles - 0.8).abs() < 0.001);
+        assert!((turf.gases[gas_index("co2")] - 9.2).abs() < 0.001);
+        assert!((turf.gases[gas_index("o2")] - 22.0).abs() < 0.001);
+        assert!((pipe.gases[gas_index("co2")] - 0.8).abs() < 0.001);
+        scrubber.configure("filter=co2,plasma;volume_rate=2500;").unwrap();
+        scrubber.process(&mut pipe, &mut turf);
+        assert_eq!(turf.gases[gas_index("co2")], 0.0);
+        assert_eq!(turf.gases[gas_index("plasma")], 0.0);
+        assert_eq!(scrubber.process(&mut pipe, &mut turf), Transfer::default());
+        scrubber.configure("scrubbing=0;volume_rate=250;").unwrap();
+        let transfer = scrubber.process(&mut pipe, &mut turf);
+        assert!((transfer.moles - 10.4).abs() < 0.001);
+        let mut full = AtmosMixture::from_gas_string("TEMP=293.15;n2=2000;").unwrap();
+        full.volume = float(200.0);
+        assert_eq!(scrubber.process(&mut full, &mut turf), Transfer::default());
+        assert!(matches!(scrubber.configure("volume_rate=-100;"), Err(Error::InvalidArgument(..))));
+        assert!(matches!(scrubber.configure("volume_rate=NaN;"), Err(Error::InvalidArgument(..))));
+    }
+    #[test]
+    fn test_process_all() {
+        let mut pipenets = PipeNets::default();
+        let mut mixtures = MixtureRegistry::default();
+        let supply = pipenets.add_pipe(float(200.0), &[]).unwrap();
+        let waste = pipenets.add_pipe(float(200.0), &[]).unwrap();
+        pipenets.air_mut(supply).unwrap().parse_gas_string("TEMP=293.15;o2=400;n2=1600;").unwrap();
+        let room = mixtures.create(AtmosMixture::from_gas_string("TEMP=293.15;o2=10;n2=40;co2=5;").unwrap());
+        let next_door = mixtures.create(AtmosMixture::from_gas_string("TEMP=293.15;o2=22;n2=82;co2=5;").unwrap());
+        let mut devices = DeviceRegistry::default();
+        devices.configure_vent("vent", "").unwrap();
+        devices.configure_scrubber("scrubber", "widenet=1;").unwrap();
+        let report = devices.process_all(&mut pipenets, &mut mixtures, &format!("vent:{}:{};scrubber:{}:{},{};", supply, room, waste, room, next_door)).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("vent$"));
+        let scrubbed: Float = lines[1].split('$').nth(1).unwrap().parse().unwrap();
+        assert!((scrubbed - 0.8).abs() < 0.001);
+        assert!((pipenets.air(waste).unwrap().gases[gas_index("co2")] - 0.8).abs() < 0.001);
+        devices.configure_scrubber("scrubber", "widenet=0;").unwrap();
+        devices.process_all(&mut pipenets, &mut mixtures, &format!("scrubber:{}:{},{};", waste, room, next_door)).unwrap();
+        assert!((mixtures.get(next_door).unwrap().gases[gas_index("co2")] - 4.6).abs() < 0.001);
+        assert!(devices.process_all(&mut pipenets, &mut mixtures, &format!("pump:{}:{};", waste, room)).is_err());
+        assert!(devices.process_all(&mut pipenets, &mut mixtures, &format!("vent:{}:;", waste)).is_err());
+        assert!(matches!(devices.configure_vent("vent:2", ""), Err(Error::InvalidArgument(..))));
+        assert!(matches!(devices.configure_scrubber("scrubber$", ""), Err(Error::InvalidArgument(..))));
+        devices.remove("vent").unwrap();
+        assert!(devices.get("vent").is_err());
+        assert!(!devices.is_empty());
+    }
+}
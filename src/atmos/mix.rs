@@ -1,127 +1,133 @@
 use atmos::gases::*;
+use atmos::lanes;
 
 use atmos::constants::*;
 
+use error::{Error, Result};
+
+#[derive(Clone)]
 pub struct AtmosMixture {
-    pub gases: [f32;GASES.len()],
-    pub temperature: f32,
-    pub volume: f32,
+    pub gases: Vec<Float>,
+    pub temperature: Float,
+    pub volume: Float,
 }
 
 impl Default for AtmosMixture {
     fn default() -> AtmosMixture {
         AtmosMixture {
-            gases: [0.0;GASES.len()], 
-            temperature: 293.15, 
-            volume: 2500.0 
+            gases: vec![float(0.0);with_gases(|gases| gases.padded_len())], 
+            temperature: float(293.15), 
+            volume: float(2500.0) 
         }
     }
 }
 
 impl AtmosMixture {
     pub fn empty(&mut self) {
-        self.gases.iter_mut().for_each(|x| *x=0.0);
-        self.temperature = 293.15;
-    }
-    pub fn parse_gas_string(&mut self,gas_string: &str) {
+        self.gases.iter_mut().for_each(|x| *x=float(0.0));
+        self.temperature = float(293.15);
+    }
+    /// Reads a `TEMP=...;o2=...;` params string. Gas ids this library doesn't know
+    /// about are skipped, since DM may well have gases we don't; malformed entries
+    /// and unparseable numbers are errors.
+    pub fn parse_gas_string(&mut self,gas_string: &str) -> Result<()> {
+        let gases = gases();
         self.empty();
-        let arg_split = gas_string.split(";");
-        for entry in arg_split {
-            let mut indiv_split = entry.split("=");
-            let name = indiv_split.next();
-            let value = indiv_split.next();
-            if name.is_some() && value.is_some() {
-                if name == Some("TEMP") {
-                    self.temperature = value.unwrap().parse().unwrap();
-                } else if GASES_BY_ID.contains_key(name.unwrap()) {
-                    self.gases[GASES_BY_ID[name.unwrap()]] = value.unwrap().parse().unwrap();
+        for entry in gas_string.split(';') {
+            if entry.is_empty() {
+                continue;
+            }
+            let mut indiv_split = entry.splitn(2,'=');
+            match (indiv_split.next(), indiv_split.next()) {
+                (Some("TEMP"), Some(value)) => {
+                    self.temperature = value.trim().parse().map_err(|_| Error::InvalidNumber(value.to_string()))?;
                 }
+                (Some(name), Some(value)) => {
+                    if let Some(index) = gases.index_of(name) {
+                        self.gases[index] = value.trim().parse().map_err(|_| Error::InvalidNumber(value.to_string()))?;
+                    }
+                }
+                _ => return Err(Error::MalformedEntry(entry.to_string())),
             }
         }
+        Ok(())
+    }
+    pub fn from_gas_string(gas_string: &str) -> Result<AtmosMixture> {
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string(gas_string)?;
+        Ok(mix)
     }
-    pub fn heat_capacity(&self) -> f32 {
-        return self.gases.iter().enumerate().fold(0.0,|x,(i,amount)| x + GASES[i].heat_capacity(*amount));
+    pub fn heat_capacity(&self) -> Float {
+        return with_gases(|gases| lanes::dot(&self.gases, gases.specific_heats()));
     }
-    pub fn total_moles(&self) -> f32 {
-        return self.gases.iter().fold(0.0,|x,amount| x + amount);
+    pub fn total_moles(&self) -> Float {
+        return lanes::sum(&self.gases);
     }
-    pub fn pressure(&self) -> f32 {
+    pub fn pressure(&self) -> Float {
         return (self.total_moles()*self.temperature*GAS_CONSTANT)/self.volume;
     }
-    pub fn thermal_energy(&self) -> f32 {
+    pub fn thermal_energy(&self) -> Float {
         return self.temperature * self.heat_capacity()
     }
     pub fn merge(&mut self, other: AtmosMixture) {
-        self.temperature = (self.thermal_energy() + other.thermal_energy()) / (self.heat_capacity() + other.heat_capacity());
-        for (i,amount) in self.gases.iter_mut().enumerate() {
-            *amount = *amount + other.gases[i];
+        let combined_heat_capacity = self.heat_capacity() + other.heat_capacity();
+        if combined_heat_capacity > 0.0 {
+            self.temperature = (self.thermal_energy() + other.thermal_energy()) / combined_heat_capacity;
         }
+        lanes::add_assign(&mut self.gases, &other.gases);
     }
-    pub fn remove(&mut self, amount: f32) -> AtmosMixture {
+    pub fn remove(&mut self, amount: Float) -> AtmosMixture {
         use std::cmp::min;
         use super::float_ord::FloatOrd;
         let mut removed : AtmosMixture = Default::default();
         let cached_total = self.total_moles();
-        let FloatOrd(real_amount) = min(FloatOrd(cached_total),FloatOrd(amount));
-        for (i,this_amount) in self.gases.iter_mut().enumerate() {
-            removed.gases[i] = (*this_amount / cached_total)*real_amount;
-            *this_amount -= removed.gases[i];
+        removed.temperature = self.temperature;
+        if cached_total <= 0.0 {
+            return removed;
         }
+        let FloatOrd(real_amount) = min(FloatOrd(cached_total),FloatOrd(amount));
+        lanes::split_off(&mut self.gases, &mut removed.gases, cached_total, real_amount);
         return removed;
     }
-    pub fn remove_ratio(&mut self, ratio: f32) -> AtmosMixture {
+    pub fn remove_ratio(&mut self, ratio: Float) -> AtmosMixture {
         use std::cmp::min;
         use super::float_ord::FloatOrd;
         let mut removed : AtmosMixture = Default::default();
-        let FloatOrd(real_ratio) = min(FloatOrd(1.0),FloatOrd(ratio));
-        for (i,this_amount) in self.gases.iter_mut().enumerate() {
-            removed.gases[i] = *this_amount * real_ratio;
-            *this_amount -= removed.gases[i];
-        }
+        let FloatOrd(real_ratio) = min(FloatOrd(float(1.0)),FloatOrd(ratio));
+        removed.temperature = self.temperature;
+        lanes::split_off(&mut self.gases, &mut removed.gases, float(1.0), real_ratio);
         return removed;
     }
-    pub fn share(&mut self,other : &mut AtmosMixture, adjacent_turfs: i16) -> (f32,f32) { // returns pressure, moles
+    /// Takes every mole of the listed gases out into a mixture of its own, at the
+    /// same temperature.
+    pub fn remove_gases(&mut self, gases: &[usize]) -> AtmosMixture {
+        let mut removed = AtmosMixture { temperature: self.temperature, ..Default::default() };
+        for &index in gases {
+            removed.gases[index] = self.gases[index];
+            self.gases[index] = float(0.0);
+        }
+        removed
+    }
+    pub fn share(&mut self,other : &mut AtmosMixture, adjacent_turfs: i16) -> (Float,Float) { // returns pressure, moles
         let old_self_heat_capacity = self.heat_capacity();
         let old_other_heat_capacity = other.heat_capacity();
-        let mut heat_capacity_self_to_other = 0.0;
-        let mut heat_capacity_other_to_self = 0.0;
-        let mut moved_moles = 0.0;
-        let mut abs_moved_moles = 0.0;
-        for (i,this_amount) in self.gases.iter_mut().enumerate() {
-            let delta = (*this_amount - other.gases[i])/(adjacent_turfs+1) as f32;
-            let heat_cap_delta = GASES[i].heat_capacity(delta);
-            if delta > 0.0
-            {
-                heat_capacity_self_to_other += heat_cap_delta;
-            }
-            else
-            {
-                heat_capacity_other_to_self -= heat_cap_delta;
-            }
-            let abs_moles_this_time=delta.abs();
-            if abs_moles_this_time>0.1
-            {
-                *this_amount -= delta;
-                other.gases[i] += delta;
-                moved_moles += delta;
-                abs_moved_moles += abs_moles_this_time;
-            }
-        }
+        let lanes::ShareTotals { heat_capacity_self_to_other, heat_capacity_other_to_self, moved_moles, abs_moved_moles } =
+            with_gases(|gases| lanes::share(&mut self.gases, &mut other.gases, gases.specific_heats(), float((adjacent_turfs+1) as Constant)));
         if abs_moved_moles > 0.1 {
             let new_self_heat_capacity = old_self_heat_capacity + heat_capacity_other_to_self - heat_capacity_self_to_other;
             let new_other_heat_capacity = old_other_heat_capacity + heat_capacity_self_to_other - heat_capacity_other_to_self;
             self.temperature = (old_self_heat_capacity * self.temperature - heat_capacity_self_to_other * self.temperature + heat_capacity_other_to_self * self.temperature) / new_self_heat_capacity;
             other.temperature = (old_other_heat_capacity * other.temperature - heat_capacity_other_to_self * other.temperature + heat_capacity_self_to_other * other.temperature) / new_other_heat_capacity;
-            self.temperature_share(other,OPEN_HEAT_TRANSFER_COEFFICIENT);
+            self.temperature_share(other,float(OPEN_HEAT_TRANSFER_COEFFICIENT));
             return ((self.temperature*(self.total_moles() + moved_moles)-other.temperature*(other.total_moles() - moved_moles) * GAS_CONSTANT) / self.volume,abs_moved_moles);
         }
         else
         {
-            self.temperature_share(other,OPEN_HEAT_TRANSFER_COEFFICIENT);
-            return (0.0, 0.0);
+            self.temperature_share(other,float(OPEN_HEAT_TRANSFER_COEFFICIENT));
+            return (float(0.0), float(0.0));
         }
     }
-    pub fn temperature_share(&mut self, other: &mut AtmosMixture, conduction_coefficient: f32) -> f32 {
+    pub fn temperature_share(&mut self, other: &mut AtmosMixture, conduction_coefficient: Float) -> Float {
         use std::cmp::max;
         use super::float_ord::FloatOrd;
         let temperature_delta = self.temperature-other.temperature;
@@ -131,42 +137,53 @@ impl AtmosMixture {
         }
         let self_heat_capacity = self.heat_capacity();
         let other_heat_capacity = other.heat_capacity();
+        if self_heat_capacity <= 0.0 || other_heat_capacity <= 0.0
+        {
+            return other.temperature;
+        }
         let heat = conduction_coefficient*temperature_delta*(self_heat_capacity*other_heat_capacity/(self_heat_capacity+other_heat_capacity));
-        self.temperature = max(FloatOrd(self.temperature - heat/self_heat_capacity), FloatOrd(CMB_TEMP)).0;
-        other.temperature = max(FloatOrd(other.temperature + heat/other_heat_capacity), FloatOrd(CMB_TEMP)).0;
+        self.temperature = max(FloatOrd(self.temperature - heat/self_heat_capacity), FloatOrd(float(CMB_TEMP))).0;
+        other.temperature = max(FloatOrd(other.temperature + heat/other_heat_capacity), FloatOrd(float(CMB_TEMP))).0;
         return other.temperature;
     }
-    pub fn temperature_share_turf(&mut self, conduction_coefficient: f32, other_temperature: f32, other_heat_capacity: f32) -> f32 {
+    pub fn temperature_share_turf(&mut self, conduction_coefficient: Float, other_temperature: Float, other_heat_capacity: Float) -> Float {
         use std::cmp::max;
         use super::float_ord::FloatOrd;
         let temperature_delta = self.temperature-other_temperature;
         let self_heat_capacity = self.heat_capacity();
+        if self_heat_capacity <= 0.0 || other_heat_capacity <= 0.0
+        {
+            return other_temperature;
+        }
         let heat = conduction_coefficient*temperature_delta*(self_heat_capacity*other_heat_capacity/(self_heat_capacity+other_heat_capacity));
-        self.temperature = max(FloatOrd(self.temperature - heat/self_heat_capacity), FloatOrd(CMB_TEMP)).0;
-        return max(FloatOrd(other_temperature + heat/other_heat_capacity), FloatOrd(CMB_TEMP)).0;
+        self.temperature = max(FloatOrd(self.temperature - heat/self_heat_capacity), FloatOrd(float(CMB_TEMP))).0;
+        return max(FloatOrd(other_temperature + heat/other_heat_capacity), FloatOrd(float(CMB_TEMP))).0;
     }
     pub fn react(&mut self) -> Vec<Vec<String>>
     {
         use atmos::reaction::*;
         let mut ret = [].to_vec();
-        for reaction in REACTIONS {
-            let (reaction_return, reaction_vec) = reaction(self);
-            if reaction_return == ReactionResult::StopReactions {
-                return [].to_vec();
-            }
-            else {
-                ret.push(reaction_vec);
+        for reaction in reactions().iter() {
+            let (reaction_return, reaction_vec) = reaction.react(self);
+            match reaction_return {
+                ReactionResult::StopReactions => return [].to_vec(),
+                ReactionResult::Reacting if reaction.exclusive => {
+                    ret.push(reaction_vec);
+                    break;
+                }
+                _ => ret.push(reaction_vec),
             }
         }
         return ret;
     }
     pub fn to_params(&mut self) -> String
     {
+        let gases = gases();
         let mut params : String = format!("TEMP={};",self.temperature);
         for (i,this_amount) in self.gases.iter_mut().enumerate()
         {
             if this_amount > &mut 0.0 {
-                params = format!("{}{}={};",params,GASES[i].id,this_amount);
+                params = format!("{}{}={};",params,gases.get(i).id,this_amount);
             }
         }
         return params;
@@ -180,20 +197,31 @@ mod tests {
     #[test]
     fn test_gas_parsing() {
         let mut mix : AtmosMixture = Default::default();
-        mix.parse_gas_string("TEMP=293.15;o2=22;n2=82;");
-        assert!(mix.gases[GASES_BY_ID["o2"]] == 22.0);
-        mix.parse_gas_string("TEMP=304.3;plasma=40;tritium=40;miasma=30;no2=40;");
+        mix.parse_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap();
+        assert!(mix.gases[gas_index("o2")] == 22.0);
+        mix.parse_gas_string("TEMP=304.3;plasma=40;tritium=40;miasma=30;no2=40;").unwrap();
         assert_eq!(mix.to_params(),"TEMP=304.3;plasma=40;tritium=40;miasma=30;no2=40;");
-        mix.parse_gas_string("TEMP=303.4;palsma=30;tritium=30;"); // sic
+        mix.parse_gas_string("TEMP=303.4;palsma=30;tritium=30;").unwrap(); // sic
         assert_eq!(mix.to_params(),"TEMP=303.4;tritium=30;");
+        assert!(mix.parse_gas_string("TEMP=hot;o2=22;").is_err());
+        assert!(mix.parse_gas_string("o2=22;n2").is_err());
     }
     #[test]
     fn test_sharing() {
         let mut mix1 : AtmosMixture = Default::default();
         let mut mix2 : AtmosMixture = Default::default();
-        mix2.parse_gas_string("TEMP=304.3;plasma=40;tritium=40;miasma=30;no2=40;");
+        mix2.parse_gas_string("TEMP=304.3;plasma=40;tritium=40;miasma=30;no2=40;").unwrap();
         let initial_total_moles = mix1.total_moles() + mix2.total_moles();
         mix1.share(&mut mix2,1);
         assert_eq!(mix1.total_moles()+mix2.total_moles(),initial_total_moles);
     }
+    #[cfg(feature = "f64")]
+    #[test]
+    fn test_double_precision() {
+        // at fusion temperatures an f32 can't tell a joule either way
+        let mut mix = AtmosMixture::from_gas_string("TEMP=1000000000;n2=100;").unwrap();
+        let energy = mix.thermal_energy();
+        mix.temperature = (energy + 1.0) / mix.heat_capacity();
+        assert_eq!((mix.thermal_energy() - energy).round(), 1.0);
+    }
 }
\ No newline at end of file
@@ -1,7 +1,28 @@
+#[cfg(not(feature = "fixed"))]
 extern crate float_ord;
-extern crate phf;
+#[cfg(feature = "fixed")]
+use self::fixed::float_ord;
 
+pub mod air_alarm;
+pub mod analyzer;
+pub mod batch;
+pub mod binary;
 pub mod constants;
+pub mod data_reaction;
+pub mod excited_group;
+#[cfg(feature = "fixed")]
+pub mod fixed;
+pub mod formula;
 pub mod gases;
+pub mod grid;
+pub mod he_pipe;
+pub mod lanes;
+pub mod machinery;
 pub mod mix;
-pub mod reaction;
\ No newline at end of file
+pub mod monstermos;
+pub mod pipenet;
+pub mod pool;
+pub mod reaction;
+pub mod registry;
+pub mod tick_job;
+pub mod zas;
\ No newline at end of file
@@ -0,0 +1,366 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use atmos::grid::{reverse_dir, Grid, CARDINALS};
+use atmos::mix::AtmosMixture;
+
+use atmos::constants::*;
+
+/// How hard, and which way, air moved out of a turf during equalization.
+/// DM uses these to throw whatever is standing on the turf.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PressureDifference {
+    pub turf: usize,
+    pub dir: u8,
+    pub moles: Float,
+}
+
+#[derive(Default)]
+pub struct EqualizeResult {
+    /// Every turf the flood fill reached, whether or not anything moved.
+    pub region: Vec<usize>,
+    pub pressure_differences: Vec<PressureDifference>,
+    pub decompressed: bool,
+}
+
+#[derive(Clone, Default)]
+struct EqualizeInfo {
+    mole_delta: Float,
+    // moles this turf gives in each of CARDINALS; negative means it receives
+    transfer: [Float; 4],
+    curr_transfer_dir: Option<usize>,
+    curr_transfer_amount: Float,
+    fast_done: bool,
+}
+
+fn dir_index(dir: u8) -> usize {
+    CARDINALS.iter().position(|&cardinal| cardinal == dir).unwrap()
+}
+
+/// Flood fills open turfs from `start`, stopping at `limit` turfs. Space turfs
+/// end the fill on their side and are returned separately.
+fn find_region(grid: &Grid, start: usize, limit: usize) -> (Vec<usize>, Vec<usize>) {
+    let mut region = vec![start];
+    let mut space = Vec::new();
+    let mut seen: HashSet<usize> = HashSet::new();
+    seen.insert(start);
+    let mut i = 0;
+    while i < region.len() && region.len() < limit {
+        for (_, other) in grid.adjacent_dirs(region[i]) {
+            if seen.insert(other) {
+                if grid.turfs[other].space {
+                    space.push(other);
+                } else if region.len() < limit {
+                    region.push(other);
+                }
+            }
+        }
+        i += 1;
+    }
+    (region, space)
+}
+
+struct Equalizer<'a> {
+    mixtures: &'a mut [AtmosMixture],
+    adjacency: &'a [Vec<(u8, usize)>],
+    info: Vec<EqualizeInfo>,
+    differences: HashMap<usize, PressureDifference>,
+}
+
+impl<'a> Equalizer<'a> {
+    fn region_neighbours(&self, index: usize, space: &[bool]) -> Vec<(usize, usize)> {
+        self.adjacency[index]
+            .iter()
+            .filter(|&&(_, other)| !space[other])
+            .map(|&(dir, other)| (dir_index(dir), other))
+            .collect()
+    }
+    fn step(&self, index: usize, dir: usize) -> usize {
+        self.adjacency[index].iter().find(|&&(other_dir, _)| other_dir == CARDINALS[dir]).unwrap().1
+    }
+    fn adjust_eq_movement(&mut self, index: usize, dir: usize, amount: Float) {
+        let other = self.step(index, dir);
+        self.info[index].transfer[dir] += amount;
+        self.info[other].transfer[dir ^ 1] -= amount;
+    }
+    fn consider_pressure_difference(&mut self, index: usize, dir: u8, moles: Float) {
+        let difference = self.differences.entry(index).or_insert(PressureDifference { turf: index, dir, moles: float(0.0) });
+        if moles > difference.moles {
+            difference.dir = dir;
+            difference.moles = moles;
+        }
+    }
+    /// Spreads every giver's surplus evenly over its neighbours, largest givers first.
+    /// Gets most of a gradient done without any searching.
+    fn fast_path(&mut self, region: &[usize], space: &[bool]) {
+        let mut order = region.to_vec();
+        order.sort_by(|&a, &b| self.info[b].mole_delta.partial_cmp(&self.info[a].mole_delta).unwrap_or(::std::cmp::Ordering::Equal));
+        for index in order {
+            let mole_delta = self.info[index].mole_delta;
+            if mole_delta > 0.0 {
+                let eligible: Vec<(usize, usize)> = self.region_neighbours(index, space).into_iter().filter(|&(_, other)| !self.info[other].fast_done).collect();
+                if !eligible.is_empty() {
+                    let moles_to_move = mole_delta / float(eligible.len() as Constant);
+                    for (dir, other) in eligible {
+                        self.adjust_eq_movement(index, dir, moles_to_move);
+                        self.info[index].mole_delta -= moles_to_move;
+                        self.info[other].mole_delta += moles_to_move;
+                    }
+                }
+            }
+            self.info[index].fast_done = true;
+        }
+    }
+    /// Settles whatever the fast path left over by searching outwards from each turf
+    /// on the rarer side (givers or takers) and routing moles back along the search tree.
+    fn slow_path(&mut self, region: &[usize], space: &[bool]) {
+        let givers: Vec<usize> = region.iter().cloned().filter(|&index| self.info[index].mole_delta > 0.0).collect();
+        let takers: Vec<usize> = region.iter().cloned().filter(|&index| self.info[index].mole_delta < 0.0).collect();
+        // sign is +1 when searching from givers, -1 when searching from takers
+        let (sources, sign) = if givers.len() <= takers.len() { (givers, 1.0) } else { (takers, -1.0) };
+        for source in sources {
+            self.info[source].curr_transfer_dir = None;
+            self.info[source].curr_transfer_amount = float(0.0);
+            let mut queue = vec![source];
+            let mut visited: HashSet<usize> = HashSet::new();
+            visited.insert(source);
+            let mut i = 0;
+            while i < queue.len() && self.info[source].mole_delta * sign > 0.0 {
+                let current = queue[i];
+                i += 1;
+                for (dir, other) in self.region_neighbours(current, space) {
+                    if !visited.insert(other) {
+                        continue;
+                    }
+                    let source_delta = self.info[source].mole_delta;
+                    let other_delta = self.info[other].mole_delta;
+                    let moved = if other_delta * sign < 0.0 {
+                        // other is on the opposite side; settle as much as we can
+                        if (other_delta * sign).abs() > (source_delta * sign).abs() { source_delta } else { -other_delta }
+                    } else {
+                        float(0.0)
+                    };
+                    {
+                        let info = &mut self.info[other];
+                        info.curr_transfer_dir = Some(dir ^ 1);
+                        info.curr_transfer_amount = -moved;
+                        info.mole_delta += moved;
+                    }
+                    self.info[source].mole_delta -= moved;
+                    queue.push(other);
+                    if self.info[source].mole_delta * sign <= 0.0 {
+                        break;
+                    }
+                }
+            }
+            // walk back from the edge of the search, handing each amount towards the source
+            for &index in queue.iter().rev() {
+                let (dir, amount) = (self.info[index].curr_transfer_dir, self.info[index].curr_transfer_amount);
+                if let Some(dir) = dir {
+                    if amount != 0.0 {
+                        self.adjust_eq_movement(index, dir, amount);
+                        let parent = self.step(index, dir);
+                        self.info[parent].curr_transfer_amount += amount;
+                        self.info[index].curr_transfer_amount = float(0.0);
+                    }
+                }
+            }
+        }
+    }
+    /// Actually moves the gas a turf was assigned to give. A turf that doesn't have
+    /// enough yet first pulls in whatever its own neighbours owe it.
+    fn finalize(&mut self, index: usize) {
+        let transfer = ::std::mem::replace(&mut self.info[index].transfer, [float(0.0); 4]);
+        for dir in 0..4 {
+            let amount = transfer[dir];
+            if amount <= 0.0 {
+                continue;
+            }
+            if self.mixtures[index].total_moles() < amount {
+                for (incoming, &incoming_amount) in transfer.iter().enumerate() {
+                    if incoming_amount < 0.0 {
+                        let other = self.step(index, incoming);
+                        self.finalize(other);
+                    }
+                }
+            }
+            let other = self.step(index, dir);
+            self.info[other].transfer[dir ^ 1] = float(0.0);
+            let removed = self.mixtures[index].remove(amount);
+            self.mixtures[other].merge(removed);
+            self.consider_pressure_difference(index, CARDINALS[dir], amount);
+        }
+    }
+}
+
+/// Equalizes total moles across a region of turf mixtures in a single step, moving
+/// gas along a spanning tree instead of letting it diffuse one neighbour at a time.
+/// `adjacency` lists each turf's open neighbours with the direction they're in, at
+/// most one per direction, and `space` marks the turfs that are space. The region
+/// is every other turf, and should be connected; if it touches space it's
+/// explosively decompressed instead. Indices in the result are into `mixtures`.
+pub fn equalize_region(mixtures: &mut [AtmosMixture], adjacency: &[Vec<(u8, usize)>], space: &[bool]) -> EqualizeResult {
+    let region: Vec<usize> = (0..mixtures.len()).filter(|&index| !space[index]).collect();
+    if region.len() < mixtures.len() {
+        return depressurize_region(mixtures, adjacency, space);
+    }
+    let mut result = EqualizeResult { region, ..Default::default() };
+    if result.region.len() < 2 {
+        return result;
+    }
+    let total_moles: Float = result.region.iter().map(|&index| mixtures[index].total_moles()).sum();
+    let average_moles = total_moles / float(result.region.len() as Constant);
+    let info = mixtures
+        .iter()
+        .map(|mix| EqualizeInfo {
+            mole_delta: mix.total_moles() - average_moles,
+            ..Default::default()
+        })
+        .collect();
+    let mut equalizer = Equalizer { mixtures, adjacency, info, differences: HashMap::new() };
+    equalizer.fast_path(&result.region, space);
+    equalizer.slow_path(&result.region, space);
+    for &index in &result.region {
+        equalizer.finalize(index);
+    }
+    let mut differences: Vec<PressureDifference> = equalizer.differences.into_values().collect();
+    differences.sort_by_key(|difference| difference.turf);
+    result.pressure_differences = differences;
+    result
+}
+
+/// Vents every turf in the region straight out to the space turfs next to it. Each
+/// turf's pressure difference is all the gas that flowed through it on the way
+/// out, pointing towards the breach.
+pub fn depressurize_region(mixtures: &mut [AtmosMixture], adjacency: &[Vec<(u8, usize)>], space: &[bool]) -> EqualizeResult {
+    let region: Vec<usize> = (0..mixtures.len()).filter(|&index| !space[index]).collect();
+    let mut towards_space: HashMap<usize, u8> = HashMap::new();
+    let mut progression = Vec::new();
+    let mut queue: VecDeque<usize> = (0..mixtures.len()).filter(|&index| space[index]).collect();
+    let mut visited: HashSet<usize> = queue.iter().cloned().collect();
+    while let Some(current) = queue.pop_front() {
+        for &(dir, other) in &adjacency[current] {
+            if visited.insert(other) {
+                towards_space.insert(other, reverse_dir(dir));
+                progression.push(other);
+                queue.push_back(other);
+            }
+        }
+    }
+    let mut flow: HashMap<usize, Float> = HashMap::new();
+    let mut differences = Vec::new();
+    for &index in progression.iter().rev() {
+        let moles = mixtures[index].total_moles() + flow.get(&index).cloned().unwrap_or(float(0.0));
+        let dir = towards_space[&index];
+        let next = adjacency[index].iter().find(|&&(other_dir, _)| other_dir == dir).unwrap().1;
+        if !space[next] {
+            *flow.entry(next).or_insert(float(0.0)) += moles;
+        }
+        differences.push(PressureDifference { turf: index, dir, moles });
+        mixtures[index].empty();
+    }
+    differences.sort_by_key(|difference| difference.turf);
+    EqualizeResult {
+        region,
+        pressure_differences: differences,
+        decompressed: true,
+    }
+}
+
+/// Runs `f` over the grid turfs in `region` and `space` as a standalone region,
+/// then writes the region's mixtures back and maps the result onto grid indices.
+fn on_grid<F>(grid: &mut Grid, region: Vec<usize>, space: Vec<usize>, f: F) -> EqualizeResult
+where
+    F: FnOnce(&mut [AtmosMixture], &[Vec<(u8, usize)>], &[bool]) -> EqualizeResult,
+{
+    let region_len = region.len();
+    let turfs: Vec<usize> = region.into_iter().chain(space).collect();
+    let local: HashMap<usize, usize> = turfs.iter().enumerate().map(|(cell, &index)| (index, cell)).collect();
+    let adjacency: Vec<Vec<(u8, usize)>> = turfs
+        .iter()
+        .map(|&index| grid.adjacent_dirs(index).into_iter().filter_map(|(dir, other)| local.get(&other).map(|&cell| (dir, cell))).collect())
+        .collect();
+    let is_space: Vec<bool> = (0..turfs.len()).map(|cell| cell >= region_len).collect();
+    let mut mixtures: Vec<AtmosMixture> = turfs.iter().map(|&index| grid.turfs[index].mix.clone()).collect();
+    let mut result = f(&mut mixtures, &adjacency, &is_space);
+    for &cell in &result.region {
+        grid.turfs[turfs[cell]].mix = ::std::mem::take(&mut mixtures[cell]);
+    }
+    result.region = result.region.iter().map(|&cell| turfs[cell]).collect();
+    for difference in &mut result.pressure_differences {
+        difference.turf = turfs[difference.turf];
+    }
+    result.pressure_differences.sort_by_key(|difference| difference.turf);
+    result
+}
+
+/// `equalize_region` over the grid region around `start`. Regions that reach space
+/// are explosively decompressed instead.
+pub fn equalize_pressure(grid: &mut Grid, start: usize) -> EqualizeResult {
+    let (region, space) = find_region(grid, start, MONSTERMOS_TURF_LIMIT);
+    if !space.is_empty() {
+        return explosively_depressurize(grid, start);
+    }
+    on_grid(grid, region, space, equalize_region)
+}
+
+/// Vents every turf connected to `start` (up to the hard limit) straight out to
+/// space, like `depressurize_region`.
+pub fn explosively_depressurize(grid: &mut Grid, start: usize) -> EqualizeResult {
+    let (region, space) = find_region(grid, start, MONSTERMOS_HARD_TURF_LIMIT);
+    on_grid(grid, region, space, depressurize_region)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atmos::grid::{EAST, WEST};
+
+    #[test]
+    fn test_equalize_room() {
+        let mut grid = Grid::new(4, 3);
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=293.15;o2=240;n2=960;").unwrap();
+        grid.set_mixture(0, 0, mix).unwrap();
+        let result = equalize_pressure(&mut grid, 0);
+        assert!(!result.decompressed);
+        assert_eq!(result.region.len(), 12);
+        for turf in &grid.turfs {
+            assert!((turf.mix.total_moles() - 100.0).abs() < 0.01);
+        }
+        let first = result.pressure_differences.iter().find(|difference| difference.turf == 0).unwrap();
+        assert!(first.moles > 0.0);
+    }
+    #[test]
+    fn test_equalize_region() {
+        use atmos::grid::{NORTH, SOUTH};
+        // 0 is west of 1, and 2 is north of 1
+        let adjacency = vec![vec![(EAST, 1)], vec![(WEST, 0), (NORTH, 2)], vec![(SOUTH, 1)]];
+        let mut mixtures = vec![AtmosMixture::from_gas_string("TEMP=293.15;n2=90;").unwrap(), Default::default(), Default::default()];
+        let result = equalize_region(&mut mixtures, &adjacency, &[false; 3]);
+        assert!(!result.decompressed);
+        assert!(mixtures.iter().all(|mix| (mix.total_moles() - 30.0).abs() < 0.01));
+        assert_eq!(result.pressure_differences.len(), 2);
+        assert_eq!((result.pressure_differences[1].dir, result.pressure_differences[1].moles), (NORTH, float(30.0)));
+        let result = equalize_region(&mut mixtures, &adjacency, &[false, false, true]);
+        assert!(result.decompressed);
+        assert_eq!(result.region, vec![0, 1]);
+        assert!(mixtures[..2].iter().all(|mix| mix.total_moles() == 0.0));
+    }
+    #[test]
+    fn test_decompression() {
+        let mut grid = Grid::new(4, 1);
+        for x in 0..3 {
+            let mut mix : AtmosMixture = Default::default();
+            mix.parse_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap();
+            grid.set_mixture(x, 0, mix).unwrap();
+        }
+        grid.set_space(3, 0, true).unwrap();
+        let result = equalize_pressure(&mut grid, 0);
+        assert!(result.decompressed);
+        assert!(grid.turfs.iter().all(|turf| turf.mix.total_moles() == 0.0));
+        let nearest = result.pressure_differences.iter().find(|difference| difference.turf == 2).unwrap();
+        assert_eq!(nearest.dir, EAST);
+        assert!((nearest.moles - 312.0).abs() < 0.01);
+        assert!(result.pressure_differences.iter().all(|difference| difference.dir != WEST));
+    }
+}
@@ -0,0 +1,286 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use atmos::constants::*;
+use atmos::mix::AtmosMixture;
+use atmos::registry::Handle;
+
+use error::{Error, Result};
+
+/// One pipe segment. Its gas lives in its network's mixture.
+pub struct Pipe {
+    pub volume: Float,
+    pub connections: Vec<Handle>,
+    network: usize,
+}
+
+struct PipeSlot {
+    generation: u32,
+    pipe: Option<Pipe>,
+}
+
+/// Connected pipes sharing one mixture, whose volume is the sum of theirs.
+pub struct PipeNetwork {
+    pub pipes: Vec<Handle>,
+    pub air: AtmosMixture,
+}
+
+/// Every pipe and the networks they make up. Pipes are addressed by handle, with
+/// the same stale-handle checks as mixtures. A machine's node is the handle of the
+/// pipe it's connected to, and reaches the network's air through that.
+#[derive(Default)]
+pub struct PipeNets {
+    slots: Vec<PipeSlot>,
+    free: Vec<u32>,
+    networks: Vec<Option<PipeNetwork>>,
+    free_networks: Vec<usize>,
+}
+
+impl PipeNets {
+    pub fn is_empty(&self) -> bool {
+        self.free.len() == self.slots.len()
+    }
+    pub fn network_count(&self) -> usize {
+        self.networks.len() - self.free_networks.len()
+    }
+    pub fn pipe(&self, handle: Handle) -> Result<&Pipe> {
+        match self.slots.get(handle.index()) {
+            Some(&PipeSlot { generation, pipe: Some(ref pipe) }) if generation == handle.generation() => Ok(pipe),
+            _ => Err(Error::InvalidPipe(handle.to_string())),
+        }
+    }
+    fn pipe_mut(&mut self, handle: Handle) -> Result<&mut Pipe> {
+        match self.slots.get_mut(handle.index()) {
+            Some(&mut PipeSlot { generation, pipe: Some(ref mut pipe) }) if generation == handle.generation() => Ok(pipe),
+            _ => Err(Error::InvalidPipe(handle.to_string())),
+        }
+    }
+    /// The network `pipe` is part of.
+    pub fn network(&self, pipe: Handle) -> Result<&PipeNetwork> {
+        let network = self.pipe(pipe)?.network;
+        Ok(self.networks[network].as_ref().unwrap())
+    }
+    fn network_mut(&mut self, pipe: Handle) -> Result<&mut PipeNetwork> {
+        let network = self.pipe(pipe)?.network;
+        Ok(self.networks[network].as_mut().unwrap())
+    }
+    pub fn air(&self, pipe: Handle) -> Result<&AtmosMixture> {
+        Ok(&self.network(pipe)?.air)
+    }
+    pub fn air_mut(&mut self, pipe: Handle) -> Result<&mut AtmosMixture> {
+        Ok(&mut self.network_mut(pipe)?.air)
+    }
+    fn create_network(&mut self, network: PipeNetwork) -> usize {
+        match self.free_networks.pop() {
+            Some(index) => {
+                self.networks[index] = Some(network);
+                index
+            }
+            None => {
+                self.networks.push(Some(network));
+                self.networks.len() - 1
+            }
+        }
+    }
+    fn total_volume(&self, pipes: &[Handle]) -> Float {
+        pipes.iter().map(|&pipe| self.pipe(pipe).map(|pipe| pipe.volume).unwrap_or(float(0.0))).sum()
+    }
+    /// Adds an empty pipe of the given volume and connects it to `connections`,
+    /// merging their networks into one.
+    pub fn add_pipe(&mut self, volume: Float, connections: &[Handle]) -> Result<Handle> {
+        if volume <= 0.0 || !volume.is_finite() {
+            return Err(Error::InvalidArgument("volume".to_string(), volume.to_string()));
+        }
+        for &connection in connections {
+            self.pipe(connection)?;
+        }
+        let air = AtmosMixture { volume, ..Default::default() };
+        let network = self.create_network(PipeNetwork { pipes: Vec::new(), air });
+        let pipe = Pipe { volume, connections: Vec::new(), network };
+        let handle = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.pipe = Some(pipe);
+                Handle::new(index, slot.generation)
+            }
+            None => {
+                self.slots.push(PipeSlot { generation: 0, pipe: Some(pipe) });
+                Handle::new((self.slots.len() - 1) as u32, 0)
+            }
+        };
+        self.networks[network].as_mut().unwrap().pipes.push(handle);
+        for &connection in connections {
+            self.connect(handle, connection)?;
+        }
+        Ok(handle)
+    }
+    /// Removes a pipe and returns the gas it held, its share of the network by
+    /// volume. Whatever it was holding together is split into separate networks.
+    pub fn remove_pipe(&mut self, handle: Handle) -> Result<AtmosMixture> {
+        self.pipe(handle)?;
+        let slot = &mut self.slots[handle.index()];
+        let pipe = slot.pipe.take().unwrap();
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index() as u32);
+        for &connection in &pipe.connections {
+            if let Ok(neighbour) = self.pipe_mut(connection) {
+                neighbour.connections.retain(|&other| other != handle);
+            }
+        }
+        let network = self.networks[pipe.network].as_mut().unwrap();
+        network.pipes.retain(|&other| other != handle);
+        if network.pipes.is_empty() {
+            let network = self.networks[pipe.network].take().unwrap();
+            self.free_networks.push(pipe.network);
+            return Ok(network.air);
+        }
+        let ratio = pipe.volume / network.air.volume;
+        let mut released = network.air.remove_ratio(ratio);
+        released.volume = pipe.volume;
+        self.split(pipe.network);
+        Ok(released)
+    }
+    pub fn connect(&mut self, first: Handle, second: Handle) -> Result<()> {
+        if self.pipe(first)?.connections.contains(&second) {
+            return Ok(());
+        }
+        self.pipe(second)?;
+        if first == second {
+            return Err(Error::AliasedHandles);
+        }
+        self.pipe_mut(first)?.connections.push(second);
+        self.pipe_mut(second)?.connections.push(first);
+        let (into, from) = (self.pipe(first)?.network, self.pipe(second)?.network);
+        if into != from {
+            let from_network = self.networks[from].take().unwrap();
+            self.free_networks.push(from);
+            for &pipe in &from_network.pipes {
+                self.pipe_mut(pipe)?.network = into;
+            }
+            let network = self.networks[into].as_mut().unwrap();
+            let volume = network.air.volume + from_network.air.volume;
+            network.air.merge(from_network.air);
+            network.air.volume = volume;
+            network.pipes.extend(from_network.pipes);
+        }
+        Ok(())
+    }
+    pub fn disconnect(&mut self, first: Handle, second: Handle) -> Result<()> {
+        self.pipe(second)?;
+        if !self.pipe(first)?.connections.contains(&second) {
+            return Ok(());
+        }
+        self.pipe_mut(first)?.connections.retain(|&other| other != second);
+        self.pipe_mut(second)?.connections.retain(|&other| other != first);
+        let network = self.pipe(first)?.network;
+        self.split(network);
+        Ok(())
+    }
+    /// Gives every connected group of pipes left in `network` a network of its own,
+    /// each taking gas in proportion to its volume. The first group keeps `network`.
+    fn split(&mut self, network: usize) {
+        let pipes = self.networks[network].as_ref().unwrap().pipes.clone();
+        let mut seen: HashSet<Handle> = HashSet::new();
+        let mut groups: Vec<Vec<Handle>> = Vec::new();
+        for &start in &pipes {
+            if !seen.insert(start) {
+                continue;
+            }
+            let mut group = vec![start];
+            let mut next = 0;
+            while next < group.len() {
+                for &connection in &self.slots[group[next].index()].pipe.as_ref().unwrap().connections {
+                    if seen.insert(connection) {
+                        group.push(connection);
+                    }
+                }
+                next += 1;
+            }
+            groups.push(group);
+        }
+        let mut remaining_volume = self.total_volume(&pipes);
+        for group in groups.into_iter().skip(1) {
+            let volume = self.total_volume(&group);
+            let kept = self.networks[network].as_mut().unwrap();
+            let mut air = kept.air.remove_ratio(volume / remaining_volume);
+            remaining_volume -= volume;
+            air.volume = volume;
+            kept.pipes.retain(|pipe| !group.contains(pipe));
+            let new_network = self.create_network(PipeNetwork { pipes: Vec::new(), air });
+            for &pipe in &group {
+                self.slots[pipe.index()].pipe.as_mut().unwrap().network = new_network;
+            }
+            self.networks[new_network].as_mut().unwrap().pipes = group;
+        }
+        let kept = self.networks[network].as_mut().unwrap();
+        kept.air.volume = remaining_volume;
+    }
+}
+
+thread_local! {
+    static PIPENETS: RefCell<PipeNets> = RefCell::new(PipeNets::default());
+}
+
+/// Runs `f` against the pipes backing the BYOND exports.
+pub fn with_pipenets<F, T>(f: F) -> T
+where
+    F: FnOnce(&mut PipeNets) -> T,
+{
+    PIPENETS.with(|cell| f(&mut cell.borrow_mut()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atmos::gases::gas_index;
+
+    #[test]
+    fn test_merge_and_split() {
+        let mut pipenets = PipeNets::default();
+        let left = pipenets.add_pipe(float(70.0), &[]).unwrap();
+        let middle = pipenets.add_pipe(float(70.0), &[left]).unwrap();
+        let right = pipenets.add_pipe(float(140.0), &[]).unwrap();
+        assert_eq!(pipenets.network_count(), 2);
+        pipenets.air_mut(left).unwrap().parse_gas_string("TEMP=293.15;o2=28;").unwrap();
+        pipenets.air_mut(right).unwrap().parse_gas_string("TEMP=293.15;n2=14;").unwrap();
+        pipenets.connect(middle, right).unwrap();
+        assert_eq!(pipenets.network_count(), 1);
+        assert_eq!(pipenets.air(left).unwrap().volume, 280.0);
+        assert_eq!(pipenets.network(right).unwrap().pipes.len(), 3);
+        let released = pipenets.remove_pipe(middle).unwrap();
+        assert_eq!(released.volume, 70.0);
+        assert!((released.gases[gas_index("o2")] - 7.0).abs() < 0.001);
+        assert!((released.gases[gas_index("n2")] - 3.5).abs() < 0.001);
+        assert_eq!(pipenets.network_count(), 2);
+        let left_air = pipenets.air(left).unwrap();
+        assert_eq!(left_air.volume, 70.0);
+        assert!((left_air.gases[gas_index("o2")] - 7.0).abs() < 0.001);
+        let right_air = pipenets.air(right).unwrap();
+        assert_eq!(right_air.volume, 140.0);
+        assert!((right_air.gases[gas_index("o2")] - 14.0).abs() < 0.001);
+        assert!((pipenets.air(left).unwrap().pressure() - right_air.pressure()).abs() < 0.01);
+        assert!(pipenets.pipe(middle).is_err());
+        assert!(pipenets.connect(left, middle).is_err());
+    }
+    #[test]
+    fn test_loops() {
+        let mut pipenets = PipeNets::default();
+        let first = pipenets.add_pipe(float(70.0), &[]).unwrap();
+        let second = pipenets.add_pipe(float(70.0), &[first]).unwrap();
+        let third = pipenets.add_pipe(float(70.0), &[first, second]).unwrap();
+        pipenets.air_mut(first).unwrap().parse_gas_string("TEMP=500;plasma=30;").unwrap();
+        pipenets.disconnect(first, second).unwrap();
+        assert_eq!(pipenets.network_count(), 1);
+        pipenets.disconnect(third, second).unwrap();
+        assert_eq!(pipenets.network_count(), 2);
+        assert!((pipenets.air(second).unwrap().gases[gas_index("plasma")] - 10.0).abs() < 0.001);
+        assert_eq!(pipenets.air(second).unwrap().temperature, 500.0);
+        assert_eq!(pipenets.air(first).unwrap().volume, 140.0);
+        assert!(pipenets.add_pipe(float(0.0), &[]).is_err());
+        for pipe in [first, second, third].iter() {
+            pipenets.remove_pipe(*pipe).unwrap();
+        }
+        assert!(pipenets.is_empty());
+        assert_eq!(pipenets.network_count(), 0);
+    }
+}
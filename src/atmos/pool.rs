@@ -0,0 +1,123 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed set of worker threads pulling jobs off a shared queue.
+pub struct ThreadPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    pub fn new(threads: usize) -> ThreadPool {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|number| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("atmos worker {}", number))
+                    .spawn(move || worker_loop(&receiver))
+                    .expect("couldn't spawn an atmos worker thread")
+            })
+            .collect();
+        ThreadPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+    /// Runs `f` for each of `0..count` across the pool and returns the results in
+    /// that order, whichever thread finished first. If any of them panicked, the
+    /// panic is resumed here once the rest are done.
+    pub fn map<T, F>(&self, count: usize, f: F) -> Vec<T>
+    where
+        T: Send + 'static,
+        F: Fn(usize) -> T + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        let (result_sender, result_receiver) = channel();
+        for index in 0..count {
+            let f = f.clone();
+            let result_sender = result_sender.clone();
+            let job: Job = Box::new(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| f(index)));
+                // let go of `f` first, so it's only held by this call once every result is in
+                drop(f);
+                let _ = result_sender.send((index, result));
+            });
+            self.sender.as_ref().unwrap().send(job).expect("atmos worker threads are gone");
+        }
+        drop(result_sender);
+        let mut results: Vec<Option<thread::Result<T>>> = (0..count).map(|_| None).collect();
+        for (index, result) in result_receiver.iter().take(count) {
+            results[index] = Some(result);
+        }
+        results
+            .into_iter()
+            .map(|result| match result.expect("an atmos job was lost") {
+                Ok(value) => value,
+                Err(payload) => panic::resume_unwind(payload),
+            })
+            .collect()
+    }
+}
+
+fn worker_loop(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).recv();
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // closing the queue makes every worker's recv fail once it's drained
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+static POOL: RwLock<Option<Arc<ThreadPool>>> = RwLock::new(None);
+
+/// The pool grid processing runs on, or None to run single-threaded.
+pub fn pool() -> Option<Arc<ThreadPool>> {
+    POOL.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+pub fn threads() -> usize {
+    pool().map(|pool| pool.threads()).unwrap_or(1)
+}
+
+/// Replaces the pool with one of `threads` workers. 0 or 1 goes back to running
+/// everything on the calling thread. The old pool's workers finish whatever
+/// they're on before exiting.
+pub fn set_threads(threads: usize) {
+    let pool = if threads > 1 { Some(Arc::new(ThreadPool::new(threads))) } else { None };
+    *POOL.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = pool;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_keeps_order() {
+        let pool = ThreadPool::new(4);
+        assert_eq!(pool.map(100, |index| index * 2), (0..100).map(|index| index * 2).collect::<Vec<usize>>());
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| pool.map(8, |index| if index == 5 { panic!("bad job") } else { index })));
+        assert!(panicked.is_err());
+        // the workers survive a panicking job
+        assert_eq!(pool.map(3, |index| index), vec![0, 1, 2]);
+    }
+}
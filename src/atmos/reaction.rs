@@ -4,6 +4,10 @@ use atmos::gases::*;
 
 use atmos::constants::*;
 
+use atmos::data_reaction::DataReaction;
+
+use std::sync::{Arc, RwLock};
+
 #[derive(PartialEq)]
 pub enum ReactionResult {
     NoReaction,
@@ -11,16 +15,53 @@ pub enum ReactionResult {
     StopReactions
 }
 
-fn nobliumsupression(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
-    if air.gases[GASES_BY_ID["nob"]] >= 5.0 {
+/// Where the built-in reactions' gases are in the gas registry. Looked up once when
+/// the reactions are built, so reacting never has to hash a gas id.
+#[derive(Clone, Copy)]
+pub struct GasIndices {
+    pub n2: usize,
+    pub o2: usize,
+    pub plasma: usize,
+    pub co2: usize,
+    pub n2o: usize,
+    pub water_vapor: usize,
+    pub bz: usize,
+    pub tritium: usize,
+    pub miasma: usize,
+    pub no2: usize,
+    pub nob: usize,
+    pub stim: usize,
+}
+
+impl GasIndices {
+    pub fn new() -> GasIndices {
+        GasIndices {
+            n2: gas_index("n2"),
+            o2: gas_index("o2"),
+            plasma: gas_index("plasma"),
+            co2: gas_index("co2"),
+            n2o: gas_index("n2o"),
+            water_vapor: gas_index("water_vapor"),
+            bz: gas_index("bz"),
+            tritium: gas_index("tritium"),
+            miasma: gas_index("miasma"),
+            no2: gas_index("no2"),
+            nob: gas_index("nob"),
+            stim: gas_index("stim"),
+        }
+    }
+}
+
+fn nobliumsupression(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>) {
+    if air.gases[gas.nob] >= 5.0 {
         return (ReactionResult::StopReactions,[].to_vec())
     }
     return (ReactionResult::NoReaction,[].to_vec());
 }
 
-fn tritfire(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
-    let initial_oxy = air.gases[GASES_BY_ID["o2"]];
-    let initial_trit = air.gases[GASES_BY_ID["tritium"]];
+fn tritfire(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>) {
+    let initial_oxy = air.gases[gas.o2];
+    let initial_trit = air.gases[gas.tritium];
     if air.temperature < FIRE_MINIMUM_TEMPERATURE_TO_EXIST || initial_trit < MINIMUM_MOLE_COUNT  || initial_oxy < MINIMUM_MOLE_COUNT {
         return (ReactionResult::NoReaction,[].to_vec());
     } else {
@@ -28,24 +69,25 @@ fn tritfire(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
         let tritium_burn_trit_factor = 10.0;
         let fire_hydrogen_energy_released = 560000.0;
         let old_energy = air.thermal_energy();
-        let mut energy_released = 0.0;
+        let mut energy_released = float(0.0);
         let mut burned_fuel;
         if initial_oxy < initial_trit {
-            burned_fuel = air.gases[GASES_BY_ID["o2"]] / tritium_burn_oxy_factor;
+            burned_fuel = air.gases[gas.o2] / tritium_burn_oxy_factor;
             if burned_fuel > initial_trit {
                 burned_fuel = initial_trit;
             }
-            air.gases[GASES_BY_ID["tritium"]] -= burned_fuel;
+            air.gases[gas.tritium] -= burned_fuel;
         } else {
             burned_fuel = initial_trit;
-            air.gases[GASES_BY_ID["tritium"]] *= 1.0 - 1.0/tritium_burn_trit_factor;
-            air.gases[GASES_BY_ID["o2"]] -= air.gases[GASES_BY_ID["tritium"]];
+            air.gases[gas.tritium] *= 1.0 - 1.0/tritium_burn_trit_factor;
+            let remaining_trit = air.gases[gas.tritium];
+            air.gases[gas.o2] -= remaining_trit;
             energy_released += fire_hydrogen_energy_released * burned_fuel * (tritium_burn_trit_factor - 1.0);
         }
         let mut strings = [].to_vec();
         if burned_fuel > 0.0 {
             energy_released += fire_hydrogen_energy_released * burned_fuel;
-            air.gases[GASES_BY_ID["water_vapor"]] += burned_fuel;
+            air.gases[gas.water_vapor] += burned_fuel;
             strings = ["tritfire".to_string(),burned_fuel.to_string(),energy_released.to_string()].to_vec();
         }
         if energy_released > 0.0 {
@@ -55,8 +97,8 @@ fn tritfire(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
     }
 }
 
-fn plasmafire(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
-    if air.temperature < FIRE_MINIMUM_TEMPERATURE_TO_EXIST || air.gases[GASES_BY_ID["plasma"]] < MINIMUM_MOLE_COUNT  || air.gases[GASES_BY_ID["o2"]] < MINIMUM_MOLE_COUNT
+fn plasmafire(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>) {
+    if air.temperature < FIRE_MINIMUM_TEMPERATURE_TO_EXIST || air.gases[gas.plasma] < MINIMUM_MOLE_COUNT  || air.gases[gas.o2] < MINIMUM_MOLE_COUNT
     {
         return (ReactionResult::NoReaction,[].to_vec());
     } else {
@@ -75,7 +117,7 @@ fn plasmafire(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
         let old_energy = air.thermal_energy();
         let energy_released;
         if air.temperature>plasma_upper_temperature {
-            temperature_scale = 1.0;
+            temperature_scale = float(1.0);
         } else {
             temperature_scale = (air.temperature-FIRE_MINIMUM_TEMPERATURE_TO_EXIST)/(plasma_upper_temperature-FIRE_MINIMUM_TEMPERATURE_TO_EXIST);
         }
@@ -83,25 +125,25 @@ fn plasmafire(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
         if temperature_scale > 0.0
         {
             oxygen_burn_rate = oxygen_burn_rate_base-temperature_scale;
-            super_saturation = air.gases[GASES_BY_ID["o2"]]/air.gases[GASES_BY_ID["plasma"]]>super_saturation_threshold;
-            if air.gases[GASES_BY_ID["o2"]]>air.gases[GASES_BY_ID["plasma"]]*plasma_oxygen_fullburn
+            super_saturation = air.gases[gas.o2]/air.gases[gas.plasma]>super_saturation_threshold;
+            if air.gases[gas.o2]>air.gases[gas.plasma]*plasma_oxygen_fullburn
             {
-                plasma_burn_rate = air.gases[GASES_BY_ID["plasma"]]*temperature_scale/plasma_burn_rate_delta;
+                plasma_burn_rate = air.gases[gas.plasma]*temperature_scale/plasma_burn_rate_delta;
             }
             else
             {
-                plasma_burn_rate = (temperature_scale*(air.gases[GASES_BY_ID["o2"]]/plasma_oxygen_fullburn))/plasma_burn_rate_delta;
+                plasma_burn_rate = (temperature_scale*(air.gases[gas.o2]/plasma_oxygen_fullburn))/plasma_burn_rate_delta;
             }
-            plasma_burn_rate = min(min(FloatOrd(plasma_burn_rate),FloatOrd(air.gases[GASES_BY_ID["plasma"]])),FloatOrd(air.gases[GASES_BY_ID["o2"]]/oxygen_burn_rate)).0;
-            air.gases[GASES_BY_ID["plasma"]] -= plasma_burn_rate;
-            air.gases[GASES_BY_ID["o2"]] -= plasma_burn_rate*oxygen_burn_rate;
+            plasma_burn_rate = min(min(FloatOrd(plasma_burn_rate),FloatOrd(air.gases[gas.plasma])),FloatOrd(air.gases[gas.o2]/oxygen_burn_rate)).0;
+            air.gases[gas.plasma] -= plasma_burn_rate;
+            air.gases[gas.o2] -= plasma_burn_rate*oxygen_burn_rate;
             if super_saturation
             {
-                air.gases[GASES_BY_ID["tritium"]]+=plasma_burn_rate;
+                air.gases[gas.tritium]+=plasma_burn_rate;
             }
             else
             {
-                air.gases[GASES_BY_ID["co2"]]+=plasma_burn_rate;
+                air.gases[gas.co2]+=plasma_burn_rate;
             }
             energy_released = fire_plasma_energy_released * plasma_burn_rate;
             let reacted = plasma_burn_rate*(1.0+oxygen_burn_rate)>0.0;
@@ -118,29 +160,29 @@ fn plasmafire(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
     }
 }
 
-fn fusion(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
-    if air.temperature < 10000.0 || air.gases[GASES_BY_ID["tritium"]] < 1.0 || air.gases[GASES_BY_ID["co2"]] < 250.0 || air.gases[GASES_BY_ID["plasma"]] < 250.0 {
+fn fusion(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>) {
+    if air.temperature < 10000.0 || air.gases[gas.tritium] < 1.0 || air.gases[gas.co2] < 250.0 || air.gases[gas.plasma] < 250.0 {
         return (ReactionResult::NoReaction,[].to_vec())
     } else {
-        use std::f32::consts::PI;
+        use atmos::constants::float_consts::PI;
         let toroid_volume_breakeven = 1000.0;
         let instability_gas_factor = 0.003;
         let plasma_binding_energy = 20_000_000.0;
         let fusion_tritium_moles_used = 1.0;
         let fusion_instability_endothermality = 2.0;
         let fusion_tritium_conversion_coefficient = 1e-10;
-        let mut reaction_energy = 0.0 ;
+        let mut reaction_energy = float(0.0) ;
         let initial_energy = air.thermal_energy();
-        let initial_plasma = air.gases[GASES_BY_ID["plasma"]];
-        let initial_carbon = air.gases[GASES_BY_ID["co2"]];
+        let initial_plasma = air.gases[gas.plasma];
+        let initial_carbon = air.gases[gas.co2];
         let scale_factor = air.volume / PI;
         //The size of the phase space hypertorus
         let toroidal_size = (3.0*PI)+((air.volume-toroid_volume_breakeven)/toroid_volume_breakeven).atan();
         //3*PI above rather than 2.0*PI because atan can return -pi
-        let mut gas_power = 0.0; // uh??
-        for (i, amount) in air.gases.iter().enumerate()
+        let mut gas_power = float(0.0); // uh??
+        for (gas, amount) in gases().iter().zip(air.gases.iter())
         {
-            gas_power+=GASES[i].fusion_power*amount;
+            gas_power+=gas.fusion_power*amount;
         }
         let instability = (gas_power*instability_gas_factor).powf(2.0)%toroidal_size;
         let mut plasma = (initial_plasma - FUSION_MOLE_THRESHOLD) / scale_factor;
@@ -148,13 +190,13 @@ fn fusion(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
         plasma = ((plasma - (instability*(carbon.sin()))%toroidal_size)).abs();
         //count the rings. ss13's modulus is positive, this ain't, who knew
         carbon = ((carbon - plasma)%toroidal_size).abs();
-        air.gases[GASES_BY_ID["plasma"]] = plasma*scale_factor + FUSION_MOLE_THRESHOLD;
-        air.gases[GASES_BY_ID["co2"]] = carbon*scale_factor + FUSION_MOLE_THRESHOLD;
-        let delta_plasma = initial_plasma - air.gases[GASES_BY_ID["plasma"]];
+        air.gases[gas.plasma] = plasma*scale_factor + FUSION_MOLE_THRESHOLD;
+        air.gases[gas.co2] = carbon*scale_factor + FUSION_MOLE_THRESHOLD;
+        let delta_plasma = initial_plasma - air.gases[gas.plasma];
         reaction_energy += delta_plasma*plasma_binding_energy;
         if instability < fusion_instability_endothermality && reaction_energy < 0.0
         {
-            reaction_energy = 0.0;
+            reaction_energy = float(0.0);
         }
         else if reaction_energy < 0.0
         {
@@ -162,27 +204,27 @@ fn fusion(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
         }
         if air.thermal_energy() + reaction_energy < 0.0
         {
-            air.gases[GASES_BY_ID["plasma"]] = initial_plasma;
-            air.gases[GASES_BY_ID["co2"]] = initial_carbon;
+            air.gases[gas.plasma] = initial_plasma;
+            air.gases[gas.co2] = initial_carbon;
             return (ReactionResult::NoReaction,[].to_vec());
         }
-        air.gases[GASES_BY_ID["tritium"]] -= fusion_tritium_moles_used;
+        air.gases[gas.tritium] -= fusion_tritium_moles_used;
         if reaction_energy > 0.0
         {
-            air.gases[GASES_BY_ID["o2"]] += fusion_tritium_moles_used*(reaction_energy*fusion_tritium_conversion_coefficient);
-            air.gases[GASES_BY_ID["n2o"]] += fusion_tritium_moles_used*(reaction_energy*fusion_tritium_conversion_coefficient);
+            air.gases[gas.o2] += fusion_tritium_moles_used*(reaction_energy*fusion_tritium_conversion_coefficient);
+            air.gases[gas.n2o] += fusion_tritium_moles_used*(reaction_energy*fusion_tritium_conversion_coefficient);
         }
         else
         {
-            air.gases[GASES_BY_ID["bz"]] += fusion_tritium_moles_used*(reaction_energy*-fusion_tritium_conversion_coefficient);
-            air.gases[GASES_BY_ID["no2"]] += fusion_tritium_moles_used*(reaction_energy*-fusion_tritium_conversion_coefficient);
+            air.gases[gas.bz] += fusion_tritium_moles_used*(reaction_energy*-fusion_tritium_conversion_coefficient);
+            air.gases[gas.no2] += fusion_tritium_moles_used*(reaction_energy*-fusion_tritium_conversion_coefficient);
         }
         if reaction_energy != 0.0 {
             use std::cmp::max;
             use super::float_ord::FloatOrd;
             let particle_chance_constant = -20_000_000.0;
             let particle_chance = (particle_chance_constant/(reaction_energy-particle_chance_constant)) + 1.0;
-            let FloatOrd(rad_power) = max(FloatOrd((-2000.0/instability)+1000.0),FloatOrd(0.0));
+            let FloatOrd(rad_power) = max(FloatOrd((-2000.0/instability)+1000.0),FloatOrd(float(0.0)));
             air.temperature = (initial_energy+reaction_energy)/air.heat_capacity();
             return (ReactionResult::Reacting,[
                 "fusion".to_string(),
@@ -193,30 +235,30 @@ fn fusion(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
     }
 }
 
-fn nitrylformation(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
-    if air.temperature < FIRE_MINIMUM_TEMPERATURE_TO_EXIST * 400.0 || air.gases[GASES_BY_ID["n2o"]] < 0.5 || air.gases[GASES_BY_ID["o2"]] < 20.0 || air.gases[GASES_BY_ID["n2"]] < 20.0 {
+fn nitrylformation(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>) {
+    if air.temperature < FIRE_MINIMUM_TEMPERATURE_TO_EXIST * 400.0 || air.gases[gas.n2o] < 0.5 || air.gases[gas.o2] < 20.0 || air.gases[gas.n2] < 20.0 {
         return (ReactionResult::NoReaction,[].to_vec())
     } else {
         use std::cmp::min;
         use super::float_ord::FloatOrd;
         let nitryl_formation_energy = 100000.0;
         let old_energy = air.thermal_energy();
-        let FloatOrd(reaction_efficiency) = min(FloatOrd(air.temperature/(FIRE_MINIMUM_TEMPERATURE_TO_EXIST*100.0)),min(FloatOrd(air.gases[GASES_BY_ID["o2"]]),FloatOrd(air.gases[GASES_BY_ID["n2"]])));
+        let FloatOrd(reaction_efficiency) = min(FloatOrd(air.temperature/(FIRE_MINIMUM_TEMPERATURE_TO_EXIST*100.0)),min(FloatOrd(air.gases[gas.o2]),FloatOrd(air.gases[gas.n2])));
         let energy_used = reaction_efficiency*nitryl_formation_energy;
-        if (air.gases[GASES_BY_ID["o2"]] < reaction_efficiency ) || (air.gases[GASES_BY_ID["n2"]] < reaction_efficiency) //Shouldn't produce gas from nothing.
+        if (air.gases[gas.o2] < reaction_efficiency ) || (air.gases[gas.n2] < reaction_efficiency) //Shouldn't produce gas from nothing.
         {
             return (ReactionResult::NoReaction,[].to_vec());
         }
-        air.gases[GASES_BY_ID["o2"]] -= reaction_efficiency;
-        air.gases[GASES_BY_ID["n2"]] -= reaction_efficiency;
-        air.gases[GASES_BY_ID["no2"]] += reaction_efficiency*2.0;
+        air.gases[gas.o2] -= reaction_efficiency;
+        air.gases[gas.n2] -= reaction_efficiency;
+        air.gases[gas.no2] += reaction_efficiency*2.0;
         air.temperature = (old_energy-energy_used)/air.heat_capacity();
         return (ReactionResult::Reacting,[].to_vec());
     }
 }
 
-fn bzformation(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
-    if air.gases[GASES_BY_ID["n2o"]] < 10.0 || air.gases[GASES_BY_ID["plasma"]] < 10.0 {
+fn bzformation(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>) {
+    if air.gases[gas.n2o] < 10.0 || air.gases[gas.plasma] < 10.0 {
         return (ReactionResult::NoReaction,[].to_vec())
     } else {
         let fire_carbon_energy_released = 100_000.0;
@@ -228,25 +270,25 @@ fn bzformation(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
         //nobody ever said this code was good. let nobody ever try to tell you this code is good
         let FloatOrd(reaction_efficiency) = min(
                 FloatOrd(1.0/(old_pressure/(0.1*ATMOSPHERE))*(max(
-                        FloatOrd(air.gases[GASES_BY_ID["plasma"]]/air.gases[GASES_BY_ID["n2o"]]),
-                        FloatOrd(1.0))).0),
+                        FloatOrd(air.gases[gas.plasma]/air.gases[gas.n2o]),
+                        FloatOrd(float(1.0)))).0),
                 min(
-                    FloatOrd(air.gases[GASES_BY_ID["n2o"]]),
-                    FloatOrd(air.gases[GASES_BY_ID["plasma"]]/2.0)));
+                    FloatOrd(air.gases[gas.n2o]),
+                    FloatOrd(air.gases[gas.plasma]/2.0)));
         let energy_released = 2.0*reaction_efficiency*fire_carbon_energy_released;
-        if (air.gases[GASES_BY_ID["n2o"]] < reaction_efficiency )|| (air.gases[GASES_BY_ID["plasma"]] < (2.0*reaction_efficiency) || energy_released <= 0.0 ) //Shouldn't produce gas from nothing.
+        if (air.gases[gas.n2o] < reaction_efficiency )|| (air.gases[gas.plasma] < (2.0*reaction_efficiency) || energy_released <= 0.0 ) //Shouldn't produce gas from nothing.
         {
             return (ReactionResult::NoReaction,[].to_vec());
         }
-        air.gases[GASES_BY_ID["bz"]] += reaction_efficiency;
-        if reaction_efficiency == air.gases[GASES_BY_ID["n2o"]]
+        air.gases[gas.bz] += reaction_efficiency;
+        if reaction_efficiency == air.gases[gas.n2o]
         {
-            let FloatOrd(nitrous_balance_change) = min(FloatOrd(old_pressure),FloatOrd(1.0));
-            air.gases[GASES_BY_ID["bz"]] -= nitrous_balance_change;
-            air.gases[GASES_BY_ID["o2"]] += nitrous_balance_change;
+            let FloatOrd(nitrous_balance_change) = min(FloatOrd(old_pressure),FloatOrd(float(1.0)));
+            air.gases[gas.bz] -= nitrous_balance_change;
+            air.gases[gas.o2] += nitrous_balance_change;
         }
-        air.gases[GASES_BY_ID["n2o"]] -= reaction_efficiency;
-        air.gases[GASES_BY_ID["plasma"]]  -= 2.0*reaction_efficiency;
+        air.gases[gas.n2o] -= reaction_efficiency;
+        air.gases[gas.plasma]  -= 2.0*reaction_efficiency;
         //SSresearch.science_tech.add_point_type(TECHWEB_POINT_TYPE_DEFAULT, min((reaction_efficency**2)*BZ_RESEARCH_SCALE),BZ_RESEARCH_MAX_AMOUNT)
         //we already returned if energy_released is non-positive
         air.temperature = (old_energy+energy_released)/air.heat_capacity();
@@ -256,8 +298,8 @@ fn bzformation(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
     }
 }
 
-fn stimformation(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
-    if air.temperature < STIMULUM_HEAT_SCALE / 2.0 || air.gases[GASES_BY_ID["no2"]] < 30.0 || air.gases[GASES_BY_ID["bz"]] < 20.0 || air.gases[GASES_BY_ID["tritium"]] < 30.0 || air.gases[GASES_BY_ID["plasma"]] < 10.0 {
+fn stimformation(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>) {
+    if air.temperature < STIMULUM_HEAT_SCALE / 2.0 || air.gases[gas.no2] < 30.0 || air.gases[gas.bz] < 20.0 || air.gases[gas.tritium] < 30.0 || air.gases[gas.plasma] < 10.0 {
         return (ReactionResult::NoReaction,[].to_vec())
     } else {
         use std::cmp::min;
@@ -268,28 +310,28 @@ fn stimformation(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
         let stimulum_second_rise = 0.0009;
         let stimulum_absolute_drop = 0.00000335;
         let old_energy = air.thermal_energy();
-        let FloatOrd(heat_scale) = min(FloatOrd(air.temperature/STIMULUM_HEAT_SCALE),min(FloatOrd(air.gases[GASES_BY_ID["tritium"]]),min(FloatOrd(air.gases[GASES_BY_ID["plasma"]]),FloatOrd(air.gases[GASES_BY_ID["no2"]]))));
+        let FloatOrd(heat_scale) = min(FloatOrd(air.temperature/STIMULUM_HEAT_SCALE),min(FloatOrd(air.gases[gas.tritium]),min(FloatOrd(air.gases[gas.plasma]),FloatOrd(air.gases[gas.no2]))));
         let stim_energy_change = heat_scale + stimulum_first_rise*(heat_scale.powf(2.0)) - stimulum_first_drop*(heat_scale.powf(3.0)) + stimulum_second_rise*(heat_scale.powf(4.0)) - stimulum_absolute_drop*(heat_scale.powf(5.0));
         //i mean it's not THAT odd it's O(-T^5) which you might think doesn't matter much except fusion tends to get to levels where that dominates
-        if air.gases[GASES_BY_ID["no2"]] < heat_scale || air.gases[GASES_BY_ID["tritium"]] < heat_scale || air.gases[GASES_BY_ID["plasma"]] < heat_scale
+        if air.gases[gas.no2] < heat_scale || air.gases[gas.tritium] < heat_scale || air.gases[gas.plasma] < heat_scale
         {
             return (ReactionResult::NoReaction,[].to_vec());
         }
-        air.gases[GASES_BY_ID["stim"]] += heat_scale/10.0;
-        air.gases[GASES_BY_ID["tritium"]] -= heat_scale;
-        air.gases[GASES_BY_ID["plasma"]] -= heat_scale;
-        air.gases[GASES_BY_ID["no2"]] -= heat_scale;
+        air.gases[gas.stim] += heat_scale/10.0;
+        air.gases[gas.tritium] -= heat_scale;
+        air.gases[gas.plasma] -= heat_scale;
+        air.gases[gas.no2] -= heat_scale;
         if stim_energy_change != 0.0 
         {
             air.temperature = (old_energy+stim_energy_change)/air.heat_capacity();
         }
         return (ReactionResult::Reacting,[
-        ("stimformation".to_string()),(max(FloatOrd(0.0),FloatOrd(stim_energy_change)).0).to_string()].to_vec());
+        ("stimformation".to_string()),(max(FloatOrd(float(0.0)),FloatOrd(stim_energy_change)).0).to_string()].to_vec());
     }
 }
 
-fn nobliumformation(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
-    if air.temperature < 5000000.0 || air.gases[GASES_BY_ID["tritium"]] < 5.0 || air.gases[GASES_BY_ID["n2"]] < 10.0 {
+fn nobliumformation(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>) {
+    if air.temperature < 5000000.0 || air.gases[gas.tritium] < 5.0 || air.gases[gas.n2] < 10.0 {
         return (ReactionResult::NoReaction,[].to_vec())
     } else {
         use std::cmp::min;
@@ -297,15 +339,15 @@ fn nobliumformation(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
         use super::float_ord::FloatOrd;
         let noblium_formation_energy = 2e9 * 1.0;
         let old_energy = air.thermal_energy();
-        let FloatOrd(nob_formed) = min(FloatOrd(air.gases[GASES_BY_ID["n2"]]+air.gases[GASES_BY_ID["tritium"]]/100.0),min(FloatOrd(air.gases[GASES_BY_ID["tritium"]]/10.0),FloatOrd(air.gases[GASES_BY_ID["n2"]]/20.0)));
-        let energy_taken = (nob_formed * noblium_formation_energy) / max(FloatOrd(air.gases[GASES_BY_ID["bz"]]),FloatOrd(1.0)).0;
-        if (air.gases[GASES_BY_ID["tritium"]] < 10.0*nob_formed) || (air.gases[GASES_BY_ID["n2"]] < 20.0*nob_formed)
+        let FloatOrd(nob_formed) = min(FloatOrd(air.gases[gas.n2]+air.gases[gas.tritium]/100.0),min(FloatOrd(air.gases[gas.tritium]/10.0),FloatOrd(air.gases[gas.n2]/20.0)));
+        let energy_taken = (nob_formed * noblium_formation_energy) / max(FloatOrd(air.gases[gas.bz]),FloatOrd(float(1.0))).0;
+        if (air.gases[gas.tritium] < 10.0*nob_formed) || (air.gases[gas.n2] < 20.0*nob_formed)
         {
             return (ReactionResult::NoReaction,[].to_vec());
         }
-        air.gases[GASES_BY_ID["tritium"]] -= 10.0*nob_formed;
-        air.gases[GASES_BY_ID["n2"]] -= 20.0*nob_formed;
-        air.gases[GASES_BY_ID["nob"]] += nob_formed;
+        air.gases[gas.tritium] -= 10.0*nob_formed;
+        air.gases[gas.n2] -= 20.0*nob_formed;
+        air.gases[gas.nob] += nob_formed;
         if energy_taken != 0.0
         {
             air.temperature = (old_energy-energy_taken)/air.heat_capacity();
@@ -315,56 +357,173 @@ fn nobliumformation(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
     }
 }
 
-fn miaster(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
-    if air.temperature < FIRE_MINIMUM_TEMPERATURE_TO_EXIST+70.0 || air.gases[GASES_BY_ID["miasma"]] < MINIMUM_MOLE_COUNT || air.gases[GASES_BY_ID["water_vapor"]]/air.total_moles() > 0.1 {
+fn miaster(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>) {
+    if air.temperature < FIRE_MINIMUM_TEMPERATURE_TO_EXIST+70.0 || air.gases[gas.miasma] < MINIMUM_MOLE_COUNT || air.gases[gas.water_vapor]/air.total_moles() > 0.1 {
         return (ReactionResult::NoReaction,[].to_vec())
     } else {
         use std::cmp::min;
         use super::float_ord::FloatOrd;
-        let FloatOrd(cleaned_air) = min(FloatOrd(air.gases[GASES_BY_ID["miasma"]]),
+        let FloatOrd(cleaned_air) = min(FloatOrd(air.gases[gas.miasma]),
         FloatOrd(20.0 + (air.temperature - FIRE_MINIMUM_TEMPERATURE_TO_EXIST - 70.0) / 20.0));
-        air.gases[GASES_BY_ID["miasma"]] -= cleaned_air;
-        air.gases[GASES_BY_ID["o2"]] += cleaned_air;
+        air.gases[gas.miasma] -= cleaned_air;
+        air.gases[gas.o2] += cleaned_air;
         air.temperature += cleaned_air * (0.002);
         return (ReactionResult::Reacting,[
         "miaster".to_string(),cleaned_air.to_string()].to_vec());
     }
 }
 
-pub const REACTIONS: &'static [fn(air: &mut AtmosMixture) -> (ReactionResult,Vec<String>)] = &[
-    nobliumsupression, // infinity
-    nobliumformation, // 6
-    stimformation, // 5
-    bzformation, // 4
-    nitrylformation, // 3
-    fusion, // 2
-    tritfire, // -1
-    plasmafire, // -2
-    miaster // -10
-];
+pub type ReactionFn = fn(air: &mut AtmosMixture, gas: &GasIndices) -> (ReactionResult,Vec<String>);
+
+pub enum ReactionHandler {
+    Native(ReactionFn, GasIndices),
+    Data(Box<DataReaction>),
+}
+
+/// A reaction and what's known about it up front. The requirements are checked
+/// before the handler runs, so a handler never sees air it can't react in.
+pub struct Reaction {
+    pub id: String,
+    pub name: String,
+    pub priority: Float,
+    pub min_temperature: Option<Float>,
+    pub min_pressure: Option<Float>,
+    pub min_moles: Vec<(usize,Float)>,
+    /// Nothing runs after this one if its requirements are met, like hyper-noblium.
+    pub stops_reactions: bool,
+    /// Nothing after this one runs on a tick where it reacted.
+    pub exclusive: bool,
+    pub handler: ReactionHandler,
+}
+
+impl Reaction {
+    fn native(id: &str, name: &str, priority: Constant, min_temperature: Option<Constant>, min_moles: &[(&str,Constant)], handler: ReactionFn) -> Reaction {
+        Reaction {
+            id: id.to_string(),
+            name: name.to_string(),
+            priority: float(priority),
+            min_temperature: min_temperature.map(float),
+            min_pressure: None,
+            min_moles: min_moles.iter().map(|&(gas,amount)| (gas_index(gas),float(amount))).collect(),
+            stops_reactions: false,
+            exclusive: false,
+            handler: ReactionHandler::Native(handler, GasIndices::new()),
+        }
+    }
+    pub fn can_react(&self, air: &AtmosMixture) -> bool {
+        !(self.min_temperature.map(|min| air.temperature < min).unwrap_or(false)
+            || self.min_pressure.map(|min| air.pressure() < min).unwrap_or(false)
+            || self.min_moles.iter().any(|&(index,min)| air.gases[index] < min))
+    }
+    pub fn react(&self, air: &mut AtmosMixture) -> (ReactionResult,Vec<String>) {
+        if !self.can_react(air) {
+            return (ReactionResult::NoReaction,[].to_vec());
+        }
+        match self.handler {
+            ReactionHandler::Native(handler, ref gas) => handler(air, gas),
+            ReactionHandler::Data(ref reaction) => reaction.react(air),
+        }
+    }
+}
+
+impl From<DataReaction> for Reaction {
+    fn from(reaction: DataReaction) -> Reaction {
+        Reaction {
+            id: reaction.id.clone(),
+            name: reaction.name.clone(),
+            priority: reaction.priority,
+            min_temperature: reaction.min_temperature,
+            min_pressure: reaction.min_pressure,
+            min_moles: reaction.min_moles.clone(),
+            stops_reactions: reaction.stops_reactions,
+            exclusive: reaction.exclusive,
+            handler: ReactionHandler::Data(Box::new(reaction)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ReactionRegistry {
+    reactions: Vec<Reaction>,
+    /// False once reactions have been loaded from a file.
+    pub builtin: bool,
+}
 
+impl ReactionRegistry {
+    pub fn builtin() -> ReactionRegistry {
+        let mut registry = ReactionRegistry { reactions: Vec::new(), builtin: true };
+        let mut suppression = Reaction::native("nobliumsupression", "Hyper-noblium reaction suppression", Constant::INFINITY, None, &[("nob",5.0)], nobliumsupression);
+        suppression.stops_reactions = true;
+        registry.register(suppression);
+        registry.register(Reaction::native("nobliumformation", "Hyper-noblium condensation", 6.0, Some(5000000.0), &[("tritium",5.0),("n2",10.0)], nobliumformation));
+        registry.register(Reaction::native("stimformation", "Stimulum formation", 5.0, Some(STIMULUM_HEAT_SCALE / 2.0), &[("no2",30.0),("bz",20.0),("tritium",30.0),("plasma",10.0)], stimformation));
+        registry.register(Reaction::native("bzFormation", "BZ formation", 4.0, None, &[("n2o",10.0),("plasma",10.0)], bzformation));
+        registry.register(Reaction::native("nitrylformation", "Nitryl formation", 3.0, Some(FIRE_MINIMUM_TEMPERATURE_TO_EXIST * 400.0), &[("n2o",0.5),("o2",20.0),("n2",20.0)], nitrylformation));
+        registry.register(Reaction::native("fusion", "Fusion", 2.0, Some(10000.0), &[("tritium",1.0),("co2",250.0),("plasma",250.0)], fusion));
+        registry.register(Reaction::native("tritfire", "Tritium combustion", -1.0, Some(FIRE_MINIMUM_TEMPERATURE_TO_EXIST), &[("tritium",MINIMUM_MOLE_COUNT),("o2",MINIMUM_MOLE_COUNT)], tritfire));
+        registry.register(Reaction::native("plasfire", "Plasma combustion", -2.0, Some(FIRE_MINIMUM_TEMPERATURE_TO_EXIST), &[("plasma",MINIMUM_MOLE_COUNT),("o2",MINIMUM_MOLE_COUNT)], plasmafire));
+        registry.register(Reaction::native("miaster", "Miasma sterilization", -10.0, Some(FIRE_MINIMUM_TEMPERATURE_TO_EXIST+70.0), &[("miasma",MINIMUM_MOLE_COUNT)], miaster));
+        registry
+    }
+    /// Adds a reaction after every reaction with the same or higher priority.
+    pub fn register(&mut self, reaction: Reaction) {
+        let position = self.reactions.iter().position(|other| other.priority < reaction.priority).unwrap_or(self.reactions.len());
+        self.reactions.insert(position,reaction);
+    }
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Reaction> {
+        self.reactions.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.reactions.len()
+    }
+}
+
+static REACTIONS: RwLock<Option<Arc<ReactionRegistry>>> = RwLock::new(None);
+
+/// The reactions in use. The built-in set is made the first time it's asked for,
+/// since it needs the gas registry to find its gases.
+pub fn reactions() -> Arc<ReactionRegistry> {
+    if let Some(ref registry) = *REACTIONS.read().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+        return registry.clone();
+    }
+    REACTIONS.write().unwrap_or_else(|poisoned| poisoned.into_inner()).get_or_insert_with(|| Arc::new(ReactionRegistry::builtin())).clone()
+}
+
+/// Swaps in a new set of reactions, or goes back to the built-in ones with None.
+/// Anything reacting right now finishes with the set it started with.
+pub fn set_reactions(registry: Option<ReactionRegistry>) {
+    *REACTIONS.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = registry.map(Arc::new);
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_registry_order() {
+        let mut registry = ReactionRegistry::builtin();
+        let priorities: Vec<Float> = registry.iter().map(|reaction| reaction.priority).collect();
+        assert!(priorities.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(registry.iter().next().unwrap().id, "nobliumsupression");
+        let mut late_fusion = Reaction::native("late_fusion", "Late fusion", 2.0, None, &[], fusion);
+        late_fusion.exclusive = true;
+        registry.register(late_fusion);
+        let ids: Vec<&str> = registry.iter().map(|reaction| reaction.id.as_str()).collect();
+        assert_eq!(&ids[5..7], &["fusion", "late_fusion"]);
+    }
     #[test]
     fn test_all_reactions() {
-        let mut mix = AtmosMixture {
-            gases: [2500.0, 2500.0, 2500.0, 2500.0, 2500.0, 2500.0, 2500.0, 2500.0, 2500.0, 2500.0, 2500.0, 0.0, 2500.0],
-            temperature: 1000000.0,
-            volume: 2500.0};
-        let reaction_results = mix.react();
-        let mut ret_string : String = mix.to_params();
-        for result in reaction_results {
-            if(result.len() > 0) {
-                ret_string = format!("{}$",ret_string);
-                for part_of_result in result {
-                    ret_string = format!("{}{};",ret_string,part_of_result);
-                    println!("{}",part_of_result);
-                }
+        let gas = GasIndices::new();
+        let mut mix = AtmosMixture { temperature: float(1000000.0), ..Default::default() };
+        for index in 0..gases().len() {
+            // hyper-noblium would stop everything else
+            if index != gas.nob {
+                mix.gases[index] = float(2500.0);
             }
-            println!("{}",ret_string);
         }
+        let reaction_results = mix.react();
+        let ids: Vec<&str> = reaction_results.iter().filter_map(|result| result.first().map(String::as_str)).collect();
+        assert!(ids.contains(&"fusion"));
+        assert!(mix.temperature.is_finite());
     }
 }
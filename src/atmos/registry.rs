@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+
+use atmos::mix::AtmosMixture;
+
+use error::{Error, Result};
+
+/// A reference to a mixture owned by a `MixtureRegistry`.
+/// The generation is bumped every time a slot is freed, so a handle kept by a
+/// deleted DM datum stops resolving instead of pointing at whoever reused the slot.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+impl Handle {
+    pub fn new(index: u32, generation: u32) -> Handle {
+        Handle { index, generation }
+    }
+    pub fn index(self) -> usize {
+        self.index as usize
+    }
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+    pub fn to_bits(self) -> u64 {
+        (u64::from(self.generation) << 32) | u64::from(self.index)
+    }
+    pub fn from_bits(bits: u64) -> Handle {
+        Handle {
+            index: bits as u32,
+            generation: (bits >> 32) as u32,
+        }
+    }
+}
+
+// Handles go to BYOND as plain integers. They do not fit in a DM number, so DM
+// should keep the text it was given rather than running it through text2num.
+impl fmt::Display for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_bits())
+    }
+}
+
+impl FromStr for Handle {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Handle> {
+        s.trim()
+            .parse::<u64>()
+            .map(Handle::from_bits)
+            .map_err(|_| Error::InvalidHandle(s.to_string()))
+    }
+}
+
+struct Slot {
+    generation: u32,
+    mix: Option<AtmosMixture>,
+}
+
+#[derive(Default)]
+pub struct MixtureRegistry {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+impl MixtureRegistry {
+    pub fn create(&mut self, mix: AtmosMixture) -> Handle {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.mix = Some(mix);
+                Handle { index, generation: slot.generation }
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, mix: Some(mix) });
+                Handle { index: (self.slots.len() - 1) as u32, generation: 0 }
+            }
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.free.len() == self.slots.len()
+    }
+    pub fn destroy(&mut self, handle: Handle) -> Result<AtmosMixture> {
+        self.get(handle)?;
+        let slot = &mut self.slots[handle.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        Ok(slot.mix.take().unwrap())
+    }
+    pub fn get(&self, handle: Handle) -> Result<&AtmosMixture> {
+        match self.slots.get(handle.index as usize) {
+            Some(&Slot { generation, mix: Some(ref mix) }) if generation == handle.generation => Ok(mix),
+            _ => Err(Error::InvalidHandle(handle.to_string())),
+        }
+    }
+    pub fn get_mut(&mut self, handle: Handle) -> Result<&mut AtmosMixture> {
+        match self.slots.get_mut(handle.index as usize) {
+            Some(&mut Slot { generation, mix: Some(ref mut mix) }) if generation == handle.generation => Ok(mix),
+            _ => Err(Error::InvalidHandle(handle.to_string())),
+        }
+    }
+    /// Borrows two distinct mixtures at once, for sharing and merging.
+    pub fn get_pair_mut(&mut self, first: Handle, second: Handle) -> Result<(&mut AtmosMixture, &mut AtmosMixture)> {
+        self.get(first)?;
+        self.get(second)?;
+        if first.index == second.index {
+            return Err(Error::AliasedHandles);
+        }
+        let (low, high) = if first.index < second.index { (first, second) } else { (second, first) };
+        let (head, tail) = self.slots.split_at_mut(high.index as usize);
+        let low_mix = head[low.index as usize].mix.as_mut().unwrap();
+        let high_mix = tail[0].mix.as_mut().unwrap();
+        if first.index < second.index {
+            Ok((low_mix, high_mix))
+        } else {
+            Ok((high_mix, low_mix))
+        }
+    }
+    /// Borrows any number of distinct mixtures at once, for machines with several ports.
+    pub fn get_disjoint_mut<const N: usize>(&mut self, handles: [Handle; N]) -> Result<[&mut AtmosMixture; N]> {
+        for &handle in &handles {
+            self.get(handle)?;
+        }
+        let slots = self.slots.get_disjoint_mut(handles.map(|handle| handle.index as usize)).map_err(|_| Error::AliasedHandles)?;
+        Ok(slots.map(|slot| slot.mix.as_mut().unwrap()))
+    }
+}
+
+thread_local! {
+    static MIXTURES: RefCell<MixtureRegistry> = RefCell::new(MixtureRegistry::default());
+}
+
+/// Runs `f` against the registry backing the BYOND exports.
+pub fn with_mixtures<F, T>(f: F) -> T
+where
+    F: FnOnce(&mut MixtureRegistry) -> T,
+{
+    MIXTURES.with(|cell| f(&mut cell.borrow_mut()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atmos::constants::float;
+
+    #[test]
+    fn test_stale_handles() {
+        let mut registry = MixtureRegistry::default();
+        let first = registry.create(Default::default());
+        registry.destroy(first).unwrap();
+        assert!(registry.is_empty());
+        let second = registry.create(Default::default());
+        assert!(registry.get(first).is_err());
+        assert!(registry.get(second).is_ok());
+        assert!(registry.destroy(first).is_err());
+        assert_eq!(second.to_string().parse::<Handle>().unwrap(), second);
+    }
+    #[test]
+    fn test_pair_borrow() {
+        let mut registry = MixtureRegistry::default();
+        let first = registry.create(Default::default());
+        let second = registry.create(Default::default());
+        {
+            let (a, b) = registry.get_pair_mut(second, first).unwrap();
+            a.temperature = float(100.0);
+            b.temperature = float(200.0);
+        }
+        assert_eq!(registry.get(second).unwrap().temperature, 100.0);
+        assert_eq!(registry.get(first).unwrap().temperature, 200.0);
+        assert!(registry.get_pair_mut(first, first).is_err());
+        let third = registry.create(Default::default());
+        {
+            let [a, b, c] = registry.get_disjoint_mut([third, first, second]).unwrap();
+            a.temperature = float(300.0);
+            b.temperature = float(400.0);
+            c.temperature = float(500.0);
+        }
+        assert_eq!(registry.get(first).unwrap().temperature, 400.0);
+        assert!(registry.get_disjoint_mut([first, third, first]).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+use atmos::grid::{with_grid, Grid};
+use atmos::mix::AtmosMixture;
+
+use byond::panic_message;
+use error::{Error, Result};
+
+/// A map edit made through the exports. While a background tick is running it's
+/// applied to the grid DM reads from and replayed onto the processed one when
+/// that gets swapped in, so nothing DM does mid-tick is lost.
+#[derive(Clone)]
+pub enum GridEdit {
+    Mixture(usize, usize, AtmosMixture),
+    Blocked(usize, usize, bool),
+    Space(usize, usize, bool),
+    Adjacency(usize, usize, u8),
+    Equalize(bool),
+}
+
+impl GridEdit {
+    pub fn apply(&self, grid: &mut Grid) -> Result<()> {
+        match *self {
+            GridEdit::Mixture(x, y, ref mix) => grid.set_mixture(x, y, mix.clone()),
+            GridEdit::Blocked(x, y, blocked) => grid.set_blocked(x, y, blocked),
+            GridEdit::Space(x, y, space) => grid.set_space(x, y, space),
+            GridEdit::Adjacency(x, y, atmos_adjacency) => grid.set_adjacency(x, y, atmos_adjacency),
+            GridEdit::Equalize(equalize) => {
+                grid.equalize = equalize;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// What a background tick reports: the turfs that changed and the reaction results
+/// of every turf that reacted.
+pub struct TickEvents {
+    pub changed: Vec<usize>,
+    pub reactions: Vec<(usize, Vec<Vec<String>>)>,
+}
+
+/// One tick's worth of work, the same as `Grid::tick`.
+pub fn run_tick(grid: &mut Grid) -> TickEvents {
+    let changed = grid.tick();
+    let reactions = std::mem::take(&mut grid.reactions);
+    TickEvents { changed, reactions }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TickStatus {
+    Idle,
+    Running,
+    Done,
+}
+
+type TickOutcome = thread::Result<(Grid, TickEvents)>;
+
+/// The thread background ticks run on. It's started by the first tick and then
+/// waits for the next grid, so a tick doesn't pay for spawning a thread.
+struct TickWorker {
+    sender: Sender<(Grid, Sender<TickOutcome>)>,
+    // never joined: the worker lives as long as the library does
+    _handle: JoinHandle<()>,
+}
+
+impl TickWorker {
+    fn new() -> Result<TickWorker> {
+        let (sender, receiver) = channel::<(Grid, Sender<TickOutcome>)>();
+        let handle = thread::Builder::new()
+            .name("atmos tick".to_string())
+            .spawn(move || {
+                for (mut grid, reply) in receiver {
+                    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                        let events = run_tick(&mut grid);
+                        (grid, events)
+                    }));
+                    let _ = reply.send(outcome);
+                }
+            })
+            .map_err(|error| Error::Panic(error.to_string()))?;
+        Ok(TickWorker { sender, _handle: handle })
+    }
+}
+
+static WORKER: Mutex<Option<TickWorker>> = Mutex::new(None);
+
+/// Hands `grid` to the tick worker, starting it first if need be.
+fn send_to_worker(grid: Grid) -> Result<Receiver<TickOutcome>> {
+    let mut worker = WORKER.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if worker.is_none() {
+        *worker = Some(TickWorker::new()?);
+    }
+    let (reply, outcome) = channel();
+    worker.as_ref().unwrap().sender.send((grid, reply)).map_err(|_| Error::Panic("the atmos tick worker is gone".to_string()))?;
+    Ok(outcome)
+}
+
+struct TickJob {
+    outcome: Receiver<TickOutcome>,
+    // filled in once the worker has replied
+    finished: Option<TickOutcome>,
+    edits: Vec<GridEdit>,
+}
+
+impl TickJob {
+    fn is_finished(&mut self) -> bool {
+        if self.finished.is_none() {
+            match self.outcome.try_recv() {
+                Ok(outcome) => self.finished = Some(outcome),
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => self.finished = Some(Err(Box::new("the atmos tick worker is gone"))),
+            }
+        }
+        true
+    }
+}
+
+thread_local! {
+    static JOB: RefCell<Option<TickJob>> = const { RefCell::new(None) };
+}
+
+pub fn tick_running() -> bool {
+    JOB.with(|cell| cell.borrow().is_some())
+}
+
+/// Refuses with `TickInProgress` while a background tick is out, for exports that
+/// would otherwise touch state the tick is about to replace.
+pub fn ensure_idle() -> Result<()> {
+    if tick_running() {
+        Err(Error::TickInProgress)
+    } else {
+        Ok(())
+    }
+}
+
+/// Snapshots the grid and starts ticking the copy on the tick worker. The grid
+/// itself stays put, so reads keep seeing the last tick until `collect_tick`.
+pub fn start_tick() -> Result<()> {
+    ensure_idle()?;
+    let snapshot = with_grid(|grid| Ok(grid.clone()))?;
+    let outcome = send_to_worker(snapshot)?;
+    JOB.with(|cell| cell.replace(Some(TickJob { outcome, finished: None, edits: Vec::new() })));
+    Ok(())
+}
+
+pub fn tick_status() -> TickStatus {
+    JOB.with(|cell| match cell.borrow_mut().as_mut().map(TickJob::is_finished) {
+        Some(true) => TickStatus::Done,
+        Some(false) => TickStatus::Running,
+        None => TickStatus::Idle,
+    })
+}
+
+/// Swaps the processed grid in, replaying the edits made since the tick started.
+/// Fails with `TickInProgress` if the worker isn't done yet, leaving it running.
+/// If the tick panicked the job is dropped and the grid keeps its last state; the
+/// worker carries on for the next one.
+pub fn collect_tick() -> Result<TickEvents> {
+    let job = JOB.with(|cell| {
+        let mut job = cell.borrow_mut();
+        match job.as_mut().map(TickJob::is_finished) {
+            Some(false) => Err(Error::TickInProgress),
+            Some(true) => Ok(job.take().unwrap()),
+            None => Err(Error::NoTick),
+        }
+    })?;
+    let (mut processed, events) = job.finished.unwrap().map_err(|payload| Error::Panic(panic_message(payload)))?;
+    for edit in &job.edits {
+        // already checked against the same dimensions when DM made it
+        edit.apply(&mut processed)?;
+    }
+    with_grid(|grid| {
+        *grid = processed;
+        Ok(())
+    })?;
+    Ok(events)
+}
+
+/// Applies a map edit to the grid, and queues it for the running tick if there is one.
+pub fn edit_grid(edit: GridEdit) -> Result<()> {
+    with_grid(|grid| edit.apply(grid))?;
+    JOB.with(|cell| {
+        if let Some(ref mut job) = *cell.borrow_mut() {
+            job.edits.push(edit);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atmos::grid::create_grid;
+
+    fn wait_for_tick() {
+        while tick_status() == TickStatus::Running {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_background_tick() {
+        create_grid(3, 1);
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=293.15;o2=22;n2=82;").unwrap();
+        edit_grid(GridEdit::Mixture(0, 0, mix)).unwrap();
+        assert_eq!(tick_status(), TickStatus::Idle);
+        assert!(collect_tick().is_err());
+        start_tick().unwrap();
+        assert!(start_tick().is_err());
+        edit_grid(GridEdit::Blocked(2, 0, true)).unwrap();
+        // reads still see the state from before the tick
+        assert_eq!(with_grid(|grid| Ok(grid.turfs[1].mix.total_moles())).unwrap(), 0.0);
+        wait_for_tick();
+        assert_eq!(tick_status(), TickStatus::Done);
+        let events = collect_tick().unwrap();
+        assert_eq!(events.changed, vec![0, 1]);
+        assert_eq!(tick_status(), TickStatus::Idle);
+        with_grid(|grid| {
+            assert!(grid.turfs[1].mix.total_moles() > 0.0);
+            assert!(grid.turfs[2].blocked);
+            Ok(())
+        }).unwrap();
+    }
+    #[test]
+    fn test_worker_is_reused() {
+        let worker_id = || WORKER.lock().unwrap().as_ref().map(|worker| worker._handle.thread().id());
+        create_grid(2, 2);
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=1000;plasma=50;o2=100;").unwrap();
+        edit_grid(GridEdit::Mixture(0, 0, mix)).unwrap();
+        start_tick().unwrap();
+        let first = worker_id();
+        assert!(first.is_some());
+        for _ in 0..3 {
+            wait_for_tick();
+            let events = collect_tick().unwrap();
+            // a fire keeps reacting, and so keeps getting ticked
+            assert!(events.reactions.iter().any(|&(index, _)| index == 0));
+            start_tick().unwrap();
+            assert_eq!(worker_id(), first);
+        }
+        wait_for_tick();
+        collect_tick().unwrap();
+    }
+    #[test]
+    fn test_matches_tick() {
+        let mut grid = Grid::new(4, 4);
+        let mut mix : AtmosMixture = Default::default();
+        mix.parse_gas_string("TEMP=1000;plasma=50;o2=100;").unwrap();
+        grid.set_mixture(1, 1, mix).unwrap();
+        let mut expected = grid.clone();
+        let events = run_tick(&mut grid);
+        assert_eq!(events.changed, expected.tick());
+        assert!(events.reactions.iter().any(|&(index, _)| index == 5));
+    }
+}
@@ -0,0 +1,374 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use atmos::grid::{reverse_dir, space_mixture, CARDINALS, EAST, NORTH, SOUTH, WEST};
+use atmos::mix::AtmosMixture;
+
+use atmos::constants::*;
+
+use error::{Error, Result};
+
+// edges share as if each connecting cell were a turf with a full set of neighbours
+const ZAS_EDGE_ADJACENT_TURFS : i16 = 4;
+
+#[derive(Clone)]
+pub struct ZasCell {
+    pub atmos_adjacency: u8,
+    pub blocked: bool,
+    pub space: bool,
+    /// Lets air through but keeps zones apart, like an open door. Such a cell
+    /// becomes a zone of its own, connected to the rooms on either side by edges.
+    pub zone_blocked: bool,
+}
+
+impl Default for ZasCell {
+    fn default() -> ZasCell {
+        ZasCell {
+            atmos_adjacency: NORTH | SOUTH | EAST | WEST,
+            blocked: false,
+            space: false,
+            zone_blocked: false,
+        }
+    }
+}
+
+/// A connected room-sized area sharing one mixture. The mixture's volume is the
+/// sum of its cells, so a single cell's air is always the mixture divided evenly.
+#[derive(Clone)]
+pub struct Zone {
+    pub cells: Vec<usize>,
+    pub mix: AtmosMixture,
+}
+
+impl Zone {
+    pub fn cell_air(&self) -> AtmosMixture {
+        let mut air = self.mix.clone();
+        let share = 1.0 / float(self.cells.len() as Constant);
+        for amount in air.gases.iter_mut() {
+            *amount *= share;
+        }
+        air.volume = float(CELL_VOLUME);
+        air
+    }
+}
+
+#[derive(Clone)]
+pub struct ZoneMap {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<ZasCell>,
+    pub zones: BTreeMap<u32, Zone>,
+    cell_zone: Vec<Option<u32>>,
+    /// Number of connecting cell borders between each pair of zones, lower id first.
+    edges: BTreeMap<(u32, u32), usize>,
+    /// Number of cell borders each zone has with space.
+    space_edges: BTreeMap<u32, usize>,
+    next_id: u32,
+}
+
+impl ZoneMap {
+    pub fn new(width: usize, height: usize) -> ZoneMap {
+        let mut map = ZoneMap {
+            width,
+            height,
+            cells: vec![Default::default(); width * height],
+            zones: BTreeMap::new(),
+            cell_zone: vec![None; width * height],
+            edges: BTreeMap::new(),
+            space_edges: BTreeMap::new(),
+            next_id: 0,
+        };
+        let everything: Vec<usize> = (0..width * height).collect();
+        map.rebuild(&everything);
+        map
+    }
+    pub fn index(&self, x: usize, y: usize) -> Result<usize> {
+        if x < self.width && y < self.height {
+            Ok(y * self.width + x)
+        } else {
+            Err(Error::OutOfBounds(x, y))
+        }
+    }
+    pub fn coords(&self, index: usize) -> (usize, usize) {
+        (index % self.width, index / self.width)
+    }
+    fn step(&self, index: usize, dir: u8) -> Option<usize> {
+        let (x, y) = self.coords(index);
+        match dir {
+            NORTH if y + 1 < self.height => Some(index + self.width),
+            SOUTH if y > 0 => Some(index - self.width),
+            EAST if x + 1 < self.width => Some(index + 1),
+            WEST if x > 0 => Some(index - 1),
+            _ => None,
+        }
+    }
+    /// Cells air can flow to from `index`, the same way the grid decides it.
+    fn air_neighbours(&self, index: usize) -> Vec<usize> {
+        let cell = &self.cells[index];
+        if cell.blocked {
+            return Vec::new();
+        }
+        CARDINALS
+            .iter()
+            .filter(|&&dir| cell.atmos_adjacency & dir != 0)
+            .filter_map(|&dir| self.step(index, dir).map(|other| (dir, other)))
+            .filter(|&(dir, other)| {
+                let other_cell = &self.cells[other];
+                !other_cell.blocked && other_cell.atmos_adjacency & reverse_dir(dir) != 0
+            })
+            .map(|(_, other)| other)
+            .collect()
+    }
+    fn in_zone(&self, index: usize) -> bool {
+        let cell = &self.cells[index];
+        !cell.blocked && !cell.space
+    }
+    fn joins_zone(&self, index: usize, other: usize) -> bool {
+        self.in_zone(other) && !self.cells[index].zone_blocked && !self.cells[other].zone_blocked
+    }
+    pub fn zone_at(&self, x: usize, y: usize) -> Result<Option<u32>> {
+        Ok(self.cell_zone[self.index(x, y)?])
+    }
+    /// Dissolves every zone touching `seeds` and flood fills their cells into fresh
+    /// zones. Each new zone takes gas from each old zone in proportion to how many of
+    /// the old zone's cells it inherited; cells that stopped being part of any zone
+    /// take their share with them.
+    fn rebuild(&mut self, seeds: &[usize]) {
+        let old_ids: BTreeSet<u32> = seeds.iter().filter_map(|&index| self.cell_zone[index]).collect();
+        // old zone mixture and how many of its cells haven't been handed out yet
+        let mut old_zones: BTreeMap<u32, (AtmosMixture, usize)> = BTreeMap::new();
+        let mut old_owner: HashMap<usize, u32> = HashMap::new();
+        let mut pending: BTreeSet<usize> = BTreeSet::new();
+        for &id in &old_ids {
+            let zone = self.zones.remove(&id).unwrap();
+            for &index in &zone.cells {
+                self.cell_zone[index] = None;
+                old_owner.insert(index, id);
+                pending.insert(index);
+            }
+            old_zones.insert(id, (zone.mix, zone.cells.len()));
+            self.space_edges.remove(&id);
+        }
+        self.edges.retain(|&(a, b), _| !old_ids.contains(&a) && !old_ids.contains(&b));
+        for &index in seeds {
+            pending.insert(index);
+        }
+        let mut new_ids = BTreeSet::new();
+        while let Some(&start) = pending.iter().next() {
+            pending.remove(&start);
+            if !self.in_zone(start) || self.cell_zone[start].is_some() {
+                continue;
+            }
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1);
+            let mut cells = vec![start];
+            self.cell_zone[start] = Some(id);
+            let mut i = 0;
+            while i < cells.len() {
+                let current = cells[i];
+                for other in self.air_neighbours(current) {
+                    if self.cell_zone[other].is_none() && self.joins_zone(current, other) {
+                        self.cell_zone[other] = Some(id);
+                        pending.remove(&other);
+                        cells.push(other);
+                    }
+                }
+                i += 1;
+            }
+            let mut inherited: BTreeMap<u32, usize> = BTreeMap::new();
+            for index in &cells {
+                if let Some(&old_id) = old_owner.get(index) {
+                    *inherited.entry(old_id).or_insert(0) += 1;
+                }
+            }
+            let mut mix : AtmosMixture = Default::default();
+            for (old_id, count) in inherited {
+                let &mut (ref mut old_mix, ref mut remaining) = old_zones.get_mut(&old_id).unwrap();
+                mix.merge(old_mix.remove_ratio(float(count as Constant) / float(*remaining as Constant)));
+                *remaining -= count;
+            }
+            mix.volume = float(cells.len() as Constant) * CELL_VOLUME;
+            self.zones.insert(id, Zone { cells, mix });
+            new_ids.insert(id);
+        }
+        for &id in &new_ids {
+            for &index in &self.zones[&id].cells {
+                for other in self.air_neighbours(index) {
+                    if self.cells[other].space {
+                        *self.space_edges.entry(id).or_insert(0) += 1;
+                        continue;
+                    }
+                    match self.cell_zone[other] {
+                        Some(other_id) if other_id != id && (!new_ids.contains(&other_id) || id < other_id) => {
+                            let key = if id < other_id { (id, other_id) } else { (other_id, id) };
+                            *self.edges.entry(key).or_insert(0) += 1;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    fn rebuild_around(&mut self, index: usize) {
+        let mut seeds = vec![index];
+        for &dir in CARDINALS.iter() {
+            if let Some(other) = self.step(index, dir) {
+                seeds.push(other);
+            }
+        }
+        self.rebuild(&seeds);
+    }
+    pub fn set_blocked(&mut self, x: usize, y: usize, blocked: bool) -> Result<()> {
+        let index = self.index(x, y)?;
+        self.cells[index].blocked = blocked;
+        self.rebuild_around(index);
+        Ok(())
+    }
+    pub fn set_space(&mut self, x: usize, y: usize, space: bool) -> Result<()> {
+        let index = self.index(x, y)?;
+        self.cells[index].space = space;
+        self.rebuild_around(index);
+        Ok(())
+    }
+    pub fn set_zone_blocked(&mut self, x: usize, y: usize, zone_blocked: bool) -> Result<()> {
+        let index = self.index(x, y)?;
+        self.cells[index].zone_blocked = zone_blocked;
+        self.rebuild_around(index);
+        Ok(())
+    }
+    pub fn set_adjacency(&mut self, x: usize, y: usize, atmos_adjacency: u8) -> Result<()> {
+        let index = self.index(x, y)?;
+        self.cells[index].atmos_adjacency = atmos_adjacency;
+        self.rebuild_around(index);
+        Ok(())
+    }
+    /// The air on one cell, i.e. its even share of the zone's mixture.
+    pub fn cell_air(&self, x: usize, y: usize) -> Result<Option<AtmosMixture>> {
+        Ok(self.zone_at(x, y)?.map(|id| self.zones[&id].cell_air()))
+    }
+    /// Replaces one cell's share of its zone's air with `mix`.
+    pub fn set_cell_air(&mut self, x: usize, y: usize, mix: AtmosMixture) -> Result<()> {
+        let zone = match self.zone_at(x, y)? {
+            Some(id) => self.zones.get_mut(&id).unwrap(),
+            None => return Ok(()),
+        };
+        let share = 1.0 / float(zone.cells.len() as Constant);
+        zone.mix.remove_ratio(share);
+        let volume = zone.mix.volume;
+        zone.mix.merge(mix);
+        zone.mix.volume = volume;
+        Ok(())
+    }
+    /// Takes the slice of a zone's air sitting on `connections` cells.
+    fn edge_part(zone: &mut Zone, connections: usize) -> AtmosMixture {
+        let mut part = zone.mix.remove_ratio(float(connections as Constant) / float(zone.cells.len() as Constant));
+        part.volume = float(connections as Constant) * CELL_VOLUME;
+        part
+    }
+    fn return_part(zone: &mut Zone, part: AtmosMixture) {
+        let volume = zone.mix.volume;
+        zone.mix.merge(part);
+        zone.mix.volume = volume;
+    }
+    /// Shares air across every zone-to-zone and zone-to-space edge. Each edge shares
+    /// the slice of air sitting on its connecting cells, so big rooms don't drain
+    /// through a single door any faster than the door allows. Returns the zones that changed.
+    pub fn tick(&mut self) -> Vec<u32> {
+        let mut changed = BTreeSet::new();
+        let edges: Vec<((u32, u32), usize)> = self.edges.iter().map(|(&key, &connections)| (key, connections)).collect();
+        for ((a, b), connections) in edges {
+            let mut part_a = ZoneMap::edge_part(self.zones.get_mut(&a).unwrap(), connections);
+            let mut part_b = ZoneMap::edge_part(self.zones.get_mut(&b).unwrap(), connections);
+            let old_temperatures = (part_a.temperature, part_b.temperature);
+            let (_, last_share) = part_a.share(&mut part_b, ZAS_EDGE_ADJACENT_TURFS);
+            if last_share > 0.0 || old_temperatures != (part_a.temperature, part_b.temperature) {
+                changed.insert(a);
+                changed.insert(b);
+            }
+            ZoneMap::return_part(self.zones.get_mut(&a).unwrap(), part_a);
+            ZoneMap::return_part(self.zones.get_mut(&b).unwrap(), part_b);
+        }
+        let space_edges: Vec<(u32, usize)> = self.space_edges.iter().map(|(&id, &connections)| (id, connections)).collect();
+        for (id, connections) in space_edges {
+            let zone = self.zones.get_mut(&id).unwrap();
+            let mut part = ZoneMap::edge_part(zone, connections);
+            let mut space = space_mixture();
+            space.volume = part.volume;
+            let (_, last_share) = part.share(&mut space, ZAS_EDGE_ADJACENT_TURFS);
+            if last_share > 0.0 {
+                changed.insert(id);
+            }
+            ZoneMap::return_part(zone, part);
+        }
+        changed.into_iter().collect()
+    }
+}
+
+thread_local! {
+    static ZONES: RefCell<Option<ZoneMap>> = const { RefCell::new(None) };
+}
+
+pub fn create_zone_map(width: usize, height: usize) {
+    ZONES.with(|cell| cell.replace(Some(ZoneMap::new(width, height))));
+}
+
+pub fn has_zones() -> bool {
+    ZONES.with(|cell| cell.borrow().is_some())
+}
+
+/// Runs `f` against the zone map backing the BYOND exports, if one has been created.
+pub fn with_zones<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce(&mut ZoneMap) -> Result<T>,
+{
+    ZONES.with(|cell| match *cell.borrow_mut() {
+        Some(ref mut zones) => f(zones),
+        None => Err(Error::NoGrid),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zone_split_and_merge() {
+        let mut map = ZoneMap::new(5, 3);
+        assert_eq!(map.zones.len(), 1);
+        map.set_cell_air(0, 0, AtmosMixture::from_gas_string("TEMP=293.15;o2=150;").unwrap()).unwrap();
+        assert!((map.cell_air(4, 2).unwrap().unwrap().gases[1] - 10.0).abs() < 0.001);
+        for y in 0..3 {
+            map.set_blocked(2, y, true).unwrap();
+        }
+        assert_eq!(map.zones.len(), 2);
+        let left = map.zone_at(0, 0).unwrap().unwrap();
+        assert_eq!(map.zones[&left].cells.len(), 6);
+        assert!((map.zones[&left].mix.gases[1] - 60.0).abs() < 0.001);
+        assert_eq!(map.zones[&left].mix.volume, 6.0 * CELL_VOLUME);
+        map.set_blocked(2, 1, false).unwrap();
+        assert_eq!(map.zones.len(), 1);
+        assert!((map.cell_air(2, 1).unwrap().unwrap().gases[1] - 120.0 / 13.0).abs() < 0.001);
+    }
+    #[test]
+    fn test_door_edges_share() {
+        let mut map = ZoneMap::new(3, 1);
+        map.set_zone_blocked(1, 0, true).unwrap();
+        assert_eq!(map.zones.len(), 3);
+        assert_eq!(map.edges.len(), 2);
+        map.set_cell_air(0, 0, AtmosMixture::from_gas_string("TEMP=293.15;o2=100;").unwrap()).unwrap();
+        assert_eq!(map.tick().len(), 3);
+        assert!(map.cell_air(2, 0).unwrap().unwrap().gases[1] > 0.0);
+        let total : Float = map.zones.values().map(|zone| zone.mix.total_moles()).sum();
+        assert!((total - 100.0).abs() < 0.001);
+    }
+    #[test]
+    fn test_space_edge_drains() {
+        let mut map = ZoneMap::new(4, 1);
+        map.set_space(3, 0, true).unwrap();
+        map.set_cell_air(0, 0, AtmosMixture::from_gas_string("TEMP=293.15;o2=100;").unwrap()).unwrap();
+        let id = map.zone_at(0, 0).unwrap().unwrap();
+        let before = map.zones[&id].mix.total_moles();
+        assert_eq!(map.tick(), vec![id]);
+        assert!(map.zones[&id].mix.total_moles() < before);
+    }
+}
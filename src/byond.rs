@@ -1,33 +1,189 @@
+use std::any::Any;
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::ffi::{CStr, CString};
+use std::panic;
 use std::slice;
 
 use std::os::raw::{c_char, c_int};
 
+use atmos::registry::Handle;
+use error::{Error, Result};
+
 static EMPTY_STRING: &[c_char; 1] = &[0];
 thread_local! {
     static RETURN_STRING: RefCell<CString> = RefCell::new(CString::default());
 }
 
 pub fn parse_args<'a>(argc: c_int, argv: *const *const c_char) -> Vec<Cow<'a, str>> {
+    if argc <= 0 || argv.is_null() {
+        return Vec::new();
+    }
     unsafe {
         slice::from_raw_parts(argv, argc as usize)
-            .into_iter()
+            .iter()
             .map(|ptr| CStr::from_ptr(*ptr))
             .map(|cstr| cstr.to_string_lossy())
             .collect()
     }
 }
 
+pub fn into_bytes<S: Into<Vec<u8>>>(value: Option<S>) -> Option<Vec<u8>> {
+    value.map(Into::into)
+}
+
+/// Conversion from one BYOND argument for `byond_fn!`.
+pub trait FromArg<'a>: Sized {
+    fn from_arg(value: &'a str, name: &str) -> Result<Self>;
+    /// What a missing trailing argument becomes. Only optional arguments have one.
+    fn missing() -> Option<Self> {
+        None
+    }
+}
+
+impl<'a> FromArg<'a> for &'a str {
+    fn from_arg(value: &'a str, _name: &str) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+macro_rules! from_arg_number {
+    ($($t:ty),*) => {
+        $(
+            impl<'a> FromArg<'a> for $t {
+                fn from_arg(value: &'a str, name: &str) -> Result<Self> {
+                    value.trim().parse().map_err(|_| Error::InvalidArgument(name.to_string(), value.to_string()))
+                }
+            }
+        )*
+    };
+}
+
+from_arg_number!(f32, f64, i16, i32, u8, u32, usize);
+#[cfg(feature = "fixed")]
+from_arg_number!(::atmos::fixed::Fixed);
+
+// DM passes booleans as 1 and 0
+impl<'a> FromArg<'a> for bool {
+    fn from_arg(value: &'a str, name: &str) -> Result<Self> {
+        match value.trim() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            number => f32::from_arg(number, name).map(|number| number != 0.0),
+        }
+    }
+}
+
+impl<'a> FromArg<'a> for Handle {
+    fn from_arg(value: &'a str, _name: &str) -> Result<Self> {
+        value.parse()
+    }
+}
+
+// an empty string counts as missing too, since DM can't leave out an argument in the middle
+impl<'a, T: FromArg<'a>> FromArg<'a> for Option<T> {
+    fn from_arg(value: &'a str, name: &str) -> Result<Self> {
+        if value.is_empty() {
+            Ok(None)
+        } else {
+            T::from_arg(value, name).map(Some)
+        }
+    }
+    fn missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+/// What a typed export can return. Errors become the usual `ERR$` envelope.
+pub trait ByondReturn {
+    fn into_return(self) -> Vec<u8>;
+}
+
+impl ByondReturn for () {
+    fn into_return(self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+impl ByondReturn for String {
+    fn into_return(self) -> Vec<u8> {
+        self.into_bytes()
+    }
+}
+
+impl ByondReturn for &str {
+    fn into_return(self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl ByondReturn for bool {
+    fn into_return(self) -> Vec<u8> {
+        (if self { "1" } else { "0" }).into_return()
+    }
+}
+
+macro_rules! byond_return_display {
+    ($($t:ty),*) => {
+        $(
+            impl ByondReturn for $t {
+                fn into_return(self) -> Vec<u8> {
+                    self.to_string().into_bytes()
+                }
+            }
+        )*
+    };
+}
+
+byond_return_display!(f32, f64, i32, u32, usize, Handle);
+#[cfg(feature = "fixed")]
+byond_return_display!(::atmos::fixed::Fixed);
+
+impl<T: ByondReturn> ByondReturn for Result<T> {
+    fn into_return(self) -> Vec<u8> {
+        match self {
+            Ok(value) => value.into_return(),
+            Err(error) => error.into(),
+        }
+    }
+}
+
+pub fn argument<'a, T: FromArg<'a>>(args: &'a [Cow<'a, str>], index: usize, name: &str, count: usize) -> Result<T> {
+    match args.get(index) {
+        Some(value) => T::from_arg(value, name),
+        None => T::missing().ok_or(Error::ArgumentCount(count, args.len())),
+    }
+}
+
+// keeps the `?`s in a typed export's body from returning out of byond_return's closure
+pub fn catch_errors<T, F: FnOnce() -> Result<T>>(f: F) -> Result<T> {
+    f()
+}
+
+pub fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Runs an export's body and hands the result to BYOND. Panics are caught here,
+/// since unwinding across `extern "C"` would take the whole server down with it.
 pub fn byond_return<F, S>(inner: F) -> *const c_char
 where
     F: FnOnce() -> Option<S>,
     S: Into<Vec<u8>>,
 {
-    match inner() {
-        Some(string) => RETURN_STRING.with(|cell| {
-            let cstring = CString::new(string).expect("null in returned string!");
+    let result = match panic::catch_unwind(panic::AssertUnwindSafe(|| into_bytes(inner()))) {
+        Ok(result) => result,
+        Err(payload) => Some(Error::Panic(panic_message(payload)).into()),
+    };
+    match result {
+        Some(bytes) => RETURN_STRING.with(|cell| {
+            let cstring = CString::new(bytes).unwrap_or_else(|_| CString::new(Vec::from(Error::Null)).unwrap());
             cell.replace(cstring);
             cell.borrow().as_ptr() as *const c_char
         }),
@@ -37,43 +193,76 @@ where
 
 #[macro_export]
 macro_rules! byond_fn {
-    ($name:ident() $body:block) => {
+    // `byond_fn! { name(handle: Handle, moles: f32, note: Option<&str>) { ... } }`.
+    // Each argument is converted before the body runs, which returns a Result of
+    // anything ByondReturn covers. Option arguments can be left off the end.
+    ($name:ident($($arg:ident : $t:ty),*) $body:block) => {
         #[no_mangle]
         pub extern "C" fn $name(
             _argc: ::std::os::raw::c_int, _argv: *const *const ::std::os::raw::c_char
         ) -> *const ::std::os::raw::c_char {
-            $crate::byond::byond_return(|| $body)
+            $crate::byond::byond_return(|| {
+                let __args = $crate::byond::parse_args(_argc, _argv);
+                let __count = <[&str]>::len(&[$(stringify!($arg)),*]);
+                let __result = $crate::byond::catch_errors(|| {
+                    if __args.len() > __count {
+                        return Err($crate::error::Error::ArgumentCount(__count, __args.len()));
+                    }
+                    #[allow(unused_mut)]
+                    let mut __argn = 0;
+                    $(
+                        let $arg: $t = $crate::byond::argument(&__args, __argn, stringify!($arg), __count)?;
+                        __argn += 1;
+                    )*
+                    let _ = __argn;
+                    $body
+                });
+                Some($crate::byond::ByondReturn::into_return(__result))
+            })
         }
     };
+}
 
-    ($name:ident($($arg:ident),*) $body:block) => {
-        #[no_mangle]
-        pub extern "C" fn $name(
-            _argc: ::std::os::raw::c_int, _argv: *const *const ::std::os::raw::c_char
-        ) -> *const ::std::os::raw::c_char {
-            let __args = $crate::byond::parse_args(_argc, _argv);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            let mut __argn = 0;
-            $(
-                let $arg = &__args[__argn];
-                __argn += 1;
-            )*
+    byond_fn! { typed_args_export(moles: f32, flag: bool, note: Option<&str>) {
+        Ok(format!("{}/{}/{}", moles, flag, note.unwrap_or("none")))
+    } }
 
-            $crate::byond::byond_return(|| $body)
-        }
-    };
+    byond_fn! { no_args_export() {
+        Ok(42)
+    } }
 
-    ($name:ident()! $body:block) => {
-        byond_fn! { $name() {
-            $body
-            None as Option<String>
-        } }
-    };
+    fn call_export(export: extern "C" fn(c_int, *const *const c_char) -> *const c_char, args: &[&str]) -> String {
+        let args: Vec<CString> = args.iter().map(|arg| CString::new(*arg).unwrap()).collect();
+        let argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let returned = export(argv.len() as c_int, argv.as_ptr());
+        unsafe { CStr::from_ptr(returned) }.to_string_lossy().into_owned()
+    }
+    fn call(args: &[&str]) -> String {
+        call_export(typed_args_export, args)
+    }
 
-    ($name:ident($($arg:ident),*)! $body:block) => {
-        byond_fn! { $name($($arg),*) {
-            $body
-            None as Option<String>
-        } }
-    };
-}
\ No newline at end of file
+    #[test]
+    fn test_typed_args() {
+        assert_eq!(call(&["2.5", "1", "hello"]), "2.5/true/hello");
+        assert_eq!(call(&["2.5", "0"]), "2.5/false/none");
+        assert_eq!(call(&["2.5", "0", ""]), "2.5/false/none");
+        assert_eq!(call(&["2.5"]), "ERR$9$Expected 3 arguments, got 1.");
+        assert_eq!(call(&["2.5", "1", "a", "b"]), "ERR$9$Expected 3 arguments, got 4.");
+        assert_eq!(call(&["lots", "1"]), "ERR$15$Invalid value lots for argument moles.");
+        assert_eq!(call_export(no_args_export, &[]), "42");
+        assert_eq!(call_export(no_args_export, &["1"]), "ERR$9$Expected 0 arguments, got 1.");
+    }
+    #[test]
+    fn test_panics_become_errors() {
+        let returned = byond_return(|| -> Option<String> { panic!("oh no") });
+        let returned = unsafe { CStr::from_ptr(returned) }.to_string_lossy().into_owned();
+        assert_eq!(returned, "ERR$11$Panicked: oh no");
+        let returned = byond_return(|| Some("nul\0in the middle"));
+        let returned = unsafe { CStr::from_ptr(returned) }.to_string_lossy().into_owned();
+        assert!(returned.starts_with("ERR$10$"));
+    }
+}
@@ -1,44 +1,110 @@
-use std::io;
+use std::error;
+use std::fmt;
 use std::result;
-use std::str::Utf8Error;
-use std::num::ParseIntError;
 
 pub type Result<T> = result::Result<T, Error>;
 
-#[derive(Fail, Debug)]
+#[derive(Debug)]
 pub enum Error {
-    #[fail(display = "Illegal null character in string.")]
+    InvalidHandle(String),
+    AliasedHandles,
+    InvalidNumber(String),
+    UnknownGas(String),
+    OutOfBounds(usize, usize),
+    NoGrid,
+    InvalidBinary(String),
+    MalformedEntry(String),
+    ArgumentCount(usize, usize),
     Null,
-    #[fail(display = "Invalid UTF-8 character at position {}.", _1)]
-    Utf8(#[cause] Utf8Error, usize),
-    #[fail(display = "Invalid or empty filename specified.")]
-    InvalidFilename,
-    #[fail(display = "{}", _0)]
-    Io(#[cause] io::Error),
-    #[fail(display = "Invalid algorithm specified.")]
-    InvalidAlgorithm,
+    Panic(String),
+    InvalidGasDefinition(String),
+    GasesInUse,
+    InvalidReaction(String),
+    InvalidArgument(String, String),
+    TickInProgress,
+    NoTick,
+    UnknownAlarm(String),
+    InvalidPipe(String),
+    UnknownDevice(String),
 }
 
-impl From<io::Error> for Error {
-    fn from(error: io::Error) -> Error {
-        Error::Io(error)
+impl Error {
+    /// Stable numeric code DM can switch on. Don't renumber these.
+    pub fn code(&self) -> u32 {
+        match *self {
+            Error::InvalidHandle(_) => 1,
+            Error::AliasedHandles => 2,
+            Error::InvalidNumber(_) => 3,
+            Error::UnknownGas(_) => 4,
+            Error::OutOfBounds(_, _) => 5,
+            Error::NoGrid => 6,
+            Error::InvalidBinary(_) => 7,
+            Error::MalformedEntry(_) => 8,
+            Error::ArgumentCount(_, _) => 9,
+            Error::Null => 10,
+            Error::Panic(_) => 11,
+            Error::InvalidGasDefinition(_) => 12,
+            Error::GasesInUse => 13,
+            Error::InvalidReaction(_) => 14,
+            Error::InvalidArgument(_, _) => 15,
+            Error::TickInProgress => 16,
+            Error::NoTick => 17,
+            Error::UnknownAlarm(_) => 18,
+            Error::InvalidPipe(_) => 19,
+            Error::UnknownDevice(_) => 20,
+        }
     }
 }
 
-impl From<Utf8Error> for Error {
-    fn from(error: Utf8Error) -> Error {
-        Error::Utf8(error, error.valid_up_to())
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidHandle(ref handle) => write!(f, "Invalid or stale mixture handle {}.", handle),
+            Error::AliasedHandles => write!(f, "The same mixture handle was passed twice."),
+            Error::InvalidNumber(ref value) => write!(f, "Could not parse {} as a number.", value),
+            Error::UnknownGas(ref id) => write!(f, "Unknown gas id {}.", id),
+            Error::OutOfBounds(x, y) => write!(f, "Coordinates ({}, {}) are outside the grid.", x.wrapping_add(1), y.wrapping_add(1)),
+            Error::NoGrid => write!(f, "The map has not been created yet."),
+            Error::InvalidBinary(ref reason) => write!(f, "Invalid binary mixture: {}.", reason),
+            Error::MalformedEntry(ref entry) => write!(f, "Malformed entry {}.", entry),
+            Error::ArgumentCount(expected, got) => write!(f, "Expected {} arguments, got {}.", expected, got),
+            Error::Null => write!(f, "Returned string contained a null character."),
+            Error::Panic(ref message) => write!(f, "Panicked: {}", message),
+            Error::InvalidGasDefinition(ref reason) => write!(f, "Invalid gas definition: {}.", reason),
+            Error::GasesInUse => write!(f, "Gases can't be redefined while mixtures, a grid, zones, air alarms, pipes, vents, scrubbers or loaded reactions exist."),
+            Error::InvalidReaction(ref reason) => write!(f, "Invalid reaction definition: {}.", reason),
+            Error::InvalidArgument(ref name, ref value) => write!(f, "Invalid value {} for argument {}.", value, name),
+            Error::TickInProgress => write!(f, "A background tick is still running."),
+            Error::NoTick => write!(f, "No background tick has been started."),
+            Error::UnknownAlarm(ref id) => write!(f, "No air alarm limits set for {}.", id),
+            Error::InvalidPipe(ref handle) => write!(f, "Invalid or stale pipe handle {}.", handle),
+            Error::UnknownDevice(ref id) => write!(f, "No vent or scrubber registered as {}.", id),
+        }
     }
 }
 
+impl error::Error for Error {}
+
+// Errors go back to BYOND as `ERR$<code>$<message>`; successful results never start with ERR$.
 impl From<Error> for String {
     fn from(error: Error) -> String {
-        error.to_string()
+        format!("ERR${}${}", error.code(), error)
     }
 }
 
 impl From<Error> for Vec<u8> {
     fn from(error: Error) -> Vec<u8> {
-        error.to_string().into_bytes()
+        String::from(error).into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope() {
+        assert_eq!(String::from(Error::UnknownGas("palsma".to_string())), "ERR$4$Unknown gas id palsma.");
+        assert_eq!(String::from(Error::ArgumentCount(2, 1)), "ERR$9$Expected 2 arguments, got 1.");
     }
-}
\ No newline at end of file
+}
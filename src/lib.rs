@@ -4,29 +4,803 @@ mod atmos;
 #[macro_use]
 mod byond;
 
-byond_fn! { react(atmos_string) {
-    use atmos::mix::AtmosMixture;
-    let mut mix : AtmosMixture = Default::default();
-    mix.parse_gas_string(atmos_string);
-    let reaction_results = mix.react();
-    let mut ret_string : String = mix.to_params();
+mod error;
+
+use atmos::constants::Float;
+use atmos::registry::with_mixtures;
+use error::{Error, Result};
+
+use atmos::registry::Handle;
+use atmos::tick_job::{collect_tick, edit_grid, ensure_idle, start_tick, tick_status, GridEdit, TickStatus};
+
+fn gas_index(id: &str) -> Result<usize> {
+    atmos::gases::gases().index_of(id).ok_or_else(|| Error::UnknownGas(id.to_string()))
+}
+
+// BYOND coordinates start at 1, the grid's start at 0.
+fn grid_coords(x: usize, y: usize) -> Result<(usize, usize)> {
+    if x == 0 || y == 0 {
+        return Err(Error::OutOfBounds(x.wrapping_sub(1), y.wrapping_sub(1)));
+    }
+    Ok((x - 1, y - 1))
+}
+
+fn format_reaction_results(reaction_results: Vec<Vec<String>>) -> String {
+    let mut ret_string = String::new();
     for result in reaction_results {
-        if(result.len() > 0) {
-            ret_string = format!("{}$",ret_string);
+        if !result.is_empty() {
+            ret_string.push('$');
             for part_of_result in result {
                 ret_string = format!("{}{};",ret_string,part_of_result);
             }
         }
     }
-    Some(ret_string)
+    ret_string
+}
+
+byond_fn! { react(atmos_string: &str) {
+    use atmos::mix::AtmosMixture;
+    let mut mix = AtmosMixture::from_gas_string(atmos_string)?;
+    let reaction_results = mix.react();
+    Ok(format!("{}{}",mix.to_params(),format_reaction_results(reaction_results)))
+} }
+
+byond_fn! { share(atmos_string: &str, sharer_atmos_string: &str, adjacent_turfs: i16) {
+    use atmos::mix::AtmosMixture;
+    let mut mix1 = AtmosMixture::from_gas_string(atmos_string)?;
+    let mut mix2 = AtmosMixture::from_gas_string(sharer_atmos_string)?;
+    let (pressure_change,last_share) = mix1.share(&mut mix2,adjacent_turfs);
+    Ok(format!("{}${}${}${}",mix1.to_params(),mix2.to_params(),last_share,pressure_change))
+} }
+
+// Gas definitions. Mixtures are sized from these when they're made, so load them
+// at startup, before any mixture, grid, zones, air alarms, pipes, vents or
+// scrubbers exist. Both return the gas count.
+
+fn replace_gases(registry: atmos::gases::GasRegistry) -> Result<String> {
+    if !with_mixtures(|mixtures| mixtures.is_empty()) || atmos::grid::has_grid() || atmos::zas::has_zones()
+        || !atmos::air_alarm::with_alarms(|alarms| alarms.is_empty()) || !atmos::pipenet::with_pipenets(|pipenets| pipenets.is_empty())
+        || !atmos::machinery::with_devices(|devices| devices.is_empty())
+        || !atmos::reaction::reactions().builtin {
+        return Err(Error::GasesInUse);
+    }
+    let count = registry.len();
+    atmos::gases::set_gases(registry);
+    // the built-in reactions look their gases up when they're registered
+    atmos::reaction::set_reactions(None);
+    Ok(count.to_string())
+}
+
+byond_fn! { load_gases(definitions: &str) {
+    use atmos::gases::GasRegistry;
+    GasRegistry::parse(definitions).and_then(replace_gases)
+} }
+
+byond_fn! { load_gases_file(path: &str) {
+    use atmos::gases::GasRegistry;
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::InvalidGasDefinition(format!("couldn't read {}: {}", path, e)))
+        .and_then(|definitions| GasRegistry::parse(&definitions))
+        .and_then(replace_gases)
+} }
+
+// Returns the loaded definitions in the same one-per-line form they're loaded from.
+byond_fn! { gas_list() {
+    let gases = atmos::gases::gases();
+    Ok(gases.iter().map(|gas| {
+        let mut line = format!("id={};name={};specific_heat={};fusion_power={};rarity={};", gas.id, gas.name, gas.specific_heat, gas.fusion_power, gas.rarity);
+        for (key, value) in &gas.metadata {
+            line = format!("{}{}={};", line, key, value);
+        }
+        line
+    }).collect::<Vec<String>>().join("\n"))
+} }
+
+// Reaction definitions. Loading replaces whatever was loaded before, so calling
+// this again after editing the file is a hot reload. A file with errors leaves the
+// current reactions alone. Returns the reaction count.
+
+fn replace_reactions(definitions: &str) -> Result<String> {
+    let reactions = atmos::data_reaction::parse_reactions(definitions)?;
+    let count = reactions.len();
+    atmos::reaction::set_reactions(Some(reactions));
+    Ok(count.to_string())
+}
+
+byond_fn! { load_reactions(definitions: &str) {
+    replace_reactions(definitions)
+} }
+
+byond_fn! { load_reactions_file(path: &str) {
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::InvalidReaction(format!("couldn't read {}: {}", path, e)))
+        .and_then(|definitions| replace_reactions(&definitions))
+} }
+
+// Goes back to the reactions built into the library.
+byond_fn! { reset_reactions() {
+    atmos::reaction::set_reactions(None);
+    Ok(())
+} }
+
+// Lists the reactions in the order they run, one per line, as
+// `id=...;name=...;priority=...;min_temperature=...;min_pressure=...;min_moles=gas:moles,...;stops_reactions=0;exclusive=0;`
+// Requirements a reaction doesn't have are left empty.
+byond_fn! { reaction_list() {
+    let gases = atmos::gases::gases();
+    let optional = |value: Option<Float>| value.map(|value| value.to_string()).unwrap_or_default();
+    Ok(atmos::reaction::reactions().iter().map(|reaction| {
+        let min_moles = reaction.min_moles.iter().map(|&(index, amount)| format!("{}:{}", gases.get(index).id, amount)).collect::<Vec<String>>().join(",");
+        format!("id={};name={};priority={};min_temperature={};min_pressure={};min_moles={};stops_reactions={};exclusive={};",
+            reaction.id, reaction.name, reaction.priority, optional(reaction.min_temperature), optional(reaction.min_pressure),
+            min_moles, reaction.stops_reactions as u8, reaction.exclusive as u8)
+    }).collect::<Vec<String>>().join("\n"))
+} }
+
+// Handle-based exports. These keep the mixture on the Rust side between calls,
+// so nothing gets formatted and reparsed unless DM actually asks for a string.
+
+byond_fn! { mix_create(atmos_string: &str) {
+    use atmos::mix::AtmosMixture;
+    let mix = AtmosMixture::from_gas_string(atmos_string)?;
+    Ok(with_mixtures(|mixtures| mixtures.create(mix)))
+} }
+
+byond_fn! { mix_destroy(handle: Handle) {
+    with_mixtures(|mixtures| mixtures.destroy(handle).map(|_| ()))
+} }
+
+byond_fn! { mix_copy(handle: Handle) {
+    with_mixtures(|mixtures| {
+        let copy = mixtures.get(handle)?.clone();
+        Ok(mixtures.create(copy))
+    })
+} }
+
+byond_fn! { mix_to_params(handle: Handle) {
+    with_mixtures(|mixtures| Ok(mixtures.get_mut(handle)?.to_params()))
+} }
+
+byond_fn! { mix_set_gas(handle: Handle, gas_id: &str, moles: Float) {
+    let index = gas_index(gas_id)?;
+    with_mixtures(|mixtures| {
+        mixtures.get_mut(handle)?.gases[index] = moles;
+        Ok(())
+    })
+} }
+
+byond_fn! { mix_get_gas(handle: Handle, gas_id: &str) {
+    let index = gas_index(gas_id)?;
+    with_mixtures(|mixtures| Ok(mixtures.get(handle)?.gases[index]))
+} }
+
+byond_fn! { mix_set_temperature(handle: Handle, temperature: Float) {
+    with_mixtures(|mixtures| {
+        mixtures.get_mut(handle)?.temperature = temperature;
+        Ok(())
+    })
+} }
+
+byond_fn! { mix_get_temperature(handle: Handle) {
+    with_mixtures(|mixtures| Ok(mixtures.get(handle)?.temperature))
+} }
+
+byond_fn! { mix_set_volume(handle: Handle, volume: Float) {
+    with_mixtures(|mixtures| {
+        mixtures.get_mut(handle)?.volume = volume;
+        Ok(())
+    })
+} }
+
+byond_fn! { mix_get_volume(handle: Handle) {
+    with_mixtures(|mixtures| Ok(mixtures.get(handle)?.volume))
+} }
+
+byond_fn! { mix_react(handle: Handle) {
+    with_mixtures(|mixtures| Ok(format_reaction_results(mixtures.get_mut(handle)?.react())))
+} }
+
+byond_fn! { mix_share(handle: Handle, sharer_handle: Handle, adjacent_turfs: i16) {
+    with_mixtures(|mixtures| {
+        let (mix1, mix2) = mixtures.get_pair_mut(handle, sharer_handle)?;
+        let (pressure_change,last_share) = mix1.share(mix2,adjacent_turfs);
+        Ok(format!("{}${}",last_share,pressure_change))
+    })
+} }
+
+byond_fn! { mix_merge(handle: Handle, giver_handle: Handle) {
+    with_mixtures(|mixtures| {
+        let giver = mixtures.get(giver_handle)?.clone();
+        mixtures.get_mut(handle)?.merge(giver);
+        Ok(())
+    })
+} }
+
+byond_fn! { mix_remove(handle: Handle, amount: Float) {
+    with_mixtures(|mixtures| {
+        let removed = mixtures.get_mut(handle)?.remove(amount);
+        Ok(mixtures.create(removed))
+    })
+} }
+
+byond_fn! { mix_remove_ratio(handle: Handle, ratio: Float) {
+    with_mixtures(|mixtures| {
+        let removed = mixtures.get_mut(handle)?.remove_ratio(ratio);
+        Ok(mixtures.create(removed))
+    })
+} }
+
+
+// Binary encoding, carried over to DM as hex. Round trips exactly, unlike the
+// params strings, so it's what replays should record.
+
+byond_fn! { mix_to_binary(handle: Handle) {
+    use atmos::binary::{encode, to_hex};
+    with_mixtures(|mixtures| Ok(to_hex(&encode(mixtures.get(handle)?))))
+} }
+
+byond_fn! { mix_from_binary(hex: &str) {
+    use atmos::binary::{decode, from_hex};
+    let mix = decode(&from_hex(hex)?)?;
+    Ok(with_mixtures(|mixtures| mixtures.create(mix)))
+} }
+
+byond_fn! { gas_string_to_binary(atmos_string: &str) {
+    use atmos::binary::{encode, to_hex};
+    use atmos::mix::AtmosMixture;
+    AtmosMixture::from_gas_string(atmos_string).map(|mix| to_hex(&encode(&mix)))
+} }
+
+byond_fn! { binary_to_gas_string(hex: &str) {
+    use atmos::binary::{decode, from_hex};
+    from_hex(hex).and_then(|bytes| decode(&bytes)).map(|mut mix| mix.to_params())
+} }
+
+// Shares every listed turf with all of its neighbours in one call. Takes
+// `turf:neighbour,neighbour;...` handles and returns the changed ones, `;`-separated.
+byond_fn! { mix_share_batch(adjacency_string: &str) {
+    use atmos::batch::{parse_adjacency, share_all};
+    let turfs = parse_adjacency(adjacency_string)?;
+    with_mixtures(|mixtures| {
+        let changed = share_all(mixtures, &turfs)?;
+        Ok(changed.iter().map(|handle| handle.to_string()).collect::<Vec<String>>().join(";"))
+    })
+} }
+
+// Gas analyzer scans. Params are `pressure=...;temperature=...;...$gas=moles,percentage,partial_pressure;...$reaction,...`;
+// pass `json` as 1 to get the same report as a JSON object for UIs instead.
+
+byond_fn! { mix_scan(handle: Handle, json: Option<bool>) {
+    use atmos::analyzer::ScanReport;
+    with_mixtures(|mixtures| {
+        let report = ScanReport::scan(mixtures.get(handle)?);
+        Ok(if json.unwrap_or(false) { report.to_json() } else { report.to_params() })
+    })
+} }
+
+byond_fn! { gas_string_scan(atmos_string: &str, json: Option<bool>) {
+    use atmos::analyzer::ScanReport;
+    use atmos::mix::AtmosMixture;
+    let report = ScanReport::scan(&AtmosMixture::from_gas_string(atmos_string)?);
+    Ok(if json.unwrap_or(false) { report.to_json() } else { report.to_params() })
+} }
+
+// Air alarms. DM registers each alarm's limits once, under any id without `:`,
+// `;` or `$` in it (others are refused), and then checks mixtures against them. Results are
+// `level$metric=level;...`, levels being 0 (safe), 1 (warning) or 2 (danger).
+
+byond_fn! { air_alarm_set_tlv(alarm_id: &str, tlv_string: &str) {
+    use atmos::air_alarm::{with_alarms, AlarmThresholds};
+    let thresholds = AlarmThresholds::parse(tlv_string)?;
+    with_alarms(|alarms| alarms.set(alarm_id, thresholds))
+} }
+
+byond_fn! { air_alarm_remove(alarm_id: &str) {
+    atmos::air_alarm::with_alarms(|alarms| alarms.remove(alarm_id).map(|_| ()))
+} }
+
+byond_fn! { air_alarm_evaluate(alarm_id: &str, handle: Handle) {
+    atmos::air_alarm::with_alarms(|alarms| {
+        let thresholds = alarms.get(alarm_id)?;
+        with_mixtures(|mixtures| Ok(thresholds.evaluate(mixtures.get(handle)?).to_params()))
+    })
+} }
+
+byond_fn! { air_alarm_evaluate_gas_string(alarm_id: &str, atmos_string: &str) {
+    use atmos::mix::AtmosMixture;
+    let mix = AtmosMixture::from_gas_string(atmos_string)?;
+    atmos::air_alarm::with_alarms(|alarms| Ok(alarms.get(alarm_id)?.evaluate(&mix).to_params()))
+} }
+
+// Checks every alarm in `alarm:handle;alarm:handle;...` and returns one
+// `alarm$level$metric=level;...` line each.
+byond_fn! { air_alarm_evaluate_batch(alarm_string: &str) {
+    atmos::air_alarm::with_alarms(|alarms| with_mixtures(|mixtures| alarms.evaluate_all(mixtures, alarm_string)))
+} }
+
+// Pumps, gates and valves between two mixtures. `kind` is pressure_pump,
+// volume_pump, passive_gate or release_valve, and `setting` the target pressure
+// in kPa, or litres per tick for a volume pump. They return `moles$energy`, the
+// moles moved and the work done in joules.
+
+byond_fn! { mix_binary_machine(kind: &str, input: Handle, output: Handle, setting: Float, overclocked: Option<bool>) {
+    use atmos::machinery::BinaryMachine;
+    let machine = BinaryMachine::new(kind, setting, overclocked.unwrap_or(false))?;
+    with_mixtures(|mixtures| {
+        let (input, output) = mixtures.get_pair_mut(input, output)?;
+        Ok(machine.process(input, output).to_string())
+    })
+} }
+
+// Gas strings don't carry a volume, so the gas string versions of the machines
+// take the litres of the machine's node air, which is what DM runs them on.
+fn node_air(gas_string: &str, volume: Float) -> Result<atmos::mix::AtmosMixture> {
+    if volume <= 0.0 || !volume.is_finite() {
+        return Err(Error::InvalidArgument("volume".to_string(), volume.to_string()));
+    }
+    let mut mix = atmos::mix::AtmosMixture::from_gas_string(gas_string)?;
+    mix.volume = volume;
+    Ok(mix)
+}
+
+// Same as above on two gas strings of `volume` litres each. Returns
+// `input$output$moles$energy`.
+byond_fn! { binary_machine(kind: &str, input_string: &str, output_string: &str, volume: Float, setting: Float, overclocked: Option<bool>) {
+    use atmos::machinery::BinaryMachine;
+    let machine = BinaryMachine::new(kind, setting, overclocked.unwrap_or(false))?;
+    let mut input = node_air(input_string, volume)?;
+    let mut output = node_air(output_string, volume)?;
+    let transfer = machine.process(&mut input, &mut output);
+    Ok(format!("{}${}${}", input.to_params(), output.to_params(), transfer))
+} }
+
+// Filters take `transfer_rate` litres of the input a tick and send the listed
+// gases (`,`-separated ids) to the filter output, the rest to the main output.
+// Mixers fill the output to `target_pressure`, `first_concentration` (0 to 1) of
+// it from the first input. Both return `moles$energy` like the pumps.
+
+byond_fn! { mix_gas_filter(input: Handle, filter_output: Handle, main_output: Handle, transfer_rate: Float, gas_ids: &str) {
+    use atmos::machinery::{filter, parse_gas_ids};
+    let filtered = parse_gas_ids(gas_ids);
+    with_mixtures(|mixtures| {
+        let [input, filter_output, main_output] = mixtures.get_disjoint_mut([input, filter_output, main_output])?;
+        Ok(filter(input, filter_output, main_output, transfer_rate, &filtered).to_string())
+    })
+} }
+
+// Gas strings of `volume` litres each. Returns
+// `input$filter_output$main_output$moles$energy`.
+byond_fn! { gas_filter(input_string: &str, filter_output_string: &str, main_output_string: &str, volume: Float, transfer_rate: Float, gas_ids: &str) {
+    use atmos::machinery::{filter, parse_gas_ids};
+    let mut input = node_air(input_string, volume)?;
+    let mut filter_output = node_air(filter_output_string, volume)?;
+    let mut main_output = node_air(main_output_string, volume)?;
+    let transfer = filter(&mut input, &mut filter_output, &mut main_output, transfer_rate, &parse_gas_ids(gas_ids));
+    Ok(format!("{}${}${}${}", input.to_params(), filter_output.to_params(), main_output.to_params(), transfer))
+} }
+
+fn mixer_concentration(first_concentration: Float) -> Result<Float> {
+    if !(0.0..=1.0).contains(&first_concentration) {
+        return Err(Error::InvalidArgument("first_concentration".to_string(), first_concentration.to_string()));
+    }
+    Ok(first_concentration)
+}
+
+byond_fn! { mix_gas_mixer(first_input: Handle, second_input: Handle, output: Handle, target_pressure: Float, first_concentration: Float) {
+    use atmos::machinery::mixer;
+    let first_concentration = mixer_concentration(first_concentration)?;
+    with_mixtures(|mixtures| {
+        let [first_input, second_input, output] = mixtures.get_disjoint_mut([first_input, second_input, output])?;
+        Ok(mixer(first_input, second_input, output, target_pressure, first_concentration).to_string())
+    })
+} }
+
+// Returns `first_input$second_input$output$moles$energy`.
+byond_fn! { gas_mixer(first_input_string: &str, second_input_string: &str, output_string: &str, volume: Float, target_pressure: Float, first_concentration: Float) {
+    use atmos::machinery::mixer;
+    let first_concentration = mixer_concentration(first_concentration)?;
+    let mut first_input = node_air(first_input_string, volume)?;
+    let mut second_input = node_air(second_input_string, volume)?;
+    let mut output = node_air(output_string, volume)?;
+    let transfer = mixer(&mut first_input, &mut second_input, &mut output, target_pressure, first_concentration);
+    Ok(format!("{}${}${}${}", first_input.to_params(), second_input.to_params(), output.to_params(), transfer))
+} }
+
+// Freezers and heaters. `heat_capacity` is the machine's rating and
+// `max_heat_transfer` an optional cap in joules per tick. Returns `heat$energy`,
+// the joules put into the gas (negative when cooling) and the energy drawn.
+// Negative or non-finite settings are refused.
+
+byond_fn! { mix_thermomachine(handle: Handle, target_temperature: Float, heat_capacity: Float, max_heat_transfer: Option<Float>) {
+    let machine = atmos::machinery::Thermomachine::new(target_temperature, heat_capacity, max_heat_transfer)?;
+    with_mixtures(|mixtures| Ok(machine.process(mixtures.get_mut(handle)?).to_string()))
+} }
+
+// Returns `mixture$heat$energy`.
+byond_fn! { thermomachine(atmos_string: &str, target_temperature: Float, heat_capacity: Float, max_heat_transfer: Option<Float>) {
+    use atmos::mix::AtmosMixture;
+    let machine = atmos::machinery::Thermomachine::new(target_temperature, heat_capacity, max_heat_transfer)?;
+    let mut mix = AtmosMixture::from_gas_string(atmos_string)?;
+    let transfer = machine.process(&mut mix);
+    Ok(format!("{}${}", mix.to_params(), transfer))
+} }
+
+// Heat exchangers conduct between two mixtures without mixing them. Returns the
+// joules that went from the first to the second.
+
+fn checked_conduction_coefficient(conduction_coefficient: Float) -> Result<Float> {
+    if !(0.0..=1.0).contains(&conduction_coefficient) {
+        return Err(Error::InvalidArgument("conduction_coefficient".to_string(), conduction_coefficient.to_string()));
+    }
+    Ok(conduction_coefficient)
+}
+
+byond_fn! { mix_heat_exchanger(first: Handle, second: Handle, conduction_coefficient: Float) {
+    let conduction_coefficient = checked_conduction_coefficient(conduction_coefficient)?;
+    with_mixtures(|mixtures| {
+        let (first, second) = mixtures.get_pair_mut(first, second)?;
+        Ok(atmos::machinery::heat_exchanger(first, second, conduction_coefficient))
+    })
+} }
+
+// Returns `first$second$heat`.
+byond_fn! { heat_exchanger(first_string: &str, second_string: &str, conduction_coefficient: Float) {
+    use atmos::mix::AtmosMixture;
+    let conduction_coefficient = checked_conduction_coefficient(conduction_coefficient)?;
+    let mut first = AtmosMixture::from_gas_string(first_string)?;
+    let mut second = AtmosMixture::from_gas_string(second_string)?;
+    let heat = atmos::machinery::heat_exchanger(&mut first, &mut second, conduction_coefficient);
+    Ok(format!("{}${}${}", first.to_params(), second.to_params(), heat))
+} }
+
+// Pipe networks. Pipes are handles of their own, separate from mixture handles.
+// Connected pipes share one mixture; anything touching a pipe, like a machine's
+// node, gets or changes the whole network's air through that pipe's handle.
+
+// Takes the pipe's volume and optionally the pipes it connects to, `,`-separated.
+byond_fn! { pipenet_add_pipe(volume: Float, connections: Option<&str>) {
+    let mut pipes = Vec::new();
+    for pipe in connections.unwrap_or("").split(',') {
+        if !pipe.trim().is_empty() {
+            pipes.push(pipe.parse::<Handle>()?);
+        }
+    }
+    atmos::pipenet::with_pipenets(|pipenets| pipenets.add_pipe(volume, &pipes))
+} }
+
+// Returns a mixture handle holding the gas the pipe had, for DM to release.
+byond_fn! { pipenet_remove_pipe(pipe: Handle) {
+    let released = atmos::pipenet::with_pipenets(|pipenets| pipenets.remove_pipe(pipe))?;
+    Ok(with_mixtures(|mixtures| mixtures.create(released)))
+} }
+
+byond_fn! { pipenet_connect(first: Handle, second: Handle) {
+    atmos::pipenet::with_pipenets(|pipenets| pipenets.connect(first, second))
+} }
+
+byond_fn! { pipenet_disconnect(first: Handle, second: Handle) {
+    atmos::pipenet::with_pipenets(|pipenets| pipenets.disconnect(first, second))
+} }
+
+byond_fn! { pipenet_get_air(pipe: Handle) {
+    atmos::pipenet::with_pipenets(|pipenets| Ok(pipenets.air_mut(pipe)?.to_params()))
+} }
+
+// Replaces the network's gas and temperature. Its volume stays the pipes' total.
+byond_fn! { pipenet_set_air(pipe: Handle, atmos_string: &str) {
+    use atmos::mix::AtmosMixture;
+    // parsed first, so a bad string leaves the network's air alone
+    let parsed = AtmosMixture::from_gas_string(atmos_string)?;
+    atmos::pipenet::with_pipenets(|pipenets| {
+        let air = pipenets.air_mut(pipe)?;
+        air.gases = parsed.gases;
+        air.temperature = parsed.temperature;
+        Ok(())
+    })
+} }
+
+byond_fn! { pipenet_get_volume(pipe: Handle) {
+    atmos::pipenet::with_pipenets(|pipenets| Ok(pipenets.air(pipe)?.volume))
+} }
+
+byond_fn! { pipenet_react(pipe: Handle) {
+    atmos::pipenet::with_pipenets(|pipenets| Ok(format_reaction_results(pipenets.air_mut(pipe)?.react())))
+} }
+
+byond_fn! { pipenet_count() {
+    Ok(atmos::pipenet::with_pipenets(|pipenets| pipenets.network_count()))
 } }
 
-byond_fn! { share(atmos_string,sharer_atmos_string,adjacent_turfs_string) {
+// Heat-exchange pipes. Takes `pipe:surface_area:turf;...`, one entry per HE pipe
+// segment, where turf is a mixture handle or `space`. Returns one
+// `pipe$temperature$radiated$conducted` line per pipe network, energies in joules.
+byond_fn! { pipenet_he_exchange(segment_string: &str, conduction_coefficient: Float) {
+    use atmos::he_pipe::{exchange, parse_segments};
+    let segments = parse_segments(segment_string)?;
+    let results = atmos::pipenet::with_pipenets(|pipenets| with_mixtures(|mixtures| exchange(pipenets, mixtures, &segments, conduction_coefficient)))?;
+    Ok(results.iter().map(|&(pipe, ref network)| format!("{}${}${}${}", pipe, network.temperature, network.radiated, network.conducted)).collect::<Vec<String>>().join("\n"))
+} }
+
+// Vents and scrubbers. Settings are air alarm style `key=value;` strings, see
+// `VentPump::configure` and `Scrubber::configure`. A vent or scrubber's
+// moles moved come back as `moles$energy` like the other machines.
+
+byond_fn! { mix_vent_pump(pipe: Handle, turf: Handle, settings: &str) {
+    let mut vent = atmos::machinery::VentPump::default();
+    vent.configure(settings)?;
+    with_mixtures(|mixtures| {
+        let (pipe, turf) = mixtures.get_pair_mut(pipe, turf)?;
+        Ok(vent.process(pipe, turf).to_string())
+    })
+} }
+
+// The pipe string is `pipe_volume` litres and the turf string a whole turf.
+// Returns `pipe$turf$moles$energy`.
+byond_fn! { vent_pump(pipe_string: &str, pipe_volume: Float, turf_string: &str, settings: &str) {
     use atmos::mix::AtmosMixture;
-    let mut mix1 : AtmosMixture = Default::default();
-    let mut mix2 : AtmosMixture = Default::default();
-    mix1.parse_gas_string(atmos_string);
-    mix2.parse_gas_string(sharer_atmos_string);
-    let (pressure_change,last_share) = mix1.share(&mut mix2,adjacent_turfs_string.parse().unwrap());
-    Some(format!("{}${}${}${}",mix1.to_params(),mix2.to_params(),last_share,pressure_change))
-} }
\ No newline at end of file
+    let mut vent = atmos::machinery::VentPump::default();
+    vent.configure(settings)?;
+    let mut pipe = node_air(pipe_string, pipe_volume)?;
+    let mut turf = AtmosMixture::from_gas_string(turf_string)?;
+    let transfer = vent.process(&mut pipe, &mut turf);
+    Ok(format!("{}${}${}", pipe.to_params(), turf.to_params(), transfer))
+} }
+
+byond_fn! { mix_scrubber(pipe: Handle, turf: Handle, settings: &str) {
+    let mut scrubber = atmos::machinery::Scrubber::default();
+    scrubber.configure(settings)?;
+    with_mixtures(|mixtures| {
+        let (pipe, turf) = mixtures.get_pair_mut(pipe, turf)?;
+        Ok(scrubber.process(pipe, turf).to_string())
+    })
+} }
+
+// Takes the same volumes as `vent_pump`. Returns `pipe$turf$moles$energy`.
+byond_fn! { scrubber(pipe_string: &str, pipe_volume: Float, turf_string: &str, settings: &str) {
+    use atmos::mix::AtmosMixture;
+    let mut scrubber = atmos::machinery::Scrubber::default();
+    scrubber.configure(settings)?;
+    let mut pipe = node_air(pipe_string, pipe_volume)?;
+    let mut turf = AtmosMixture::from_gas_string(turf_string)?;
+    let transfer = scrubber.process(&mut pipe, &mut turf);
+    Ok(format!("{}${}${}", pipe.to_params(), turf.to_params(), transfer))
+} }
+
+// Registered devices keep their settings between calls, so an air alarm only has
+// to send what it changed. Ids can't contain `:`, `;` or `$`.
+
+byond_fn! { vent_configure(device_id: &str, settings: &str) {
+    atmos::machinery::with_devices(|devices| devices.configure_vent(device_id, settings))
+} }
+
+byond_fn! { scrubber_configure(device_id: &str, settings: &str) {
+    atmos::machinery::with_devices(|devices| devices.configure_scrubber(device_id, settings))
+} }
+
+byond_fn! { vent_scrubber_remove(device_id: &str) {
+    atmos::machinery::with_devices(|devices| devices.remove(device_id).map(|_| ()))
+} }
+
+// Runs every listed device once. Takes `device:pipe:turf,turf,...;...`, with the
+// pipe handle its node is on and mixture handles for its turf and, for widenet
+// scrubbers, the adjacent ones. Returns one `device$moles$energy` line each.
+byond_fn! { vent_scrubber_process_batch(device_string: &str) {
+    atmos::machinery::with_devices(|devices| atmos::pipenet::with_pipenets(|pipenets| {
+        with_mixtures(|mixtures| devices.process_all(pipenets, mixtures, device_string))
+    }))
+} }
+
+// Grid exports. DM pushes map edits in and pulls changed turfs out; the
+// mixtures themselves stay on the rust side between ticks.
+
+byond_fn! { grid_create(width: usize, height: usize) {
+    ensure_idle()?;
+    atmos::grid::create_grid(width, height);
+    Ok(())
+} }
+
+byond_fn! { grid_set_turf(x: usize, y: usize, atmos_string: &str) {
+    use atmos::mix::AtmosMixture;
+    let (x, y) = grid_coords(x,y)?;
+    let mix = AtmosMixture::from_gas_string(atmos_string)?;
+    edit_grid(GridEdit::Mixture(x, y, mix))
+} }
+
+byond_fn! { grid_get_turf(x: usize, y: usize) {
+    atmos::grid::with_grid(|grid| {
+        let (x, y) = grid_coords(x,y)?;
+        let index = grid.index(x, y)?;
+        Ok(grid.turfs[index].mix.to_params())
+    })
+} }
+
+byond_fn! { grid_set_blocked(x: usize, y: usize, blocked: bool) {
+    let (x, y) = grid_coords(x,y)?;
+    edit_grid(GridEdit::Blocked(x, y, blocked))
+} }
+
+byond_fn! { grid_set_space(x: usize, y: usize, space: bool) {
+    let (x, y) = grid_coords(x,y)?;
+    edit_grid(GridEdit::Space(x, y, space))
+} }
+
+byond_fn! { grid_set_adjacency(x: usize, y: usize, atmos_adjacency: u8) {
+    let (x, y) = grid_coords(x,y)?;
+    edit_grid(GridEdit::Adjacency(x, y, atmos_adjacency))
+} }
+
+// Returns the coordinates of every turf that changed, as `x,y;x,y;...`, then a
+// line per turf that reacted like atmos_tick_collect.
+byond_fn! { grid_tick() {
+    ensure_idle()?;
+    atmos::grid::with_grid(|grid| {
+        let changed = grid.tick();
+        let mut ret_string = changed.iter().map(|&index| {
+            let (x, y) = grid.coords(index);
+            format!("{},{}", x + 1, y + 1)
+        }).collect::<Vec<String>>().join(";");
+        for &(index, ref results) in &grid.reactions {
+            let (x, y) = grid.coords(index);
+            ret_string = format!("{}\n{},{}{}", ret_string, x + 1, y + 1, format_reaction_results(results.clone()));
+        }
+        Ok(ret_string)
+    })
+} }
+
+byond_fn! { grid_set_equalize(equalize: bool) {
+    edit_grid(GridEdit::Equalize(equalize))
+} }
+
+fn format_pressure_differences(grid: &atmos::grid::Grid, differences: &[atmos::monstermos::PressureDifference]) -> String {
+    differences.iter().map(|difference| {
+        let (x, y) = grid.coords(difference.turf);
+        format!("{},{},{},{}", x + 1, y + 1, difference.dir, difference.moles)
+    }).collect::<Vec<String>>().join(";")
+}
+
+// Equalizes the region around one turf right now. Returns `1` if it reached space
+// and was vented, `0` otherwise, then `$` and the pressure differences as `x,y,dir,moles;...`.
+byond_fn! { grid_equalize(x: usize, y: usize) {
+    ensure_idle()?;
+    atmos::grid::with_grid(|grid| {
+        let (x, y) = grid_coords(x,y)?;
+        let index = grid.index(x, y)?;
+        let result = atmos::monstermos::equalize_pressure(grid, index);
+        for &turf in &result.region {
+            grid.activate(turf);
+        }
+        Ok(format!("{}${}", result.decompressed as i32, format_pressure_differences(grid, &result.pressure_differences)))
+    })
+} }
+
+// Pressure differences from the last grid_tick with equalization on, as `x,y,dir,moles;...`.
+byond_fn! { grid_pressure_differences() {
+    atmos::grid::with_grid(|grid| {
+        Ok(format_pressure_differences(grid, &grid.pressure_differences))
+    })
+} }
+
+byond_fn! { grid_stats() {
+    atmos::grid::with_grid(|grid| {
+        Ok(format!("active_turfs={};excited_groups={};",grid.active_turfs().len(),grid.excited_groups.len()))
+    })
+} }
+
+// How many threads grid processing is split across. 0 or 1 runs it all on the
+// calling thread; any count gives the same results, just at different speeds.
+byond_fn! { atmos_set_threads(threads: usize) {
+    atmos::pool::set_threads(threads);
+    Ok(())
+} }
+
+byond_fn! { atmos_get_threads() {
+    Ok(atmos::pool::threads())
+} }
+
+// Background ticks. atmos_tick_start snapshots the grid and processes the copy on a
+// worker thread; until atmos_tick_collect swaps it in, reads see the previous tick and
+// map edits apply right away and are replayed onto the result. grid_create, grid_tick
+// and grid_equalize refuse to run in the meantime.
+
+byond_fn! { atmos_tick_start() {
+    start_tick()
+} }
+
+// `idle`, `running`, or `done` once atmos_tick_collect won't have to wait.
+byond_fn! { atmos_tick_status() {
+    Ok(match tick_status() {
+        TickStatus::Idle => "idle",
+        TickStatus::Running => "running",
+        TickStatus::Done => "done",
+    })
+} }
+
+// The changed turfs as `x,y;x,y;...`, then a line per turf that reacted: its
+// coordinates followed by the reaction results in the same form react() gives them.
+byond_fn! { atmos_tick_collect() {
+    let events = collect_tick()?;
+    atmos::grid::with_grid(|grid| {
+        let mut ret_string = events.changed.iter().map(|&index| {
+            let (x, y) = grid.coords(index);
+            format!("{},{}", x + 1, y + 1)
+        }).collect::<Vec<String>>().join(";");
+        for (index, results) in events.reactions {
+            let (x, y) = grid.coords(index);
+            ret_string = format!("{}\n{},{}{}", ret_string, x + 1, y + 1, format_reaction_results(results));
+        }
+        Ok(ret_string)
+    })
+} }
+
+// Zone (ZAS-style) exports. Same map-edit interface as the grid, but DM only ever
+// talks in turf coordinates; zones are rebuilt behind its back as walls and doors change.
+
+byond_fn! { zas_create(width: usize, height: usize) {
+    atmos::zas::create_zone_map(width, height);
+    Ok(())
+} }
+
+byond_fn! { zas_set_blocked(x: usize, y: usize, blocked: bool) {
+    atmos::zas::with_zones(|zones| {
+        let (x, y) = grid_coords(x,y)?;
+        zones.set_blocked(x, y, blocked)?;
+        Ok(())
+    })
+} }
+
+byond_fn! { zas_set_space(x: usize, y: usize, space: bool) {
+    atmos::zas::with_zones(|zones| {
+        let (x, y) = grid_coords(x,y)?;
+        zones.set_space(x, y, space)?;
+        Ok(())
+    })
+} }
+
+byond_fn! { zas_set_zone_blocked(x: usize, y: usize, zone_blocked: bool) {
+    atmos::zas::with_zones(|zones| {
+        let (x, y) = grid_coords(x,y)?;
+        zones.set_zone_blocked(x, y, zone_blocked)?;
+        Ok(())
+    })
+} }
+
+byond_fn! { zas_set_adjacency(x: usize, y: usize, atmos_adjacency: u8) {
+    atmos::zas::with_zones(|zones| {
+        let (x, y) = grid_coords(x,y)?;
+        zones.set_adjacency(x, y, atmos_adjacency)?;
+        Ok(())
+    })
+} }
+
+byond_fn! { zas_set_turf(x: usize, y: usize, atmos_string: &str) {
+    use atmos::mix::AtmosMixture;
+    atmos::zas::with_zones(|zones| {
+        let (x, y) = grid_coords(x,y)?;
+        let mix = AtmosMixture::from_gas_string(atmos_string)?;
+        zones.set_cell_air(x, y, mix)?;
+        Ok(())
+    })
+} }
+
+// Walls and space have no zone and return an empty string.
+byond_fn! { zas_get_turf(x: usize, y: usize) {
+    atmos::zas::with_zones(|zones| {
+        let (x, y) = grid_coords(x,y)?;
+        Ok(zones.cell_air(x, y)?.map(|mut air| air.to_params()).unwrap_or_default())
+    })
+} }
+
+// Returns every turf in a zone whose air changed, as `x,y;x,y;...`.
+byond_fn! { zas_tick() {
+    atmos::zas::with_zones(|zones| {
+        let changed = zones.tick();
+        let mut turfs = Vec::new();
+        for id in changed {
+            for &index in &zones.zones[&id].cells {
+                let (x, y) = zones.coords(index);
+                turfs.push(format!("{},{}", x + 1, y + 1));
+            }
+        }
+        Ok(turfs.join(";"))
+    })
+} }